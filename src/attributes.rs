@@ -0,0 +1,210 @@
+//! Path attributes from `.crabattributes` files, in gitattributes syntax:
+//!
+//! ```text
+//! *.png binary
+//! *.svg diff
+//! docs/*.pdf -diff
+//! ```
+//!
+//! Each line is a pattern followed by attributes: `name` sets one, `-name`
+//! unsets it, `name=value` gives it a value and `!name` returns it to
+//! unspecified. `binary` is shorthand for `-diff -merge -text`.
+//!
+//! `.crab_git/info/attributes` takes precedence over the per-directory files,
+//! and a file deeper in the tree over those above it. Within a file the last
+//! matching line wins.
+
+use crate::ignore::glob_match;
+use crate::{Repository, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const ATTRIBUTES_FILE: &str = ".crabattributes";
+
+/// The state of one attribute for a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeState {
+    Set,
+    Unset,
+    Value(String),
+    /// Explicitly returned to unspecified with `!name`.
+    Unspecified,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    anchored: bool,
+    glob: String,
+    /// Directory (relative to the work tree, `""` for the root) the pattern
+    /// is relative to.
+    base: String,
+    attributes: Vec<(String, AttributeState)>,
+}
+
+impl Rule {
+    fn parse(line: &str, base: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let pattern = fields.next().filter(|pattern| !pattern.starts_with('#'))?;
+
+        let mut attributes = Vec::new();
+        for field in fields {
+            let (name, state) = if let Some(name) = field.strip_prefix('-') {
+                (name, AttributeState::Unset)
+            } else if let Some(name) = field.strip_prefix('!') {
+                (name, AttributeState::Unspecified)
+            } else if let Some((name, value)) = field.split_once('=') {
+                (name, AttributeState::Value(value.to_string()))
+            } else {
+                (field, AttributeState::Set)
+            };
+            if name == "binary" && state == AttributeState::Set {
+                for implied in ["diff", "merge", "text"] {
+                    attributes.push((implied.to_string(), AttributeState::Unset));
+                }
+            }
+            attributes.push((name.to_string(), state));
+        }
+
+        Some(Rule {
+            anchored: pattern.contains('/'),
+            glob: pattern.trim_start_matches('/').to_string(),
+            base: base.to_string(),
+            attributes,
+        })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(self.base.as_str()).and_then(|p| p.strip_prefix('/')) {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+        if self.anchored {
+            glob_match(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            glob_match(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+
+    fn state(&self, name: &str) -> Option<&AttributeState> {
+        self.attributes.iter().rev().find(|(attribute, _)| attribute == name).map(|(_, state)| state)
+    }
+}
+
+/// The attributes of a work tree. Per-directory files are read lazily and
+/// cached.
+pub struct Attributes {
+    work_dir: PathBuf,
+    info_rules: Vec<Rule>,
+    dir_rules: RefCell<HashMap<String, Vec<Rule>>>,
+}
+
+impl Attributes {
+    pub fn load(repo: &Repository) -> Result<Self> {
+        Ok(Attributes {
+            work_dir: repo.work_dir.clone(),
+            info_rules: read_rules(&repo.git_dir.join("info").join("attributes"), "")?,
+            dir_rules: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// The state of attribute `name` for a work-tree relative path, `None`
+    /// when it is unspecified.
+    pub fn get(&self, path: &str, name: &str) -> Option<AttributeState> {
+        let path = path.trim_matches('/');
+        let mut dirs = vec![String::new()];
+        let mut prefix_end = 0;
+        while let Some(offset) = path[prefix_end..].find('/') {
+            dirs.push(path[..prefix_end + offset].to_string());
+            prefix_end += offset + 1;
+        }
+
+        let state = match last_state(&self.info_rules, path, name) {
+            Some(state) => Some(state.clone()),
+            None => dirs.iter().rev().find_map(|dir| {
+                self.load_dir(dir);
+                last_state(&self.dir_rules.borrow()[dir], path, name).cloned()
+            }),
+        };
+        state.filter(|state| *state != AttributeState::Unspecified)
+    }
+
+    fn load_dir(&self, dir: &str) {
+        if self.dir_rules.borrow().contains_key(dir) {
+            return;
+        }
+        let file = self.work_dir.join(dir).join(ATTRIBUTES_FILE);
+        let rules = read_rules(&file, dir).unwrap_or_default();
+        self.dir_rules.borrow_mut().insert(dir.to_string(), rules);
+    }
+}
+
+/// The state given by the last of `rules` that matches `path` and mentions
+/// `name`.
+fn last_state<'a>(rules: &'a [Rule], path: &str, name: &str) -> Option<&'a AttributeState> {
+    rules.iter().rev().filter(|rule| rule.matches(path)).find_map(|rule| rule.state(name))
+}
+
+fn read_rules(file: &Path, base: &str) -> Result<Vec<Rule>> {
+    if !file.is_file() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(file)?;
+    Ok(content.lines().filter_map(|line| Rule::parse(line, base)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn attribute_states() {
+        let rule = Rule::parse("*.png binary merge=ours !eol", "").unwrap();
+        assert_eq!(rule.state("diff"), Some(&AttributeState::Unset));
+        assert_eq!(rule.state("text"), Some(&AttributeState::Unset));
+        assert_eq!(rule.state("binary"), Some(&AttributeState::Set));
+        assert_eq!(rule.state("merge"), Some(&AttributeState::Value("ours".to_string())));
+        assert_eq!(rule.state("eol"), Some(&AttributeState::Unspecified));
+        assert_eq!(rule.state("other"), None);
+        assert!(Rule::parse("# comment diff", "").is_none());
+        assert!(Rule::parse("", "").is_none());
+    }
+
+    #[test]
+    fn patterns_match_like_ignore_patterns() {
+        let rule = Rule::parse("*.png diff", "assets").unwrap();
+        assert!(rule.matches("assets/logo.png"));
+        assert!(rule.matches("assets/icons/logo.png"));
+        assert!(!rule.matches("logo.png"));
+        assert!(!rule.matches("assetsx/logo.png"));
+
+        let anchored = Rule::parse("/docs/*.pdf -diff", "").unwrap();
+        assert!(anchored.matches("docs/guide.pdf"));
+        assert!(!anchored.matches("src/docs/guide.pdf"));
+    }
+
+    #[test]
+    fn deeper_files_and_info_attributes_take_precedence() {
+        let test = TestRepo::new();
+        test.write(ATTRIBUTES_FILE, "*.dat -diff\n*.txt -diff\n*.txt diff\n");
+        test.write(&format!("data/{}", ATTRIBUTES_FILE), "*.dat diff\nkeep.dat !diff\n");
+        let attributes = Attributes::load(&test.repo).unwrap();
+
+        assert_eq!(attributes.get("a.dat", "diff"), Some(AttributeState::Unset));
+        assert_eq!(attributes.get("a.txt", "diff"), Some(AttributeState::Set));
+        assert_eq!(attributes.get("data/a.dat", "diff"), Some(AttributeState::Set));
+        assert_eq!(attributes.get("data/keep.dat", "diff"), None);
+        assert_eq!(attributes.get("a.rs", "diff"), None);
+
+        test.write(".crab_git/info/attributes", "data/*.dat binary\n");
+        let attributes = Attributes::load(&test.repo).unwrap();
+        assert_eq!(attributes.get("data/a.dat", "diff"), Some(AttributeState::Unset));
+    }
+}
@@ -0,0 +1,123 @@
+//! Git's binary patch format, as `diff --binary` writes it:
+//!
+//! ```text
+//! GIT binary patch
+//! literal 7
+//! OcmYdHVn|L&O#=W3;sRCx
+//!
+//! literal 5
+//! McmYdHVn|K_00f)?ivR!s
+//!
+//! ```
+//!
+//! The first block gives the new content and the second the old one, so the
+//! patch applies in both directions. Each block is zlib-compressed and
+//! base85-encoded in lines of up to 52 bytes, the leading letter giving the
+//! line's length (`A`-`Z` for 1-26, `a`-`z` for 27-52). Contents are always
+//! written whole (`literal`); git's `delta` blocks are not produced.
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+const BASE85: &[u8; 85] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Bytes of compressed data per line.
+const LINE_BYTES: usize = 52;
+
+/// The `GIT binary patch` section turning `old` into `new`.
+pub fn binary_patch(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = b"GIT binary patch\n".to_vec();
+    literal(&mut out, new);
+    literal(&mut out, old);
+    out
+}
+
+fn literal(out: &mut Vec<u8>, content: &[u8]) {
+    out.extend(format!("literal {}\n", content.len()).into_bytes());
+    // Fastest compression, as git uses for patches.
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    // Writing to a Vec cannot fail.
+    let _ = encoder.write_all(content);
+    let compressed = encoder.finish().unwrap_or_default();
+
+    for chunk in compressed.chunks(LINE_BYTES) {
+        let len = chunk.len() as u8;
+        out.push(if len <= 26 { b'A' + len - 1 } else { b'a' + len - 27 });
+        out.extend(base85(chunk));
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+}
+
+/// Five characters per four bytes, the last group padded with zeros.
+fn base85(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len().div_ceil(4) * 5);
+    for group in data.chunks(4) {
+        let mut bytes = [0u8; 4];
+        bytes[..group.len()].copy_from_slice(group);
+        let mut value = u32::from_be_bytes(bytes);
+        let mut encoded = [0u8; 5];
+        for slot in encoded.iter_mut().rev() {
+            *slot = BASE85[(value % 85) as usize];
+            value /= 85;
+        }
+        out.extend(encoded);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    /// Decodes one `literal` block's data lines back to the content.
+    fn decode(lines: &[&str]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        for line in lines {
+            let (length, data) = line.split_at(1);
+            let length = match length.as_bytes()[0] {
+                b @ b'A'..=b'Z' => (b - b'A' + 1) as usize,
+                b => (b - b'a' + 27) as usize,
+            };
+            let mut bytes = Vec::new();
+            for group in data.as_bytes().chunks(5) {
+                let value = group.iter().fold(0u32, |value, c| {
+                    value * 85 + BASE85.iter().position(|b| b == c).unwrap() as u32
+                });
+                bytes.extend(value.to_be_bytes());
+            }
+            compressed.extend(&bytes[..length]);
+        }
+        let mut content = Vec::new();
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn base85_groups() {
+        assert_eq!(base85(&[0, 0, 0, 0]), b"00000");
+        assert_eq!(base85(&[0, 0, 0, 1]), b"00001");
+        assert_eq!(base85(&[0, 0, 0, 85]), b"00010");
+        assert_eq!(base85(&[1]).len(), 5);
+    }
+
+    #[test]
+    fn patches_hold_both_contents() {
+        let old = b"old\0content".to_vec();
+        let new: Vec<u8> = (0..=255u8).cycle().take(300).collect();
+        let patch = String::from_utf8(binary_patch(&old, &new)).unwrap();
+        let mut lines = patch.lines();
+        assert_eq!(lines.next(), Some("GIT binary patch"));
+
+        for expected in [&new, &old] {
+            assert_eq!(lines.next(), Some(format!("literal {}", expected.len()).as_str()));
+            let data: Vec<&str> = lines.by_ref().take_while(|line| !line.is_empty()).collect();
+            assert!(data.iter().all(|line| line.len() <= 1 + LINE_BYTES / 4 * 5));
+            assert_eq!(&decode(&data), expected);
+        }
+        assert_eq!(lines.next(), None);
+    }
+}
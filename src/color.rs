@@ -0,0 +1,273 @@
+//! ANSI colors for terminal output.
+//!
+//! `diff` and `log` color their output with `--color=auto|always|never`;
+//! without the option `color.diff`, or else `color.ui`, from the repository
+//! config decides, and `auto` colors only when writing to a terminal. Each
+//! part of a diff can be recolored with `color.diff.<slot>`, using git's
+//! color syntax:
+//!
+//! ```text
+//! [color "diff"]
+//!     old = red bold
+//!     new = #00ff00
+//!     frag = magenta ul
+//! ```
+
+use crate::config::Config;
+use crate::{CrabGitError, Repository, Result};
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+pub const RUST_COLOR: &str = "\x1b[38;2;217;155;121m";
+pub const RESET_COLOR: &str = "\x1b[0m";
+
+/// When to color output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    /// Only when writing to a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "auto" => Ok(ColorMode::Auto),
+            "always" | "true" | "yes" | "on" => Ok(ColorMode::Always),
+            "never" | "false" | "no" | "off" => Ok(ColorMode::Never),
+            _ => Err(format!("invalid color mode '{}' (expected auto, always or never)", value)),
+        }
+    }
+}
+
+impl ColorMode {
+    /// The mode set by `color.diff`, or else by `color.ui`.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        ["color.diff", "color.ui"]
+            .iter()
+            .find_map(|key| config.get(key).and_then(|value| value.parse().ok()))
+    }
+
+    /// Whether output to stdout is colored in this mode.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => {
+                std::io::stdout().is_terminal() && std::env::var("TERM").map_or(true, |term| term != "dumb")
+            }
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// The escape sequences of each part of a diff; an empty one leaves that
+/// part uncolored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffColors {
+    /// File headers: `diff --git`, `index`, `---`, `+++` and the like.
+    pub meta: String,
+    /// Hunk headers.
+    pub frag: String,
+    /// Removed lines and words.
+    pub old: String,
+    /// Added lines and words.
+    pub new: String,
+    /// Unchanged lines.
+    pub context: String,
+    /// The commit line of `log`.
+    pub commit: String,
+}
+
+impl Default for DiffColors {
+    fn default() -> Self {
+        DiffColors {
+            meta: "\x1b[1m".to_string(),
+            frag: "\x1b[36m".to_string(),
+            old: "\x1b[31m".to_string(),
+            new: "\x1b[32m".to_string(),
+            context: String::new(),
+            commit: "\x1b[33m".to_string(),
+        }
+    }
+}
+
+impl DiffColors {
+    /// No colors at all.
+    pub fn none() -> Self {
+        DiffColors {
+            meta: String::new(),
+            frag: String::new(),
+            old: String::new(),
+            new: String::new(),
+            context: String::new(),
+            commit: String::new(),
+        }
+    }
+
+    /// The default colors with any `color.diff.<slot>` from `config`
+    /// applied.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut colors = DiffColors::default();
+        let slots = [
+            ("meta", &mut colors.meta),
+            ("frag", &mut colors.frag),
+            ("old", &mut colors.old),
+            ("new", &mut colors.new),
+            ("context", &mut colors.context),
+            ("commit", &mut colors.commit),
+        ];
+        for (slot, color) in slots {
+            let key = format!("color.diff.{}", slot);
+            if let Some(spec) = config.get(&key) {
+                *color = parse_color(spec).ok_or_else(|| {
+                    CrabGitError::InvalidOperation(format!("invalid color '{}' for {}", spec, key))
+                })?;
+            }
+        }
+        Ok(colors)
+    }
+
+    /// The colors to use for `repo`'s diff output, or `None` when `mode` (or
+    /// the config when no mode is given) turns colors off.
+    pub fn for_repo(repo: &Repository, mode: Option<ColorMode>) -> Result<Option<Self>> {
+        let config = Config::for_repo(repo)?;
+        let mode = mode.or_else(|| ColorMode::from_config(&config)).unwrap_or_default();
+        if !mode.enabled() {
+            return Ok(None);
+        }
+        Ok(Some(DiffColors::from_config(&config)?))
+    }
+}
+
+/// `text` wrapped in `color` and a reset, or unchanged for an empty color.
+pub fn paint(color: &str, text: &str) -> String {
+    if color.is_empty() || text.is_empty() {
+        return text.to_string();
+    }
+    format!("{}{}{}", color, text, RESET_COLOR)
+}
+
+/// Converts a git color such as `red bold`, `brightblue black`, `214` or
+/// `#ff8000 ul` to its escape sequence: the first color is the foreground,
+/// the second the background, and attributes (`bold`, `dim`, `italic`, `ul`,
+/// `blink`, `reverse`, `strike`, each negatable with `no`) may go anywhere.
+/// `normal` and `default` leave a color unchanged; an empty spec is no color.
+pub fn parse_color(spec: &str) -> Option<String> {
+    let mut attributes = Vec::new();
+    let mut colors: Vec<Option<String>> = Vec::new();
+    for word in spec.split_whitespace() {
+        let word = word.to_lowercase();
+        if let Some(code) = attribute_code(&word) {
+            attributes.push(code.to_string());
+        } else if colors.len() < 2 {
+            let base = if colors.is_empty() { 30 } else { 40 };
+            colors.push(color_code(&word, base)?);
+        } else {
+            return None;
+        }
+    }
+
+    let codes: Vec<String> = attributes.into_iter().chain(colors.into_iter().flatten()).collect();
+    if codes.is_empty() {
+        return Some(String::new());
+    }
+    Some(format!("\x1b[{}m", codes.join(";")))
+}
+
+fn attribute_code(word: &str) -> Option<&'static str> {
+    let (negated, name) = match word.strip_prefix("no") {
+        Some(name) => (true, name.strip_prefix('-').unwrap_or(name)),
+        None => (false, word),
+    };
+    let (set, unset) = match name {
+        "bold" => ("1", "22"),
+        "dim" => ("2", "22"),
+        "italic" => ("3", "23"),
+        "ul" => ("4", "24"),
+        "blink" => ("5", "25"),
+        "reverse" => ("7", "27"),
+        "strike" => ("9", "29"),
+        _ => return None,
+    };
+    Some(if negated { unset } else { set })
+}
+
+/// The code of a color word, offset from `base` (30 for foreground, 40 for
+/// background); `Some(None)` for a word that leaves the color unchanged.
+fn color_code(word: &str, base: u8) -> Option<Option<String>> {
+    const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    if word == "normal" || word == "default" {
+        return Some(None);
+    }
+    if let Some(i) = NAMES.iter().position(|name| *name == word) {
+        return Some(Some((base + i as u8).to_string()));
+    }
+    if let Some(i) = word.strip_prefix("bright").and_then(|name| NAMES.iter().position(|n| *n == name)) {
+        return Some(Some((base + 60 + i as u8).to_string()));
+    }
+    if let Some(hex) = word.strip_prefix('#').filter(|hex| hex.len() == 6) {
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Some(format!("{};2;{};{};{}", base + 8, channel(0)?, channel(2)?, channel(4)?)));
+    }
+    let index: u8 = word.parse().ok()?;
+    Some(Some(format!("{};5;{}", base + 8, index)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn git_color_specs() {
+        assert_eq!(parse_color("red").as_deref(), Some("\x1b[31m"));
+        assert_eq!(parse_color("red bold").as_deref(), Some("\x1b[1;31m"));
+        assert_eq!(parse_color("ul brightblue black").as_deref(), Some("\x1b[4;94;40m"));
+        assert_eq!(parse_color("normal red").as_deref(), Some("\x1b[41m"));
+        assert_eq!(parse_color("214 nobold").as_deref(), Some("\x1b[22;38;5;214m"));
+        assert_eq!(parse_color("#ff8000").as_deref(), Some("\x1b[38;2;255;128;0m"));
+        assert_eq!(parse_color("").as_deref(), Some(""));
+        assert_eq!(parse_color("red green blue"), None);
+        assert_eq!(parse_color("#ff80"), None);
+        assert_eq!(parse_color("crimson"), None);
+    }
+
+    #[test]
+    fn painting() {
+        assert_eq!(paint("\x1b[31m", "old"), "\x1b[31mold\x1b[0m");
+        assert_eq!(paint("", "old"), "old");
+        assert_eq!(paint("\x1b[31m", ""), "");
+    }
+
+    #[test]
+    fn modes_and_slots_come_from_the_config() {
+        assert_eq!("ALWAYS".parse(), Ok(ColorMode::Always));
+        assert_eq!("off".parse(), Ok(ColorMode::Never));
+        assert!("sometimes".parse::<ColorMode>().is_err());
+
+        let test = TestRepo::new();
+        let mut config = Config::for_repo(&test.repo).unwrap();
+        assert_eq!(ColorMode::from_config(&config), None);
+        config.set("color.ui", "never");
+        assert_eq!(ColorMode::from_config(&config), Some(ColorMode::Never));
+        config.set("color.diff", "always");
+        assert_eq!(ColorMode::from_config(&config), Some(ColorMode::Always));
+
+        config.set("color.diff.old", "magenta");
+        config.set("color.diff.meta", "");
+        let colors = DiffColors::from_config(&config).unwrap();
+        assert_eq!(colors.old, "\x1b[35m");
+        assert_eq!(colors.meta, "");
+        assert_eq!(colors.new, DiffColors::default().new);
+        config.set("color.diff.new", "chartreuse");
+        assert!(DiffColors::from_config(&config).is_err());
+        config.save().unwrap();
+
+        assert_eq!(DiffColors::for_repo(&test.repo, Some(ColorMode::Never)).unwrap(), None);
+        assert!(DiffColors::for_repo(&test.repo, Some(ColorMode::Always)).is_err());
+    }
+}
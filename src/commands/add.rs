@@ -1,61 +1,34 @@
-use crate::{Index, IndexEntry, Repository, Result, object_store::ObjectStore, utils};
+use crate::{CrabGitError, Index, IndexEntry, Repository, Result, ignore::IgnoreRules, object_store::ObjectStore, utils};
 use std::fs;
-use std::path::Path;
-use walkdir::WalkDir;
 
 
-fn path_contains_excluded_folders(path: &Path) -> bool {
-    for component in path.components() {
-        if let Some(folder_name) = component.as_os_str().to_str() {
-            if folder_name.starts_with('.') || folder_name == "target" {
-                return true; 
-            }
-        }
-    }
-    false 
-}
-
-pub fn add(repo: &Repository, paths: Vec<String>) -> Result<()> {
+pub fn add(repo: &Repository, paths: Vec<String>, force: bool) -> Result<()> {
     let mut index = utils::load_index(repo)?;
     let object_store = ObjectStore::new(repo);
+    let ignore = IgnoreRules::load(repo)?;
 
     for path_str in paths {
-        if path_str == "." {
-            for entry in WalkDir::new(&repo.work_dir)
-                 .into_iter()
-                 .filter_map(|e| e.ok())
-                 .filter(|e| !path_contains_excluded_folders(e.path()))
-                 .filter(|e| e.file_type().is_file())
-                 {
-                    let path = entry.path();
-                    if path.starts_with(&repo.work_dir) {
-                        let relative_path = path
-                            .strip_prefix(&repo.work_dir)?
-                            .to_string_lossy()
-                            .replace("\\", "/");
-
-                        add_file_to_index(&mut index, &object_store, path, &relative_path)?;
-                    }
-                 }
-        } else {
-            let full_path = repo.work_dir.join(&path_str);
-            if full_path.is_file() {
-                add_file_to_index(&mut index, &object_store, &full_path, &path_str)?;
-            } else if full_path.is_dir() {
-                for entry in WalkDir::new(&full_path) 
-                     .into_iter()
-                     .filter_map(|e| e.ok())
-                     .filter(|e| e.file_type().is_file())
-                {
-                    let path = entry.path();
-                    let relative_path = path
-                         .strip_prefix(&full_path)?
-                         .to_string_lossy()
-                         .replace("\\", "/");
-
-                    add_file_to_index(&mut index, &object_store, &path, &relative_path)?;
-                }
+        let relative_path = utils::pathspec(repo, &path_str)?;
+        let full_path = repo.work_dir.join(&relative_path);
+
+        if full_path.is_file() {
+            if !force && ignore.is_ignored(&relative_path, false) {
+                return Err(CrabGitError::InvalidOperation(format!(
+                    "'{}' is ignored by one of your ignore files, use --force to add it anyway",
+                    relative_path
+                )));
             }
+            add_file_to_index(&mut index, &object_store, &full_path, &relative_path)?;
+        } else if full_path.is_dir() {
+            stage_deletions(repo, &mut index, &relative_path);
+            for file in ignore.unignored_files(&full_path) {
+                add_file_to_index(&mut index, &object_store, &repo.work_dir.join(&file), &file)?;
+            }
+        } else if stage_deletions(repo, &mut index, &relative_path) == 0 {
+            return Err(CrabGitError::InvalidOperation(format!(
+                "pathspec '{}' did not match any files",
+                path_str
+            )));
         }
     }
 
@@ -65,6 +38,23 @@ pub fn add(repo: &Repository, paths: Vec<String>) -> Result<()> {
     Ok(())
 }
 
+/// Removes index entries at or below `prefix` (the whole index for `""`)
+/// whose files no longer exist, returning how many were removed.
+fn stage_deletions(repo: &Repository, index: &mut Index, prefix: &str) -> usize {
+    let deleted: Vec<String> = index
+        .entries
+        .keys()
+        .filter(|path| utils::is_path_or_child(path, prefix))
+        .filter(|path| !repo.work_dir.join(path).is_file())
+        .cloned()
+        .collect();
+
+    for path in &deleted {
+        index.entries.remove(path);
+        index.unmerged.remove(path);
+    }
+    deleted.len()
+}
 
 pub fn add_file_to_index(
     index: &mut Index,
@@ -76,14 +66,81 @@ pub fn add_file_to_index(
     let content = fs::read(file_path)?;
     let hash = object_store.store_blob(&content)?;
 
+    index.unmerged.remove(relative_path);
     index.entries.insert(
         relative_path.to_string(),
-        IndexEntry { 
+        IndexEntry {
         hash,
         mode: "100644".to_string(),
-        path: relative_path.to_string(), 
+        path: relative_path.to_string(),
         }
     );
     Ok(())
-    
-}
\ No newline at end of file
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    fn staged(test: &TestRepo) -> Vec<String> {
+        let mut paths: Vec<String> = utils::load_index(&test.repo).unwrap().entries.into_keys().collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_current_directory() {
+        let test = TestRepo::new();
+        test.write("top.txt", "top\n");
+        test.write("src/lib.rs", "lib\n");
+        test.write("src/nested/mod.rs", "mod\n");
+        test.cd("src");
+
+        add(&test.repo, vec!["lib.rs".to_string()], false).unwrap();
+        assert_eq!(staged(&test), ["src/lib.rs"]);
+        add(&test.repo, vec!["../top.txt".to_string(), "./nested".to_string()], false).unwrap();
+        assert_eq!(staged(&test), ["src/lib.rs", "src/nested/mod.rs", "top.txt"]);
+
+        assert!(add(&test.repo, vec!["../../outside.txt".to_string()], false).is_err());
+    }
+
+    #[test]
+    fn ignored_files_need_force() {
+        let test = TestRepo::new();
+        test.write(".crabignore", "*.log\n");
+        test.write("app.log", "log\n");
+        test.write("app.rs", "app\n");
+
+        assert!(add(&test.repo, vec!["app.log".to_string()], false).is_err());
+        add(&test.repo, vec![".".to_string()], false).unwrap();
+        assert_eq!(staged(&test), [".crabignore", "app.rs"]);
+        add(&test.repo, vec!["app.log".to_string()], true).unwrap();
+        assert_eq!(staged(&test), [".crabignore", "app.log", "app.rs"]);
+    }
+
+    #[test]
+    fn missing_paths_stage_deletions() {
+        let test = TestRepo::new();
+        test.write("keep.txt", "keep\n");
+        test.write("gone.txt", "gone\n");
+        test.write("dir/a.txt", "a\n");
+        test.write("dir/b.txt", "b\n");
+        test.commit("initial");
+
+        fs::remove_file(test.path("gone.txt")).unwrap();
+        add(&test.repo, vec!["gone.txt".to_string()], false).unwrap();
+        assert_eq!(staged(&test), ["dir/a.txt", "dir/b.txt", "keep.txt"]);
+
+        fs::remove_file(test.path("dir/a.txt")).unwrap();
+        add(&test.repo, vec!["dir".to_string()], false).unwrap();
+        assert_eq!(staged(&test), ["dir/b.txt", "keep.txt"]);
+
+        fs::remove_dir_all(test.path("dir")).unwrap();
+        add(&test.repo, vec!["dir".to_string()], false).unwrap();
+        assert_eq!(staged(&test), ["keep.txt"]);
+
+        assert!(add(&test.repo, vec!["never-existed.txt".to_string()], false).is_err());
+    }
+}
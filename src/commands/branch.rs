@@ -1,4 +1,5 @@
-use crate::{Repository, Result, utils};
+use crate::refs::{Expected, RefTransaction};
+use crate::{CrabGitError, Repository, Result, utils};
 use std::fs;
 
 pub fn branch(repo: &Repository, name: Option<String>, delete: bool) -> Result<()> {
@@ -9,23 +10,26 @@ pub fn branch(repo: &Repository, name: Option<String>, delete: bool) -> Result<(
 
     if let Some(branch_name) = name {
         if delete {
-            let branch_path = refs_heads.join(&branch_name);
-            if branch_path.exists() {
-                fs::remove_file(branch_path)?;
+            if let Some(commit) = utils::get_branch_commit(repo, &branch_name)? {
+                let mut transaction = RefTransaction::new(repo);
+                transaction.delete(&format!("refs/heads/{}", branch_name), Expected::Value(commit));
+                transaction.commit()?;
                 println!("Deleted branch {}", branch_name);
             } else {
-                return Err(format!("Branch {} does not exist", branch_name).into());
+                return Err(CrabGitError::RefNotFound(branch_name));
             }
         }
 
         else {
-            let current_branch = utils::get_current_branch(repo)?;
-            let current_commit = utils::get_branch_commit(repo, &current_branch)?;
+            if utils::get_branch_commit(repo, &branch_name)?.is_some() {
+                return Err(CrabGitError::AlreadyExists(format!("Branch {}", branch_name)));
+            }
+            let current_commit = utils::get_head_commit(repo)?;
             if let Some(commit) = current_commit {
-                utils::update_branch(repo, &branch_name, &commit)?;
+                utils::update_branch(repo, &branch_name, &commit, Expected::Missing, "branch: Created from HEAD")?;
                 println!("Created branch {}", branch_name);
             } else {
-                return Err("No commits yet, cannot create branch".into());
+                return Err(CrabGitError::InvalidOperation("No commits yet, cannot create branch".to_string()));
             }
         }
     } 
@@ -0,0 +1,27 @@
+use crate::{Repository, Result, ignore::IgnoreRules, utils};
+
+pub fn check_ignore(repo: &Repository, paths: Vec<String>, verbose: bool) -> Result<()> {
+    let ignore = IgnoreRules::load(repo)?;
+
+    for path in paths {
+        let relative_path = utils::pathspec(repo, &path)?;
+        let is_dir = repo.work_dir.join(&relative_path).is_dir();
+
+        let Some(pattern) = ignore.matching_pattern(&relative_path, is_dir) else {
+            continue;
+        };
+
+        if verbose {
+            let source = pattern
+                .source
+                .strip_prefix(&repo.work_dir)
+                .unwrap_or(&pattern.source)
+                .display();
+            println!("{}:{}:{}\t{}", source, pattern.line, pattern.text, path);
+        } else if !pattern.negated {
+            println!("{}", path);
+        }
+    }
+
+    Ok(())
+}
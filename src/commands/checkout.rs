@@ -1,68 +1,236 @@
-use crate::{Repository, Result, object_store::ObjectStore, utils};
+use crate::{CrabGitError, IndexEntry, Repository, Result, object_store::ObjectStore, rev_parse, utils};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 
-pub fn checkout(repo: &Repository, branch_or_commit: String) -> Result<()> {
+pub fn checkout(repo: &Repository, branch_or_commit: String, force: bool) -> Result<()> {
     let refs_heads = repo.git_dir.join("refs").join("heads");
     let branch_path = refs_heads.join(&branch_or_commit);
+    let object_store = ObjectStore::new(repo);
+    let current = utils::get_head_commit(repo)?;
+    let from = match utils::get_current_branch(repo)?.as_str() {
+        "detached" => current.clone().unwrap_or_default(),
+        branch => branch.to_string(),
+    };
+    let reason = format!("checkout: moving from {} to {}", from, branch_or_commit);
 
     if branch_path.exists() {
-        utils::update_head(repo, &branch_or_commit)?;
-
         let commit_hash = utils::get_branch_commit(repo, &branch_or_commit)?;
         if let Some(commit) = commit_hash {
-            restore_working_directory(repo, &commit)?;
+            update_working_tree(repo, &object_store, current.as_deref(), &commit, force)?;
         }
+        utils::update_head(repo, &branch_or_commit, &reason)?;
 
         println!("Switched to branch '{}'", branch_or_commit);
     } else {
-        let object_store = ObjectStore::new(repo);
-        if object_store.object_exists(&branch_or_commit) {
-            // Update HEAD to point directly to commit (detached HEAD)
-            fs::write(repo.git_dir.join("HEAD"), &branch_or_commit)?;
-            restore_working_directory(repo, &branch_or_commit)?;
+        let commit = rev_parse::resolve_commit(repo, &object_store, &branch_or_commit)?;
+        update_working_tree(repo, &object_store, current.as_deref(), &commit, force)?;
+        utils::detach_head(repo, &commit, &reason)?;
 
-            println!("HEAD is now at {} (detached HEAD)", &branch_or_commit[..8]);
+        if utils::get_tag_target(repo, &branch_or_commit)?.is_some() {
+            println!("HEAD is now at {} (tag '{}', detached HEAD)", &commit[..8], branch_or_commit);
         } else {
-            return Err(format!("Branch or commit '{}' not found", branch_or_commit).into());
+            println!("HEAD is now at {} (detached HEAD)", &commit[..8]);
         }
     }
 
     Ok(())
 }
 
-fn restore_working_directory(repo: &Repository, commit_hash: &str) -> Result<()> {
-    let object_store = ObjectStore::new(repo);
-    let commit = object_store.load_commit(commit_hash)?;
-    let tree = object_store.load_tree(&commit.tree)?;
+/// Moves the working tree from the `current` commit's tree to the `target`
+/// commit's tree, touching only the paths that differ between the two.
+///
+/// Untracked files and local edits to paths that do not change are kept. When
+/// a changing path has local modifications (in the working tree or staged), or
+/// an untracked file is in the way, nothing is written and the conflicting
+/// paths are reported, unless `force` is set. The index entries of every
+/// updated path are set to the target's; `force` resets the whole index to it.
+pub fn update_working_tree(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    current: Option<&str>,
+    target: &str,
+    force: bool,
+) -> Result<()> {
+    let current_files = match current {
+        Some(hash) => object_store.read_tree(&object_store.load_commit(hash)?.tree)?,
+        None => BTreeMap::new(),
+    };
+    let target_files = object_store.read_tree(&object_store.load_commit(target)?.tree)?;
+    let mut index = utils::load_index(repo)?;
 
-    for entry in fs::read_dir(&repo.work_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+    let paths: BTreeSet<&String> = current_files.keys().chain(target_files.keys()).collect();
+    let mut updates = Vec::new();
+    let mut conflicts = Vec::new();
 
-        if path.file_name().unwrap() == ".crab_git" {
+    for path in paths {
+        let current_entry = current_files.get(path);
+        let target_entry = target_files.get(path);
+        let worktree_hash = worktree_hash(repo, object_store, path)?;
+        let target_hash = target_entry.map(|entry| &entry.hash);
+
+        if !force && utils::same_tree_entry(current_entry, target_entry) {
+            continue;
+        }
+        // Nothing can be lost when the work tree already has the target content.
+        if worktree_hash.as_ref() == target_hash {
+            updates.push((path, target_entry));
             continue;
         }
 
-        if path.is_file() {
-            fs::remove_file(&path)?;
-        } else if path.is_dir() {
-            fs::remove_dir_all(&path)?;
+        let modified = worktree_hash.as_ref() != current_entry.map(|entry| &entry.hash);
+        let staged = index
+            .entries
+            .get(path)
+            .is_some_and(|entry| Some(&entry.hash) != current_entry.map(|e| &e.hash));
+
+        if (modified || staged) && !force {
+            conflicts.push(path.clone());
+        } else {
+            updates.push((path, target_entry));
         }
     }
 
-    for (path, tree_entry) in &tree.entries {
-        if tree_entry.is_file {
-            let blob = object_store.load_blob(&tree_entry.hash)?;
-            let file_path = repo.work_dir.join(path);
+    if !conflicts.is_empty() {
+        return Err(CrabGitError::conflict(
+            "Your local changes to the following files would be overwritten by checkout \
+             (commit them, or use --force to discard them):",
+            conflicts,
+        ));
+    }
 
-            // Create parent directories if needed
-            if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
+    for (path, target_entry) in updates {
+        let file_path = repo.work_dir.join(path);
+        match target_entry {
+            Some(entry) => {
+                let blob = object_store.load_blob(&entry.hash)?;
+                if let Some(parent) = file_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&file_path, &blob.content)?;
+                index.entries.insert(
+                    path.clone(),
+                    IndexEntry { hash: entry.hash.clone(), mode: entry.mode.clone(), path: path.clone() },
+                );
+            }
+            None => {
+                if file_path.is_file() {
+                    fs::remove_file(&file_path)?;
+                }
+                utils::remove_empty_parents(&repo.work_dir, &file_path)?;
+                index.entries.remove(path);
             }
-
-            fs::write(file_path, &blob.content)?;
         }
     }
 
+    if force {
+        index = utils::tree_to_index(&target_files);
+    }
+    utils::save_index(repo, &index)?;
+
     Ok(())
-}
\ No newline at end of file
+}
+
+fn worktree_hash(repo: &Repository, object_store: &ObjectStore, path: &str) -> Result<Option<String>> {
+    let file_path = repo.work_dir.join(path);
+    if file_path.is_file() {
+        Ok(Some(object_store.hash_blob(&fs::read(file_path)?)))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::branch;
+    use crate::testing::TestRepo;
+
+    /// `main` has `shared.txt` and `dir/old.txt`; `topic` changes
+    /// `shared.txt`, deletes `dir/old.txt` and adds `new.txt`.
+    fn two_branches() -> TestRepo {
+        let test = TestRepo::new();
+        test.write("shared.txt", "main\n");
+        test.write("same.txt", "same\n");
+        test.write("dir/old.txt", "old\n");
+        test.commit("main");
+        branch(&test.repo, Some("topic".to_string()), false).unwrap();
+        checkout(&test.repo, "topic".to_string(), false).unwrap();
+
+        fs::remove_dir_all(test.path("dir")).unwrap();
+        test.write("shared.txt", "topic\n");
+        test.write("new.txt", "new\n");
+        test.commit("topic");
+        checkout(&test.repo, "main".to_string(), false).unwrap();
+        test
+    }
+
+    #[test]
+    fn switching_only_touches_changed_paths() {
+        let test = two_branches();
+        test.write("untracked.txt", "keep\n");
+        test.write("same.txt", "local edit\n");
+
+        checkout(&test.repo, "topic".to_string(), false).unwrap();
+        assert_eq!(test.read("shared.txt"), "topic\n");
+        assert_eq!(test.read("new.txt"), "new\n");
+        assert!(!test.path("dir").exists());
+        assert_eq!(test.read("untracked.txt"), "keep\n");
+        assert_eq!(test.read("same.txt"), "local edit\n");
+
+        checkout(&test.repo, "main".to_string(), false).unwrap();
+        assert_eq!(test.read("dir/old.txt"), "old\n");
+        assert!(!test.path("new.txt").exists());
+    }
+
+    #[test]
+    fn local_changes_block_the_switch() {
+        let test = two_branches();
+        test.write("shared.txt", "local edit\n");
+        test.write("new.txt", "untracked in the way\n");
+
+        let err = checkout(&test.repo, "topic".to_string(), false).unwrap_err();
+        match err {
+            CrabGitError::Conflict { paths, .. } => assert_eq!(paths, ["new.txt", "shared.txt"]),
+            other => panic!("unexpected error: {}", other),
+        }
+        // Nothing was written and HEAD did not move.
+        assert_eq!(test.read("shared.txt"), "local edit\n");
+        assert!(test.path("dir/old.txt").exists());
+        assert_eq!(utils::get_current_branch(&test.repo).unwrap(), "main");
+    }
+
+    #[test]
+    fn staged_changes_block_the_switch() {
+        let test = two_branches();
+        test.write("shared.txt", "staged\n");
+        test.add_all();
+        test.write("shared.txt", "main\n");
+
+        assert!(checkout(&test.repo, "topic".to_string(), false).is_err());
+    }
+
+    #[test]
+    fn force_discards_local_changes() {
+        let test = two_branches();
+        test.write("shared.txt", "local edit\n");
+
+        checkout(&test.repo, "topic".to_string(), true).unwrap();
+        assert_eq!(test.read("shared.txt"), "topic\n");
+        let index = utils::load_index(&test.repo).unwrap();
+        let mut paths: Vec<&str> = index.entries.keys().map(String::as_str).collect();
+        paths.sort();
+        assert_eq!(paths, ["new.txt", "same.txt", "shared.txt"]);
+    }
+
+    #[test]
+    fn tags_and_commits_detach_head() {
+        let test = two_branches();
+        let main = test.head();
+        utils::update_tag(&test.repo, "v1", &main).unwrap();
+        checkout(&test.repo, "topic".to_string(), false).unwrap();
+
+        checkout(&test.repo, "v1".to_string(), false).unwrap();
+        assert_eq!(fs::read_to_string(test.repo.git_dir.join("HEAD")).unwrap().trim_end(), main);
+        assert_eq!(test.read("shared.txt"), "main\n");
+    }
+}
@@ -1,65 +1,132 @@
-use crate::{Commit, Repository, Result, Tree, TreeEntry, object_store::ObjectStore, utils};
+use crate::refs::{Expected, RefTransaction};
+use crate::{Commit, CrabGitError, Repository, Result, object_store::ObjectStore, utils};
 use chrono::Utc;
-use std::collections::HashMap;
+use std::fs;
 
-pub fn commit(repo: &Repository, message: String, author: Option<String>) -> Result<()> {
+pub fn commit(repo: &Repository, message: Option<String>, author: Option<String>) -> Result<()> {
     let index = utils::load_index(repo)?;
-    if index.entries.is_empty() {
-        return Err("Nothing to commit, working tree clean".into());
+    if !index.unmerged.is_empty() {
+        return Err(CrabGitError::conflict(
+            "Cannot commit with unresolved conflicts, add the resolved files first:",
+            index.unmerged.iter().cloned().collect(),
+        ));
     }
 
     let object_store = ObjectStore::new(repo);
+    let tree_hash = object_store.write_tree(&index.entries)?;
 
-    let mut tree_entries = HashMap::new();
-    for (path, entry) in &index.entries {
-        tree_entries.insert(
-            path.clone(),
-            TreeEntry {
-                mode: entry.mode.clone(),
-                hash: entry.hash.clone(),
-                name: path.clone(),
-                is_file: true,
-            },
-        );
+    let head = utils::get_head_commit(repo)?;
+    let merge_head_path = repo.git_dir.join("MERGE_HEAD");
+    let merge_msg_path = repo.git_dir.join("MERGE_MSG");
+
+    // The index is the whole next snapshot, so an unchanged tree means nothing was staged.
+    let unchanged = match &head {
+        Some(head) => object_store.load_commit(head)?.tree == tree_hash,
+        None => index.entries.is_empty(),
+    };
+    if unchanged && !merge_head_path.exists() {
+        return Err(CrabGitError::InvalidOperation("Nothing to commit, working tree clean".to_string()));
     }
 
-    let tree_content = serde_json::to_vec(&tree_entries)?;
-    let tree_hash = ObjectStore::hash_content(&tree_content);
-    let tree = Tree {
-        hash: tree_hash.clone(),
-        entries: tree_entries,
+    let merge_head = if merge_head_path.exists() {
+        Some(fs::read_to_string(&merge_head_path)?.trim().to_string())
+    } else {
+        None
     };
+    let parents: Vec<String> = head.iter().cloned().chain(merge_head.clone()).collect();
 
-    object_store.store_tree(&tree)?;
+    // A conflicted merge leaves its message behind as the default.
+    let message = match message {
+        Some(message) => message,
+        None if merge_msg_path.exists() => fs::read_to_string(&merge_msg_path)?,
+        None => {
+            return Err(CrabGitError::InvalidOperation(
+                "Aborting commit due to empty commit message".to_string(),
+            ))
+        }
+    };
 
-    let current_branch = utils::get_current_branch(repo)?;
-    let parent = utils::get_branch_commit(repo, &current_branch)?;
+    let reason = match parents.len() {
+        0 => format!("commit (initial): {}", message),
+        1 => format!("commit: {}", message),
+        _ => format!("commit (merge): {}", message),
+    };
 
-    let author = author.unwrap_or_else(|| "Unknown email <unknown@example.com>".to_string());
-    let commit_content = format!(
-        "{}{}{}{}",
-        tree_hash,
-        parent.as_ref().unwrap_or(&String::new()),
-        author,
-        message
-    );
-    let commit_hash = ObjectStore::hash_content(commit_content.as_bytes());
+    let author = author.unwrap_or_else(|| utils::DEFAULT_IDENTITY.to_string());
+    let commit_hash = create_commit(&object_store, tree_hash, parents, author, message)?;
+
+    // Moving HEAD and concluding the merge happen in one transaction.
+    let mut transaction = RefTransaction::new(repo);
+    transaction.update_head(&commit_hash, Expected::from(head), &reason)?;
+    if let Some(merge_head) = merge_head {
+        transaction.delete("MERGE_HEAD", Expected::Value(merge_head));
+    }
+    transaction.commit()?;
+    if merge_msg_path.exists() {
+        fs::remove_file(merge_msg_path)?;
+    }
+
+    println!("Created commit {}", &commit_hash[..8]);
+    Ok(())
+}
 
+pub fn create_commit(
+    object_store: &ObjectStore,
+    tree_hash: String,
+    parents: Vec<String>,
+    author: String,
+    message: String,
+) -> Result<String> {
     let commit = Commit {
-        hash: commit_hash.clone(),
-        parent,
+        hash: String::new(),
+        parents,
         tree: tree_hash,
         author,
         message,
         timestamp: Utc::now(),
     };
 
-    object_store.store_commit(&commit)?;
+    object_store.store_commit(&commit)
+}
 
-    utils::update_branch(repo, &current_branch, &commit_hash)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
 
-    utils::save_index(repo, &crate::Index { entries: HashMap::new() })?;
+    fn head_files(test: &TestRepo) -> Vec<String> {
+        let object_store = ObjectStore::new(&test.repo);
+        let tree = object_store.load_commit(&test.head()).unwrap().tree;
+        object_store.read_tree(&tree).unwrap().into_keys().collect()
+    }
 
-    println!("Created commit {}", &commit_hash[..8]);
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn commits_keep_files_that_were_not_restaged() {
+        let test = TestRepo::new();
+        test.write("a.txt", "a\n");
+        test.write("b.txt", "b\n");
+        test.commit("initial");
+
+        test.write("a.txt", "changed\n");
+        crate::commands::add(&test.repo, vec!["a.txt".to_string()], false).unwrap();
+        commit(&test.repo, Some("change a".to_string()), None).unwrap();
+        assert_eq!(head_files(&test), ["a.txt", "b.txt"]);
+        assert_eq!(utils::load_index(&test.repo).unwrap().entries.len(), 2);
+
+        fs::remove_file(test.path("b.txt")).unwrap();
+        test.commit("remove b");
+        assert_eq!(head_files(&test), ["a.txt"]);
+    }
+
+    #[test]
+    fn unchanged_trees_are_not_committed() {
+        let test = TestRepo::new();
+        assert!(commit(&test.repo, Some("empty".to_string()), None).is_err());
+
+        test.write("a.txt", "a\n");
+        test.commit("initial");
+        let head = test.head();
+        assert!(commit(&test.repo, Some("again".to_string()), None).is_err());
+        assert_eq!(test.head(), head);
+    }
+}
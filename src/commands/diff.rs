@@ -1,102 +1,862 @@
-use crate::{Repository, Result, object_store::{self, ObjectStore}, utils};
+use crate::commands::merge;
+use crate::rev_parse::{self, RevRange};
+use crate::attributes::{AttributeState, Attributes};
+use crate::renames::{self, FileVersion, Rename};
+use crate::{binary_patch, color, CrabGitError, Index, Repository, Result, TreeEntry, diff_engine::{self, DiffOptions}, object_store::ObjectStore, utils};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::io::Write;
+use std::path::Path;
 
+/// One side of a file diff: object hash, mode and content.
+struct Side {
+    hash: String,
+    mode: String,
+    content: Vec<u8>,
+}
+
+impl Side {
+    fn load(object_store: &ObjectStore, source: Source, path: &str, entry: &TreeEntry) -> Result<Self> {
+        Ok(Side {
+            hash: entry.hash.clone(),
+            mode: entry.mode.clone(),
+            content: load_content(object_store, source, path, &entry.hash)?,
+        })
+    }
+}
+
+/// Lines inserted into and deleted from one file, as `--stat` shows them.
+#[derive(Debug, Clone)]
+pub struct FileStat {
+    pub path: String,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// Old and new size in bytes of a binary file, whose lines are not
+    /// counted.
+    pub binary: Option<(usize, usize)>,
+}
+
+/// A per-file overview shown instead of, or before, the patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffSummary {
+    /// A histogram of inserted and deleted lines per file, with totals.
+    Stat,
+    /// Inserted and deleted line counts per file, tab-separated.
+    Numstat,
+    /// Only the paths of changed files.
+    NameOnly,
+    /// The paths of changed files with a status letter.
+    NameStatus,
+}
+
+/// Width of a `--stat` line, as in git.
+const STAT_WIDTH: usize = 80;
+
+/// Where the new side of a diff reads file contents from.
+#[derive(Debug, Clone, Copy)]
+enum Source<'a> {
+    /// The object store: a commit's tree or the index.
+    Objects,
+    /// The files in this working tree.
+    WorkTree(&'a Path),
+}
+
+/// A file that differs between the two sides of a diff.
+struct FileChange {
+    old_path: String,
+    new_path: String,
+    old: Option<TreeEntry>,
+    new: Option<TreeEntry>,
+    /// Set when the new file is a rename or copy of `old_path`.
+    rename: Option<Rename>,
+}
+
+impl FileChange {
+    /// The path the change is listed under.
+    fn path(&self) -> &str {
+        if self.new.is_some() { &self.new_path } else { &self.old_path }
+    }
+
+    /// The path as `--stat` shows it, `old => new` for renames and copies.
+    fn display_path(&self) -> String {
+        match &self.rename {
+            Some(rename) => renames::display_rename(&rename.from, &rename.to),
+            None => self.path().to_string(),
+        }
+    }
 
-pub fn diff(repo: &Repository, files: Vec<String>) -> Result<()> {
+    /// The `--name-status` letter: added, deleted, type changed (such as a
+    /// file becoming a symlink) or modified. Renames and copies are shown
+    /// with their similarity instead.
+    fn status(&self) -> char {
+        match (&self.old, &self.new) {
+            (None, _) => 'A',
+            (_, None) => 'D',
+            (Some(old), Some(new)) if file_type(&old.mode) != file_type(&new.mode) => 'T',
+            _ => 'M',
+        }
+    }
+}
+
+/// The mode without its permission bits.
+fn file_type(mode: &str) -> &str {
+    &mode[..mode.len().saturating_sub(3)]
+}
+
+/// Shows changes between the working tree, the index and commits:
+///
+/// - no revision: the working tree against the index
+/// - `cached`: the index against HEAD, or against the given commit
+/// - one revision: the working tree against that commit
+/// - two revisions, or `A..B`: commit `A` against commit `B`
+/// - `A...B`: the merge base of `A` and `B` against `B`
+///
+/// The remaining arguments and `paths` limit the diff to those files or
+/// directories. Renames, and copies when enabled, are detected among the
+/// selected files. A `summary` replaces the patch unless `patch` asks for
+/// both.
+pub fn diff(
+    repo: &Repository,
+    args: Vec<String>,
+    paths: Vec<String>,
+    cached: bool,
+    options: DiffOptions,
+    summary: Option<DiffSummary>,
+    patch: bool,
+) -> Result<()> {
 
     let index = utils::load_index(repo)?;
     let object_store = ObjectStore::new(repo);
+    let attributes = Attributes::load(repo)?;
+
+    let (commits, mut files) = split_revisions(repo, &object_store, args)?;
+    files.extend(paths);
+
+    let index_files: BTreeMap<String, TreeEntry> = index
+        .entries
+        .iter()
+        .map(|(path, entry)| (path.clone(), tree_entry(path, &entry.hash, &entry.mode)))
+        .collect();
+    let commit_files = |commit: &String| merge::commit_files(&object_store, commit);
+    let work_tree = Source::WorkTree(&repo.work_dir);
 
-    if files.is_empty() {
-        for (path, _index_entry) in &index.entries {
-            let _ = show_file_diff(repo, &object_store, path)?;
+    let (mut old, mut new, source) = match (commits.as_slice(), cached) {
+        ([], false) => (index_files, work_tree_files(repo, &object_store, &index)?, work_tree),
+        ([], true) => {
+            let head = match utils::get_head_commit(repo)? {
+                Some(head) => commit_files(&head)?,
+                None => BTreeMap::new(),
+            };
+            (head, index_files, Source::Objects)
         }
-    }
+        ([commit], true) => (commit_files(commit)?, index_files, Source::Objects),
+        ([commit], false) => (commit_files(commit)?, work_tree_files(repo, &object_store, &index)?, work_tree),
+        ([old, new], false) => (commit_files(old)?, commit_files(new)?, Source::Objects),
+        _ => {
+            return Err(CrabGitError::InvalidOperation(
+                "--cached compares the index with a single commit".to_string(),
+            ));
+        }
+    };
 
-    else {
-        for file in files{
-            if index.entries.contains_key(&file) {
-                let _ = show_file_diff(repo, &object_store, &file)?;
-            } else {
+    if !files.is_empty() {
+        let candidates: BTreeSet<String> = old.keys().chain(new.keys()).cloned().collect();
+        let mut selected = BTreeSet::new();
+        for file in &files {
+            let prefix = utils::pathspec(repo, file)?;
+            let matched: Vec<&String> = candidates
+                .iter()
+                .filter(|path| utils::is_path_or_child(path, &prefix))
+                .collect();
+            if matched.is_empty() {
                 println!("File '{}' not tracked", file);
             }
+            selected.extend(matched);
+        }
+        old.retain(|path, _| selected.contains(path));
+        new.retain(|path, _| selected.contains(path));
+    }
+
+    let changes = changes(&object_store, &old, &new, source, &options)?;
+    if let Some(summary) = summary {
+        show_summary(&object_store, &attributes, source, &changes, summary, &options)?;
+        if !patch {
+            return Ok(());
+        }
+        if !changes.is_empty() {
+            println!();
+        }
+    }
+    for change in &changes {
+        show_change(&object_store, &attributes, source, change, &options)?;
+    }
+    Ok(())
+}
+
+/// The tracked files present in the working tree, hashed as they are now.
+/// Only tracked files take part on the working tree side.
+fn work_tree_files(repo: &Repository, object_store: &ObjectStore, index: &Index) -> Result<BTreeMap<String, TreeEntry>> {
+    let mut files = BTreeMap::new();
+    for (path, entry) in &index.entries {
+        let file_path = repo.work_dir.join(path);
+        if file_path.is_file() {
+            let hash = object_store.hash_blob(&fs::read(&file_path)?);
+            files.insert(path.clone(), tree_entry(path, &hash, &entry.mode));
+        }
+    }
+    Ok(files)
+}
+
+/// The content of `path` with object `hash`: from the working tree when the
+/// new side is read from there and the file is at that version, otherwise
+/// from the object store.
+fn load_content(object_store: &ObjectStore, source: Source, path: &str, hash: &str) -> Result<Vec<u8>> {
+    if let Source::WorkTree(work_dir) = source {
+        let content = fs::read(work_dir.join(path))?;
+        if object_store.hash_blob(&content) == hash {
+            return Ok(content);
+        }
+    }
+    Ok(object_store.load_blob(hash)?.content)
+}
+
+/// Every file that differs between `old` and `new`, ordered by path, with
+/// renames and copies paired up as `options.renames` asks.
+fn changes(
+    object_store: &ObjectStore,
+    old: &BTreeMap<String, TreeEntry>,
+    new: &BTreeMap<String, TreeEntry>,
+    source: Source,
+    options: &DiffOptions,
+) -> Result<Vec<FileChange>> {
+    let mut changes = Vec::new();
+    for path in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+        let (old, new) = (old.get(path), new.get(path));
+        if utils::same_tree_entry(old, new) {
+            continue;
+        }
+        changes.push(FileChange {
+            old_path: path.clone(),
+            new_path: path.clone(),
+            old: old.cloned(),
+            new: new.cloned(),
+            rename: None,
+        });
+    }
+    let Some(rename_options) = &options.renames else {
+        return Ok(changes);
+    };
+
+    let versions = |keep: fn(&FileChange) -> bool, new_side: bool| -> Vec<FileVersion> {
+        changes
+            .iter()
+            .filter(|change| keep(change))
+            .map(|change| {
+                let entry = if new_side { &change.new } else { &change.old };
+                FileVersion { path: change.path().to_string(), hash: entry.as_ref().unwrap().hash.clone() }
+            })
+            .collect()
+    };
+    let deleted = versions(|change| change.new.is_none(), false);
+    let added = versions(|change| change.old.is_none(), true);
+    let modified = versions(|change| change.old.is_some() && change.new.is_some(), false);
+    let found = renames::detect(&deleted, &added, &modified, rename_options, |file| {
+        // Only added files can come from the working tree.
+        let side = if new.get(&file.path).is_some_and(|entry| entry.hash == file.hash) { source } else { Source::Objects };
+        load_content(object_store, side, &file.path, &file.hash)
+    })?;
+
+    for rename in found {
+        if !rename.copy {
+            changes.retain(|change| change.new.is_some() || change.old_path != rename.from);
+        }
+        if let Some(change) = changes.iter_mut().find(|change| change.old.is_none() && change.new_path == rename.to) {
+            change.old_path = rename.from.clone();
+            change.old = old.get(&rename.from).cloned();
+            change.rename = Some(rename);
         }
     }
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(changes)
+}
 
+fn show_change(
+    object_store: &ObjectStore,
+    attributes: &Attributes,
+    source: Source,
+    change: &FileChange,
+    options: &DiffOptions,
+) -> Result<()> {
+    let (old, new) = load_sides(object_store, source, change)?;
+    let binary = is_binary(attributes, change.path(), [content(old.as_ref()), content(new.as_ref())]);
+    show_file_diff(change, old.as_ref(), new.as_ref(), binary, options)
+}
 
+/// The old and new side of a change, the old one always from the object
+/// store.
+fn load_sides(object_store: &ObjectStore, source: Source, change: &FileChange) -> Result<(Option<Side>, Option<Side>)> {
+    let old = change
+        .old
+        .as_ref()
+        .map(|entry| Side::load(object_store, Source::Objects, &change.old_path, entry))
+        .transpose()?;
+    let new = change
+        .new
+        .as_ref()
+        .map(|entry| Side::load(object_store, source, &change.new_path, entry))
+        .transpose()?;
+    Ok((old, new))
+}
+
+fn show_summary(
+    object_store: &ObjectStore,
+    attributes: &Attributes,
+    source: Source,
+    changes: &[FileChange],
+    summary: DiffSummary,
+    options: &DiffOptions,
+) -> Result<()> {
+    match summary {
+        DiffSummary::Stat => print_stat(&file_stats(object_store, attributes, source, changes, options)?),
+        DiffSummary::Numstat => print_numstat(&file_stats(object_store, attributes, source, changes, options)?),
+        DiffSummary::NameOnly => {
+            for change in changes {
+                println!("{}", change.path());
+            }
+        }
+        DiffSummary::NameStatus => {
+            for change in changes {
+                println!("{}", name_status(change));
+            }
+        }
+    }
     Ok(())
 }
 
-fn show_file_diff(repo: &Repository, object_store: &ObjectStore, path: &str) -> Result<()> {
+/// A `--name-status` line: the status letter, with the similarity for
+/// renames and copies, and the path or paths.
+fn name_status(change: &FileChange) -> String {
+    match &change.rename {
+        Some(rename) => {
+            let status = if rename.copy { 'C' } else { 'R' };
+            format!("{}{:03}\t{}\t{}", status, rename.similarity, rename.from, rename.to)
+        }
+        None => format!("{}\t{}", change.status(), change.path()),
+    }
+}
 
-    let file_path = repo.work_dir.join(path);
-    let index = utils::load_index(repo)?;
+/// Inserted and deleted line counts of every change; binary files get
+/// their sizes instead.
+fn file_stats(
+    object_store: &ObjectStore,
+    attributes: &Attributes,
+    source: Source,
+    changes: &[FileChange],
+    options: &DiffOptions,
+) -> Result<Vec<FileStat>> {
+    let mut stats = Vec::new();
+    for change in changes {
+        let (old, new) = load_sides(object_store, source, change)?;
+        let (old_content, new_content) = (content(old.as_ref()), content(new.as_ref()));
+        let path = change.display_path();
+        if is_binary(attributes, change.path(), [old_content, new_content]) {
+            let sizes = Some((old_content.len(), new_content.len()));
+            stats.push(FileStat { path, insertions: 0, deletions: 0, binary: sizes });
+        } else {
+            let (insertions, deletions) = diff_engine::line_counts(old_content, new_content, options.algorithm);
+            stats.push(FileStat { path, insertions, deletions, binary: None });
+        }
+    }
+    Ok(stats)
+}
+
+/// The content of a side, empty when the file does not exist there.
+fn content(side: Option<&Side>) -> &[u8] {
+    side.map_or(&[], |side| &side.content)
+}
+
+/// Whether a file is shown as binary. The `diff` attribute decides when it
+/// is set or unset; otherwise the content of either side does.
+fn is_binary(attributes: &Attributes, path: &str, contents: [&[u8]; 2]) -> bool {
+    match attributes.get(path, "diff") {
+        Some(AttributeState::Unset) => true,
+        Some(AttributeState::Set) => false,
+        _ => contents.iter().any(|content| diff_engine::is_binary(content)),
+    }
+}
+
+/// Splits off up to two leading revision arguments, or one range. An
+/// argument that names an existing file is treated as a path, and `A..B`
+/// only counts as a range when both sides resolve.
+fn split_revisions(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    mut args: Vec<String>,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut commits = Vec::new();
+    while commits.len() < 2 {
+        let Some(first) = args.first() else {
+            break;
+        };
+        if Path::new(first).exists() {
+            break;
+        }
+        if commits.is_empty() && rev_parse::is_range(repo, object_store, first) {
+            commits = match rev_parse::resolve_range(repo, object_store, first)? {
+                RevRange::Single(commit) => vec![commit],
+                RevRange::Range { exclude, include } => vec![exclude, include],
+                RevRange::Symmetric { left, right } => {
+                    let base = merge::find_merge_base(object_store, &left, &right)?
+                        .ok_or_else(|| CrabGitError::InvalidOperation(format!("{}: no merge base", first)))?;
+                    vec![base, right]
+                }
+            };
+            args.remove(0);
+            break;
+        }
+        match rev_parse::resolve_commit(repo, object_store, first) {
+            Ok(commit) => {
+                commits.push(commit);
+                args.remove(0);
+            }
+            Err(_) => break,
+        }
+    }
+    Ok((commits, args))
+}
+
+fn tree_entry(path: &str, hash: &str, mode: &str) -> TreeEntry {
+    TreeEntry {
+        mode: mode.to_string(),
+        hash: hash.to_string(),
+        name: path.rsplit('/').next().unwrap_or(path).to_string(),
+        is_file: true,
+    }
+}
 
-    let index_entry = match index.entries.get(path) {
-        Some(entry) => entry,
-        None => return Ok(()),
+fn show_file_diff(
+    change: &FileChange,
+    old: Option<&Side>,
+    new: Option<&Side>,
+    binary: bool,
+    options: &DiffOptions,
+) -> Result<()> {
+    std::io::stdout().write_all(&file_diff(change, old, new, binary, options)?)?;
+    Ok(())
+}
+
+/// The `diff --git` text for one file; empty when both sides are the same.
+fn file_diff(
+    change: &FileChange,
+    old: Option<&Side>,
+    new: Option<&Side>,
+    binary: bool,
+    options: &DiffOptions,
+) -> Result<Vec<u8>> {
+    // File header lines, colored as a whole, then the patch itself.
+    let mut header = Vec::new();
+    let mut body = Vec::new();
+    let (old_path, new_path) = match (old, new) {
+        (Some(_), Some(_)) => (&change.old_path, &change.new_path),
+        (Some(_), None) => (&change.old_path, &change.old_path),
+        (None, Some(_)) => (&change.new_path, &change.new_path),
+        (None, None) => return Ok(Vec::new()),
     };
+    let (old_hash, new_hash) = (old.map(|side| side.hash.as_str()), new.map(|side| side.hash.as_str()));
+    let (old_mode, new_mode) = (old.map(|side| side.mode.as_str()), new.map(|side| side.mode.as_str()));
+    if old_hash == new_hash && old_mode == new_mode && change.rename.is_none() {
+        return Ok(Vec::new());
+    }
 
-    if !file_path.exists() {
-        println!("diff --git a/{} b/{}", path, path);
-        println!("deleted file");
-        println!("--- a/{}", path);
-        println!("+++ /dev/null");
+    header.push(format!("diff --git a/{} b/{}", old_path, new_path));
+    match (old, new) {
+        (Some(old), Some(new)) => {
+            if old.mode != new.mode {
+                header.push(format!("old mode {}", old.mode));
+                header.push(format!("new mode {}", new.mode));
+            }
+            if let Some(rename) = &change.rename {
+                let kind = if rename.copy { "copy" } else { "rename" };
+                header.push(format!("similarity index {}%", rename.similarity));
+                header.push(format!("{} from {}", kind, rename.from));
+                header.push(format!("{} to {}", kind, rename.to));
+            }
+        }
+        (Some(old), None) => header.push(format!("deleted file mode {}", old.mode)),
+        (None, Some(new)) => header.push(format!("new file mode {}", new.mode)),
+        (None, None) => {}
+    }
 
+    // A change of mode alone has no patch.
+    if old_hash != new_hash {
+        // Binary patches name the objects in full so they can be checked
+        // when applied.
+        let full = binary && options.binary;
+        let hash_len = old_hash.or(new_hash).map_or(0, str::len);
+        let name = |hash: Option<&str>| -> String {
+            let hash = hash.map_or_else(|| "0".repeat(hash_len), str::to_string);
+            if full { hash } else { hash[..7].to_string() }
+        };
+        let mode = match (old, new) {
+            (Some(old), Some(new)) if old.mode == new.mode => format!(" {}", old.mode),
+            _ => String::new(),
+        };
+        header.push(format!("index {}..{}{}", name(old_hash), name(new_hash), mode));
 
-        let blob = object_store.load_blob(&index_entry.hash)?;
-        let staged_content = String::from_utf8_lossy(&blob.content);
-        for line in staged_content.lines() {
-            println!("-{}", line);
+        let (old_content, new_content) = (content(old), content(new));
+        let old_label = if old.is_some() { format!("a/{}", old_path) } else { "/dev/null".to_string() };
+        let new_label = if new.is_some() { format!("b/{}", new_path) } else { "/dev/null".to_string() };
+        if full {
+            body.extend(binary_patch::binary_patch(old_content, new_content));
+        } else if binary {
+            writeln!(body, "Binary files {} and {} differ", old_label, new_label)?;
+        } else {
+            header.push(format!("--- {}", old_label));
+            header.push(format!("+++ {}", new_label));
+            body.extend(diff_engine::unified_diff(old_content, new_content, options));
         }
-        return Ok(());
     }
 
-    let current_content = fs::read(&file_path)?;
-    let current_hash = ObjectStore::hash_content(&current_content);
+    let meta = options.colors.as_ref().map_or("", |colors| colors.meta.as_str());
+    let mut out = Vec::new();
+    for line in header {
+        writeln!(out, "{}", color::paint(meta, &line))?;
+    }
+    out.extend(body);
+    Ok(out)
+}
 
-    if current_hash == index_entry.hash {
-        return Ok(());
+/// Prints the unified diff between two flattened trees.
+pub fn show_tree_diff(
+    object_store: &ObjectStore,
+    attributes: &Attributes,
+    old: &BTreeMap<String, TreeEntry>,
+    new: &BTreeMap<String, TreeEntry>,
+    options: &DiffOptions,
+) -> Result<()> {
+    for change in changes(object_store, old, new, Source::Objects, options)? {
+        show_change(object_store, attributes, Source::Objects, &change, options)?;
     }
+    Ok(())
+}
 
-    let blob = object_store.load_blob(&index_entry.hash)?;
-    let staged_content = String::from_utf8_lossy(&blob.content);
-    let current_content_str = String::from_utf8_lossy(&current_content);
+/// Prints a summary of the changes between two flattened trees.
+pub fn show_tree_summary(
+    object_store: &ObjectStore,
+    attributes: &Attributes,
+    old: &BTreeMap<String, TreeEntry>,
+    new: &BTreeMap<String, TreeEntry>,
+    summary: DiffSummary,
+    options: &DiffOptions,
+) -> Result<()> {
+    let changes = changes(object_store, old, new, Source::Objects, options)?;
+    show_summary(object_store, attributes, Source::Objects, &changes, summary, options)
+}
 
-    println!("diff --git a/{} b/{}", path, path);
-    println!("--- a/{}", path);
-    println!("+++ b/{}", path);
+/// Prints a `--stat` summary: one ` path | count +++--` line per file,
+/// scaled to fit, and the totals.
+pub fn print_stat(stats: &[FileStat]) {
+    for line in stat_lines(stats) {
+        println!("{}", line);
+    }
+}
 
-    let staged_lines: Vec<&str> = staged_content.lines().collect();
-    let current_lines: Vec<&str> = current_content_str.lines().collect();
+/// The lines of a `--stat` summary; none without changes, as in git.
+fn stat_lines(stats: &[FileStat]) -> Vec<String> {
+    let mut lines = Vec::new();
+    if stats.is_empty() {
+        return lines;
+    }
+    let name_width = stats.iter().map(|stat| stat.path.chars().count()).max().unwrap_or(0);
+    let max_change = stats.iter().map(|stat| stat.insertions + stat.deletions).max().unwrap_or(0);
+    // Binary files show `Bin` in the count column.
+    let bin_width = if stats.iter().any(|stat| stat.binary.is_some()) { 3 } else { 0 };
+    let count_width = max_change.to_string().len().max(bin_width);
+    let graph_width = STAT_WIDTH.saturating_sub(name_width + count_width + 5).max(10);
 
-    let mut common_start = 0;
+    for stat in stats {
+        if let Some((old_size, new_size)) = stat.binary {
+            let bin = "Bin";
+            lines.push(format!(" {:<name_width$} | {bin:>count_width$} {} -> {} bytes", stat.path, old_size, new_size));
+            continue;
+        }
+        let change = stat.insertions + stat.deletions;
+        let (plus, minus) = if max_change <= graph_width {
+            (stat.insertions, stat.deletions)
+        } else {
+            // Scale, keeping at least one mark for any change.
+            let scale = |n: usize| if n == 0 { 0 } else { (n * graph_width / max_change).max(1) };
+            (scale(stat.insertions), scale(stat.deletions))
+        };
+        let line = format!(
+            " {:<name_width$} | {:>count_width$} {}{}",
+            stat.path,
+            change,
+            "+".repeat(plus),
+            "-".repeat(minus),
+        );
+        lines.push(line.trim_end().to_string());
+    }
 
-    while common_start < staged_lines.len()
-    && common_start < current_lines.len()
-    && staged_lines[common_start] == current_lines[common_start]
-    {
-        common_start += 1;
+    let insertions: usize = stats.iter().map(|stat| stat.insertions).sum();
+    let deletions: usize = stats.iter().map(|stat| stat.deletions).sum();
+    let mut summary = format!(" {} file{} changed", stats.len(), if stats.len() == 1 { "" } else { "s" });
+    if insertions > 0 || deletions == 0 {
+        summary.push_str(&format!(", {} insertion{}(+)", insertions, if insertions == 1 { "" } else { "s" }));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary.push_str(&format!(", {} deletion{}(-)", deletions, if deletions == 1 { "" } else { "s" }));
     }
+    lines.push(summary);
+    lines
+}
 
-    let mut common_end = 0;
+/// Prints `--numstat` lines: inserted and deleted lines and the path,
+/// tab-separated, with `-` for the counts of binary files.
+pub fn print_numstat(stats: &[FileStat]) {
+    for stat in stats {
+        match stat.binary {
+            Some(_) => println!("-\t-\t{}", stat.path),
+            None => println!("{}\t{}\t{}", stat.insertions, stat.deletions, stat.path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::DiffColors;
+    use crate::commands::add;
+    use crate::testing::TestRepo;
 
-    while common_end < (staged_lines.len() - common_start)
-        && common_end < (current_lines.len() - common_start)
-        && staged_lines[staged_lines.len() - 1 - common_end]
-            == current_lines[current_lines.len() - 1 - common_end]
-    {
-        common_end += 1;
+    fn side(content: &str, mode: &str) -> Side {
+        let hash = format!("{:x<64}", content.len());
+        Side { hash, mode: mode.to_string(), content: content.as_bytes().to_vec() }
     }
 
-    for line in &staged_lines[common_start..staged_lines.len() - common_end] {
-        println!("-{}", line);
+    fn text(path: &str, old: Option<&Side>, new: Option<&Side>) -> String {
+        let change = FileChange {
+            old_path: path.to_string(),
+            new_path: path.to_string(),
+            old: None,
+            new: None,
+            rename: None,
+        };
+        String::from_utf8(file_diff(&change, old, new, false, &DiffOptions::default()).unwrap()).unwrap()
     }
 
-    for line in &current_lines[common_start..current_lines.len() - common_end] {
-        println!("+{}", line);
+    #[test]
+    fn mode_changes_get_a_header() {
+        let old = side("a\n", "100644");
+        assert_eq!(text("run.sh", Some(&old), Some(&old)), "");
+        assert_eq!(
+            text("run.sh", Some(&old), Some(&side("a\n", "100755"))),
+            "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n"
+        );
+
+        let changed = text("run.sh", Some(&old), Some(&side("ab\n", "100755")));
+        assert!(changed.starts_with(
+            "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\nindex 2xxxxxx..3xxxxxx\n--- a/run.sh\n"
+        ));
+        assert!(changed.ends_with("-a\n+ab\n"));
     }
 
+    #[test]
+    fn added_and_deleted_files() {
+        let file = side("a\n", "100644");
+        assert!(text("a.txt", None, Some(&file)).starts_with("diff --git a/a.txt b/a.txt\nnew file mode 100644\n"));
+        assert!(text("a.txt", Some(&file), None).contains("deleted file mode 100644\nindex 2xxxxxx..0000000\n"));
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn renames_are_paired_and_labelled() {
+        let test = TestRepo::new();
+        test.write("src/old.txt", "one\ntwo\nthree\nfour\n");
+        test.write("kept.txt", "kept\n");
+        test.commit("first");
+        let first = test.head();
+        std::fs::remove_file(test.path("src/old.txt")).unwrap();
+        test.write("src/new.txt", "one\ntwo\nthree\nFOUR\n");
+        add(&test.repo, vec![".".to_string()], false).unwrap();
+        test.commit("rename");
+        let object_store = ObjectStore::new(&test.repo);
+        let old = merge::commit_files(&object_store, &first).unwrap();
+        let new = merge::commit_files(&object_store, &test.head()).unwrap();
+
+        let found = changes(&object_store, &old, &new, Source::Objects, &DiffOptions::default()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].old_path.as_str(), found[0].new_path.as_str()), ("src/old.txt", "src/new.txt"));
+        assert_eq!(found[0].display_path(), "src/{old.txt => new.txt}");
+        let text = String::from_utf8(
+            file_diff(
+                &found[0],
+                Some(&Side::load(&object_store, Source::Objects, "src/old.txt", found[0].old.as_ref().unwrap()).unwrap()),
+                Some(&Side::load(&object_store, Source::Objects, "src/new.txt", found[0].new.as_ref().unwrap()).unwrap()),
+                false,
+                &DiffOptions::default(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(text.starts_with(
+            "diff --git a/src/old.txt b/src/new.txt\nsimilarity index 73%\nrename from src/old.txt\nrename to src/new.txt\n"
+        ));
+        assert!(text.contains("--- a/src/old.txt\n+++ b/src/new.txt\n"));
+
+        let no_renames = DiffOptions { renames: None, ..DiffOptions::default() };
+        let found = changes(&object_store, &old, &new, Source::Objects, &no_renames).unwrap();
+        let paths: Vec<&str> = found.iter().map(|change| change.path()).collect();
+        assert_eq!(paths, ["src/new.txt", "src/old.txt"]);
+    }
+
+    #[test]
+    fn binary_files_are_noted_or_patched() {
+        let change = FileChange {
+            old_path: "logo.png".to_string(),
+            new_path: "logo.png".to_string(),
+            old: None,
+            new: None,
+            rename: None,
+        };
+        let (old, new) = (side("a\0", "100644"), side("ab\0", "100644"));
+        let diff = |options: &DiffOptions| {
+            String::from_utf8(file_diff(&change, Some(&old), Some(&new), true, options).unwrap()).unwrap()
+        };
+
+        assert_eq!(
+            diff(&DiffOptions::default()),
+            "diff --git a/logo.png b/logo.png\nindex 2xxxxxx..3xxxxxx 100644\nBinary files a/logo.png and b/logo.png differ\n"
+        );
+        let patch = diff(&DiffOptions { binary: true, ..DiffOptions::default() });
+        let header = format!("index {}..{} 100644\nGIT binary patch\n", old.hash, new.hash);
+        assert!(patch.starts_with(&format!("diff --git a/logo.png b/logo.png\n{}", header)));
+    }
+
+    #[test]
+    fn attributes_override_the_content_check() {
+        let test = TestRepo::new();
+        test.write(".crabattributes", "*.txt -diff\n*.dat diff\n");
+        let attributes = Attributes::load(&test.repo).unwrap();
+
+        assert!(is_binary(&attributes, "notes.txt", [b"text", b"text"]));
+        assert!(!is_binary(&attributes, "data.dat", [b"\0", b""]));
+        assert!(is_binary(&attributes, "data.bin", [b"", b"\0"]));
+        assert!(!is_binary(&attributes, "data.bin", [b"text", b""]));
+    }
+
+    #[test]
+    fn binary_stats_give_sizes() {
+        let test = TestRepo::new();
+        test.write("data.bin", "a\0");
+        test.write("notes.txt", "a\n");
+        test.commit("first");
+        let first = test.head();
+        test.write("data.bin", "abc\0");
+        test.write("notes.txt", "b\n");
+        test.commit("second");
+        let object_store = ObjectStore::new(&test.repo);
+        let old = merge::commit_files(&object_store, &first).unwrap();
+        let new = merge::commit_files(&object_store, &test.head()).unwrap();
+        let attributes = Attributes::load(&test.repo).unwrap();
+
+        let options = DiffOptions::default();
+        let changes = changes(&object_store, &old, &new, Source::Objects, &options).unwrap();
+        let stats = file_stats(&object_store, &attributes, Source::Objects, &changes, &options).unwrap();
+        let stats: Vec<_> =
+            stats.into_iter().map(|stat| (stat.path, stat.insertions, stat.deletions, stat.binary)).collect();
+        assert_eq!(stats, [("data.bin".to_string(), 0, 0, Some((2, 4))), ("notes.txt".to_string(), 1, 1, None)]);
+    }
+
+    #[test]
+    fn stat_summaries() {
+        let stat = |path: &str, insertions, deletions, binary| FileStat {
+            path: path.to_string(),
+            insertions,
+            deletions,
+            binary,
+        };
+        assert!(stat_lines(&[]).is_empty());
+        let stats = [stat("a.txt", 2, 1, None), stat("longer.txt", 0, 3, None), stat("logo.png", 0, 0, Some((5, 9)))];
+        assert_eq!(
+            stat_lines(&stats),
+            [
+                " a.txt      |   3 ++-",
+                " longer.txt |   3 ---",
+                " logo.png   | Bin 5 -> 9 bytes",
+                " 3 files changed, 2 insertions(+), 4 deletions(-)",
+            ]
+        );
+        assert_eq!(stat_lines(&[stat("a.txt", 1, 0, None)])[1], " 1 file changed, 1 insertion(+)");
+
+        // Large changes are scaled to the width, keeping a mark per side.
+        let lines = stat_lines(&[stat("big.txt", 1000, 1, None)]);
+        assert!(lines[0].starts_with(" big.txt | 1001 +++"));
+        assert!(lines[0].ends_with("+-"));
+        assert!(lines[0].len() <= STAT_WIDTH);
+    }
+
+    #[test]
+    fn name_status_letters() {
+        let entry = |mode: &str| Some(tree_entry("x", &"1".repeat(64), mode));
+        let change = |old, new, rename| FileChange {
+            old_path: "old".to_string(),
+            new_path: "new".to_string(),
+            old,
+            new,
+            rename,
+        };
+        assert_eq!(name_status(&change(None, entry("100644"), None)), "A\tnew");
+        assert_eq!(name_status(&change(entry("100644"), None, None)), "D\told");
+        assert_eq!(name_status(&change(entry("100644"), entry("100755"), None)), "M\tnew");
+        assert_eq!(name_status(&change(entry("100644"), entry("120000"), None)), "T\tnew");
+        let rename = Rename { from: "old".to_string(), to: "new".to_string(), similarity: 87, copy: false };
+        assert_eq!(name_status(&change(entry("100644"), entry("100644"), Some(rename.clone()))), "R087\told\tnew");
+        let copy = Rename { copy: true, ..rename };
+        assert_eq!(name_status(&change(entry("100644"), entry("100644"), Some(copy))), "C087\told\tnew");
+    }
+
+    #[test]
+    fn headers_are_colored_as_meta() {
+        let change = FileChange {
+            old_path: "a.txt".to_string(),
+            new_path: "a.txt".to_string(),
+            old: None,
+            new: None,
+            rename: None,
+        };
+        let options = DiffOptions { colors: Some(DiffColors::default()), ..DiffOptions::default() };
+        let text = file_diff(&change, Some(&side("a\n", "100644")), Some(&side("bb\n", "100644")), false, &options);
+        let text = String::from_utf8(text.unwrap()).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("\x1b[1mdiff --git a/a.txt b/a.txt\x1b[0m"));
+        assert_eq!(lines.next(), Some("\x1b[1mindex 2xxxxxx..3xxxxxx 100644\x1b[0m"));
+        assert_eq!(lines.nth(2), Some("\x1b[36m@@ -1 +1 @@\x1b[0m"));
+    }
+
+    #[test]
+    fn revisions_ranges_and_paths() {
+        let test = TestRepo::new();
+        test.write("src/a.txt", "a");
+        test.commit("first");
+        let first = test.head();
+        test.write("src/a.txt", "b");
+        test.commit("second");
+        let second = test.head();
+        let branch = utils::get_current_branch(&test.repo).unwrap();
+        let object_store = ObjectStore::new(&test.repo);
+        test.cd("src");
+        let split = |args: &[&str]| {
+            split_revisions(&test.repo, &object_store, args.iter().map(|arg| arg.to_string()).collect()).unwrap()
+        };
+
+        assert_eq!(split(&[&first, "HEAD", "a.txt"]), (vec![first.clone(), second.clone()], vec!["a.txt".to_string()]));
+        let range = format!("{}..{}", first, branch);
+        assert_eq!(split(&[&range]), (vec![first.clone(), second.clone()], vec![]));
+        let symmetric = format!("{}...{}", first, branch);
+        assert_eq!(split(&[&symmetric]).0, [first.clone(), second.clone()]);
+        // Files win over revisions, and `..` in a path is not a range.
+        assert_eq!(split(&["a.txt", "HEAD"]).0, Vec::<String>::new());
+        assert_eq!(split(&["../src/a.txt"]), (vec![], vec!["../src/a.txt".to_string()]));
+        assert_eq!(utils::pathspec(&test.repo, "../src").unwrap(), "src");
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{CrabGitError, Repository, Result, object_store::ObjectStore, pack};
+use std::collections::BTreeSet;
+use std::fs;
+
+pub fn gc(repo: &Repository) -> Result<()> {
+    if repo.git_compatible {
+        // Git cannot read CrabGit packs, so packing would hide objects from it.
+        return Err(CrabGitError::InvalidOperation(
+            "gc is not supported in git-compatible repositories, use `git gc` instead".to_string(),
+        ));
+    }
+
+    let object_store = ObjectStore::new(repo);
+    let pack_dir = object_store.pack_dir();
+
+    let loose = object_store.loose_objects()?;
+    let hashes: BTreeSet<String> = loose
+        .iter()
+        .cloned()
+        .chain(object_store.packed_objects()?)
+        .collect();
+
+    if hashes.is_empty() {
+        println!("Nothing to pack");
+        return Ok(());
+    }
+
+    let objects = hashes
+        .into_iter()
+        .map(|hash| {
+            let content = object_store.load_object(&hash)?;
+            Ok((hash, content))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let old_packs: Vec<_> = if pack_dir.is_dir() {
+        fs::read_dir(&pack_dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let stats = pack::write_pack(&pack_dir, objects)?;
+
+    for path in old_packs {
+        let is_new_pack = path
+            .file_stem()
+            .is_some_and(|stem| stem.to_string_lossy() == stats.name);
+        if !is_new_pack {
+            fs::remove_file(path)?;
+        }
+    }
+    for hash in &loose {
+        object_store.remove_loose_object(hash)?;
+    }
+
+    println!(
+        "Packed {} objects ({} as deltas) into {}",
+        stats.objects, stats.deltas, stats.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn packed_objects_stay_readable() {
+        let test = TestRepo::new();
+        test.write("file.txt", &"line\n".repeat(200));
+        test.commit("first");
+        test.write("file.txt", &format!("{}changed\n", "line\n".repeat(200)));
+        test.commit("second");
+        let head = test.head();
+
+        gc(&test.repo).unwrap();
+        let object_store = ObjectStore::new(&test.repo);
+        assert!(object_store.loose_objects().unwrap().is_empty());
+
+        // A second gc repacks everything, including new loose objects, into one pack.
+        test.write("other.txt", "other\n");
+        test.commit("third");
+        gc(&test.repo).unwrap();
+        let packs = fs::read_dir(object_store.pack_dir()).unwrap().count();
+        assert_eq!(packs, 2, "expected one .pack and one .idx");
+
+        let object_store = ObjectStore::new(&test.repo);
+        let second = object_store.load_commit(&head).unwrap();
+        let files = object_store.read_tree(&second.tree).unwrap();
+        let blob = object_store.load_blob(&files["file.txt"].hash).unwrap();
+        assert!(blob.content.ends_with(b"changed\n"));
+        assert_eq!(object_store.load_commit(&test.head()).unwrap().parents, vec![head]);
+    }
+}
@@ -1,28 +1,62 @@
-use crate::{Repository, Result};
+use crate::config::Config;
+use crate::encoding::OBJECT_VERSION;
+use crate::object_store::HashAlgorithm;
+use crate::utils::{CRAB_GIT_DIR, GIT_COMPATIBLE_KEY, GIT_DIR};
+use crate::{CrabGitError, Result};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
-pub fn init(path: Option<PathBuf>) -> Result<()> {
+/// Creates a repository. With `git_compatible` it uses git's `.git` layout,
+/// config and index so git can work on it too; `object_format` picks the hash
+/// function (SHA-1 by default for git repositories, SHA-256 otherwise).
+pub fn init(path: Option<PathBuf>, git_compatible: bool, object_format: Option<HashAlgorithm>) -> Result<()> {
     let work_dir = path.unwrap_or_else(|| std::env::current_dir().unwrap());
-    let git_dir = work_dir.join(".crab_git");
+    let git_dir = work_dir.join(if git_compatible { GIT_DIR } else { CRAB_GIT_DIR });
 
     if git_dir.exists() {
-        return Err("Repository already initialized".into());
+        return Err(CrabGitError::AlreadyExists(format!("Repository {}", git_dir.display())));
     }
 
     fs::create_dir_all(&git_dir)?;
     fs::create_dir_all(git_dir.join("objects"))?;
     fs::create_dir_all(git_dir.join("refs").join("heads"))?;
+    fs::create_dir_all(git_dir.join("refs").join("tags"))?;
     fs::create_dir_all(git_dir.join("refs").join("remotes"))?;
 
-    fs::write(git_dir.join("HEAD"), "ref: refs/heads/main")?;
+    fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n")?;
 
+    fs::create_dir_all(git_dir.join("info"))?;
     fs::write(
-        git_dir.join("config"), 
-        "[\n  \"core\"\n]\n"
+        git_dir.join("info").join("exclude"),
+        "# Repository-local ignore patterns, using the same syntax as .crabignore.\n\
+         .git/\n\
+         target/\n"
     )?;
 
-    println!("Initialized empty crab git repository in {}", git_dir.display());
+    let default_format = if git_compatible { HashAlgorithm::Sha1 } else { HashAlgorithm::Sha256 };
+    let object_format = object_format.unwrap_or(default_format);
+
+    let mut config = Config::load(&git_dir)?;
+    if git_compatible {
+        // Git only reads `extensions.*` in format version 1 repositories.
+        let sha256 = object_format == HashAlgorithm::Sha256;
+        config.set("core.repositoryformatversion", if sha256 { "1" } else { "0" });
+        config.set("core.filemode", "true");
+        config.set("core.bare", "false");
+        config.set(GIT_COMPATIBLE_KEY, "true");
+    } else {
+        config.set("core.objectversion", &OBJECT_VERSION.to_string());
+    }
+    if object_format != default_format {
+        config.set("extensions.objectformat", object_format.as_str());
+    }
+    config.save()?;
+
+    if git_compatible {
+        println!("Initialized empty git-compatible repository in {}", git_dir.display());
+    } else {
+        println!("Initialized empty crab git repository in {}", git_dir.display());
+    }
 
     Ok(())
 }
\ No newline at end of file
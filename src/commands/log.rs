@@ -1,36 +1,733 @@
-use crate::{Repository, Result, object_store::ObjectStore, utils};
+use crate::commands::{diff, merge, DiffSummary};
+use crate::attributes::Attributes;
+use crate::renames::{self, FileVersion};
+use crate::{Commit, CrabGitError, Repository, Result, TreeEntry, color, diff_engine::DiffOptions, graph::Graph, object_store::ObjectStore, refs, rev_parse, utils};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::str::FromStr;
 
-pub fn log(repo: &Repository, max_count: Option<usize>) -> Result<()> {
-    let current_branch = utils::get_current_branch(repo)?;
-    let mut current_commit = utils::get_branch_commit(repo, &current_branch)?;
+/// How `log` prints each commit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Hash, author, date and the indented message.
+    #[default]
+    Medium,
+    /// Hash, author and the subject.
+    Short,
+    /// Hash and subject on one line.
+    Oneline,
+    /// A format string with `%` placeholders, see [`format_commit`].
+    Format(String),
+}
 
-    if current_commit.is_none() {
-        println!("No commits found");
-        return Ok(());
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "medium" => Ok(LogFormat::Medium),
+            "short" => Ok(LogFormat::Short),
+            "oneline" => Ok(LogFormat::Oneline),
+            _ => match value.strip_prefix("format:").or_else(|| value.strip_prefix("tformat:")) {
+                Some(format) => Ok(LogFormat::Format(format.to_string())),
+                None if value.contains('%') => Ok(LogFormat::Format(value.to_string())),
+                None => Err(format!("invalid pretty format: {}", value)),
+            },
+        }
     }
+}
 
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    pub max_count: Option<usize>,
+    pub format: LogFormat,
+    /// Abbreviate commit hashes in the built-in formats.
+    pub abbrev_commit: bool,
+    /// Draw the commit graph to the left of the log.
+    pub graph: bool,
+    /// Show the refs pointing at each commit.
+    pub decorate: bool,
+    /// Oldest commits first.
+    pub reverse: bool,
+    /// Only commits whose author contains one of these.
+    pub authors: Vec<String>,
+    /// Only commits whose message contains one of these.
+    pub grep: Vec<String>,
+    /// Match `authors` and `grep` case-insensitively.
+    pub ignore_case: bool,
+    /// Only commits at or after this time, see [`utils::parse_time`].
+    pub since: Option<String>,
+    /// Only commits at or before this time.
+    pub until: Option<String>,
+    /// Show each commit's patch.
+    pub patch: bool,
+    /// Show an overview of each commit's changed files.
+    pub summary: Option<DiffSummary>,
+    /// Follow the single path given across renames.
+    pub follow: bool,
+    /// How `patch` and `summary` compare files, including rename detection.
+    pub diff: DiffOptions,
+}
+
+/// Shows the commits reachable from `revisions` (HEAD when empty), newest
+/// first. Each revision may be a range (`A..B`, `A...B`) or `^A` to leave
+/// out the commits reachable from `A`. With `paths`, only commits changing
+/// files at or below them are shown; a revision argument that does not
+/// resolve but names an existing file is taken as a path too.
+pub fn log(repo: &Repository, revisions: Vec<String>, mut paths: Vec<String>, options: LogOptions) -> Result<()> {
+    if options.graph && options.reverse {
+        return Err(CrabGitError::InvalidOperation("--reverse and --graph cannot be used together".to_string()));
+    }
     let object_store = ObjectStore::new(repo);
+    let now = Utc::now();
+    let parse_time = |value: &Option<String>| -> Result<Option<DateTime<Utc>>> {
+        value
+            .as_deref()
+            .map(|value| {
+                utils::parse_time(value, now)
+                    .ok_or_else(|| CrabGitError::InvalidOperation(format!("invalid date '{}'", value)))
+            })
+            .transpose()
+    };
+    let filter = Filter {
+        authors: options.authors.iter().map(|author| fold_case(author, options.ignore_case)).collect(),
+        grep: options.grep.iter().map(|pattern| fold_case(pattern, options.ignore_case)).collect(),
+        ignore_case: options.ignore_case,
+        since: parse_time(&options.since)?,
+        until: parse_time(&options.until)?,
+    };
+
+    let (mut include, exclude) = resolve_revisions(repo, &object_store, &revisions, &mut paths)?;
+
+    if include.is_empty() {
+        match utils::get_head_commit(repo)? {
+            Some(head) => include.push(head),
+            None => {
+                println!("No commits found");
+                return Ok(());
+            }
+        }
+    }
+
+    let prefixes = paths.iter().map(|path| utils::pathspec(repo, path)).collect::<Result<Vec<_>>>()?;
 
-    let mut count = 0;
-    let max = max_count.unwrap_or(usize::MAX);
+    if options.follow && prefixes.len() != 1 {
+        return Err(CrabGitError::InvalidOperation("--follow requires exactly one path".to_string()));
+    }
 
-    while let Some(commit_hash) = current_commit {
-        if count >= max {
+    let mut commits = rev_parse::rev_list(&object_store, &include, &exclude)?;
+    if options.graph {
+        commits = rev_parse::topo_order(commits);
+    }
+    let walked: HashMap<String, Commit> = commits.iter().map(|commit| (commit.hash.clone(), commit.clone())).collect();
+
+    // With --follow, the followed path changes at each rename; the commit
+    // that renamed it is shown with both names.
+    let mut followed = options.follow.then(|| prefixes[0].clone());
+    let mut commit_prefixes = HashMap::new();
+    let mut shown = Vec::new();
+    for commit in commits {
+        if shown.len() >= options.max_count.unwrap_or(usize::MAX) {
             break;
         }
+        let mut paths = prefixes.clone();
+        if let Some(path) = &mut followed {
+            paths = vec![path.clone()];
+            if let Some(from) = renamed_from(&object_store, &commit, path, &options.diff)? {
+                paths.push(from.clone());
+                *path = from;
+            }
+        }
+        if filter.matches(&commit) && touches_paths(&object_store, &commit, &paths)? {
+            commit_prefixes.insert(commit.hash.clone(), paths);
+            shown.push(commit);
+        }
+    }
+    let mut commits = shown;
+    let listed: HashSet<String> = commits.iter().map(|commit| commit.hash.clone()).collect();
+    if options.reverse {
+        commits.reverse();
+    }
 
-        let commit = object_store.load_commit(&commit_hash)?;
+    let decorations = decorations(repo, &object_store)?;
+    let attributes = Attributes::load(repo)?;
+    let mut graph = Graph::new();
+    for commit in &commits {
+        let lines = format_commit(&object_store, commit, &decorations, &options)?;
+        if !options.graph {
+            for line in lines {
+                println!("{}", line);
+            }
+        } else {
+            let parents = listed_parents(commit, &listed, &walked);
+            let rows = graph.next(&commit.hash, &parents);
+            for (i, line) in lines.iter().enumerate() {
+                let prefix = if i == 0 { &rows.commit } else { &rows.padding };
+                println!("{}", format!("{} {}", prefix, line).trim_end());
+            }
+            for edge in rows.edges {
+                println!("{}", edge);
+            }
+        }
 
-        println!("commit {}", commit.hash);
-        println!("Author: {}", commit.author);
-        println!("Date: {}", commit.timestamp.format("%Y-%m-%d %H:%M:%S UTC"));
-        println!();
-        println!("    {}", commit.message);
-        println!();
+        // Like git, merges show no changes of their own.
+        if (options.patch || options.summary.is_some()) && commit.parents.len() <= 1 {
+            show_changes(&object_store, &attributes, commit, &commit_prefixes[&commit.hash], &options)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The commits to start from and to leave out. An argument that is not a
+/// revision but names an existing file, relative to the current directory,
+/// is moved to `paths`; `A..B` counts as a range only when both sides resolve,
+/// so `../notes.txt` is a path.
+fn resolve_revisions(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    revisions: &[String],
+    paths: &mut Vec<String>,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    for spec in revisions {
+        if !rev_parse::is_range(repo, object_store, spec)
+            && !spec.starts_with('^')
+            && rev_parse::resolve_commit(repo, object_store, spec).is_err()
+            && Path::new(spec).exists()
+        {
+            paths.push(spec.clone());
+        } else if let Some(name) = spec.strip_prefix('^') {
+            exclude.push(rev_parse::resolve_commit(repo, object_store, name)?);
+        } else {
+            let (tips, excluded) = rev_parse::resolve_range(repo, object_store, spec)?.tips(object_store)?;
+            include.extend(tips);
+            exclude.extend(excluded);
+        }
+    }
+    Ok((include, exclude))
+}
+
+/// The `--author`, `--grep`, `--since` and `--until` conditions.
+struct Filter {
+    authors: Vec<String>,
+    grep: Vec<String>,
+    ignore_case: bool,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
 
-        current_commit = commit.parent;
-        count += 1;
+impl Filter {
+    fn matches(&self, commit: &Commit) -> bool {
+        let contains_any = |text: &str, patterns: &[String]| {
+            let text = fold_case(text, self.ignore_case);
+            patterns.is_empty() || patterns.iter().any(|pattern| text.contains(pattern.as_str()))
+        };
+        contains_any(&commit.author, &self.authors)
+            && contains_any(&commit.message, &self.grep)
+            && self.since.is_none_or(|since| commit.timestamp >= since)
+            && self.until.is_none_or(|until| commit.timestamp <= until)
     }
+}
 
+fn fold_case(text: &str, ignore_case: bool) -> String {
+    if ignore_case { text.to_lowercase() } else { text.to_string() }
+}
+
+/// Whether `commit` changes a file at or below one of `prefixes` (always
+/// true without prefixes). A merge counts only when it differs from every
+/// parent there, so merges that just bring in a branch's changes are skipped.
+fn touches_paths(object_store: &ObjectStore, commit: &Commit, prefixes: &[String]) -> Result<bool> {
+    if prefixes.is_empty() {
+        return Ok(true);
+    }
+    let files = select_paths(object_store.read_tree(&commit.tree)?, prefixes);
+    if commit.parents.is_empty() {
+        return Ok(!files.is_empty());
+    }
+    for parent in &commit.parents {
+        let parent_files = select_paths(merge::commit_files(object_store, parent)?, prefixes);
+        if same_files(&files, &parent_files) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// The path `path` was renamed from in `commit`, when the commit adds it and
+/// it is similar enough to a file deleted from the first parent.
+fn renamed_from(object_store: &ObjectStore, commit: &Commit, path: &str, options: &DiffOptions) -> Result<Option<String>> {
+    let (Some(rename_options), Some(parent)) = (&options.renames, commit.parents.first()) else {
+        return Ok(None);
+    };
+    let files = object_store.read_tree(&commit.tree)?;
+    let parent_files = merge::commit_files(object_store, parent)?;
+    let Some(entry) = files.get(path) else {
+        return Ok(None);
+    };
+    if parent_files.contains_key(path) {
+        return Ok(None);
+    }
+
+    let version = |path: &String, entry: &TreeEntry| FileVersion { path: path.clone(), hash: entry.hash.clone() };
+    let deleted: Vec<FileVersion> = parent_files
+        .iter()
+        .filter(|(path, _)| !files.contains_key(*path))
+        .map(|(path, entry)| version(path, entry))
+        .collect();
+    let added = [version(&path.to_string(), entry)];
+    let renames = renames::detect(&deleted, &added, &[], rename_options, |file| {
+        Ok(object_store.load_blob(&file.hash)?.content)
+    })?;
+    Ok(renames.into_iter().next().map(|rename| rename.from))
+}
+
+fn select_paths(files: BTreeMap<String, TreeEntry>, prefixes: &[String]) -> BTreeMap<String, TreeEntry> {
+    if prefixes.is_empty() {
+        return files;
+    }
+    files
+        .into_iter()
+        .filter(|(path, _)| prefixes.iter().any(|prefix| utils::is_path_or_child(path, prefix)))
+        .collect()
+}
+
+fn same_files(a: &BTreeMap<String, TreeEntry>, b: &BTreeMap<String, TreeEntry>) -> bool {
+    a.len() == b.len() && a.iter().all(|(path, entry)| utils::same_tree_entry(Some(entry), b.get(path)))
+}
+
+/// Prints the summary and/or `--patch` of a commit against its
+/// parent, limited to `prefixes`.
+fn show_changes(
+    object_store: &ObjectStore,
+    attributes: &Attributes,
+    commit: &Commit,
+    prefixes: &[String],
+    options: &LogOptions,
+) -> Result<()> {
+    let old = match commit.parents.first() {
+        Some(parent) => select_paths(merge::commit_files(object_store, parent)?, prefixes),
+        None => BTreeMap::new(),
+    };
+    let new = select_paths(object_store.read_tree(&commit.tree)?, prefixes);
+    let diff_options = &options.diff;
+
+    if let Some(summary) = options.summary {
+        diff::show_tree_summary(object_store, attributes, &old, &new, summary, diff_options)?;
+    }
+    if options.patch {
+        if options.summary.is_some() {
+            println!();
+        }
+        diff::show_tree_diff(object_store, attributes, &old, &new, diff_options)?;
+    }
+    if options.format != LogFormat::Oneline && !matches!(options.format, LogFormat::Format(_)) {
+        println!();
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// The parents the graph connects `commit` to: its nearest ancestors that
+/// are shown, looking through commits that were walked but filtered out.
+fn listed_parents(commit: &Commit, listed: &HashSet<String>, walked: &HashMap<String, Commit>) -> Vec<String> {
+    let mut parents = Vec::new();
+    let mut queue: VecDeque<&String> = commit.parents.iter().collect();
+    let mut seen = HashSet::new();
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        if listed.contains(hash) {
+            if !parents.contains(hash) {
+                parents.push(hash.clone());
+            }
+        } else if let Some(ancestor) = walked.get(hash) {
+            queue.extend(ancestor.parents.iter());
+        }
+    }
+    parents
+}
+
+/// The lines `log` prints for `commit`.
+///
+/// Format strings support `%H`/`%h` (commit hash, full/abbreviated),
+/// `%T`/`%t` (tree), `%P`/`%p` (parents), `%an`, `%ae`, `%ad`, `%ar`, `%at`,
+/// `%ai` and `%aI` (author name, email and date as default, relative, unix,
+/// ISO and strict ISO; the `%c` forms give the same values), `%s` (subject),
+/// `%b` (body), `%B` (raw message), `%d`/`%D` (decorations with and without
+/// parentheses), `%n` (newline) and `%%`.
+pub fn format_commit(
+    object_store: &ObjectStore,
+    commit: &Commit,
+    decorations: &HashMap<String, Vec<String>>,
+    options: &LogOptions,
+) -> Result<Vec<String>> {
+    let labels = decorations.get(&commit.hash).map(|labels| labels.join(", "));
+    let decoration = match &labels {
+        Some(labels) if options.decorate => format!(" ({})", labels),
+        _ => String::new(),
+    };
+    let hash = if options.abbrev_commit {
+        rev_parse::abbreviate(object_store, &commit.hash, DEFAULT_ABBREV)?
+    } else {
+        commit.hash.clone()
+    };
+
+    let commit_color = options.diff.colors.as_ref().map_or("", |colors| colors.commit.as_str());
+
+    let mut lines = Vec::new();
+    match &options.format {
+        LogFormat::Oneline => {
+            let line = format!("{}{} {}", color::paint(commit_color, &hash), decoration, subject(&commit.message));
+            lines.push(line);
+        }
+        LogFormat::Medium | LogFormat::Short => {
+            lines.push(color::paint(commit_color, &format!("commit {}{}", hash, decoration)));
+            if commit.parents.len() > 1 {
+                let short: Vec<&str> = commit.parents.iter().map(|p| &p[..8]).collect();
+                lines.push(format!("Merge: {}", short.join(" ")));
+            }
+            lines.push(format!("Author: {}", commit.author));
+            if options.format == LogFormat::Medium {
+                lines.push(format!("Date: {}", format_date(&commit.timestamp)));
+                lines.push(String::new());
+                lines.extend(commit.message.lines().map(|line| format!("    {}", line)));
+            } else {
+                lines.push(String::new());
+                lines.push(format!("    {}", subject(&commit.message)));
+            }
+            lines.push(String::new());
+        }
+        LogFormat::Format(format) => {
+            let text = expand_placeholders(object_store, format, commit, labels.as_deref())?;
+            lines.extend(text.split('\n').map(str::to_string));
+        }
+    }
+    Ok(lines)
+}
+
+/// Hash length used by `--oneline`, `--abbrev-commit` and `%h`.
+const DEFAULT_ABBREV: usize = 7;
+
+fn expand_placeholders(
+    object_store: &ObjectStore,
+    format: &str,
+    commit: &Commit,
+    labels: Option<&str>,
+) -> Result<String> {
+    let (name, email) = split_identity(&commit.author);
+    let abbreviate = |hash: &str| rev_parse::abbreviate(object_store, hash, DEFAULT_ABBREV);
+
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let Some(key) = chars.next() else {
+            out.push('%');
+            break;
+        };
+        match key {
+            '%' => out.push('%'),
+            'n' => out.push('\n'),
+            'H' => out.push_str(&commit.hash),
+            'h' => out.push_str(&abbreviate(&commit.hash)?),
+            'T' => out.push_str(&commit.tree),
+            't' => out.push_str(&abbreviate(&commit.tree)?),
+            'P' => out.push_str(&commit.parents.join(" ")),
+            'p' => {
+                let parents = commit.parents.iter().map(|parent| abbreviate(parent)).collect::<Result<Vec<_>>>()?;
+                out.push_str(&parents.join(" "));
+            }
+            's' => out.push_str(subject(&commit.message)),
+            'b' => out.push_str(body(&commit.message)),
+            'B' => out.push_str(&commit.message),
+            'd' => {
+                if let Some(labels) = labels {
+                    out.push_str(&format!(" ({})", labels));
+                }
+            }
+            'D' => out.push_str(labels.unwrap_or("")),
+            // The author is also recorded as the committer.
+            'a' | 'c' => {
+                let value = match chars.peek() {
+                    Some('n') => name.to_string(),
+                    Some('e') => email.to_string(),
+                    Some('d') => format_date(&commit.timestamp),
+                    Some('r') => relative_date(&commit.timestamp, Utc::now()),
+                    Some('t') => commit.timestamp.timestamp().to_string(),
+                    Some('i') => commit.timestamp.format("%Y-%m-%d %H:%M:%S +0000").to_string(),
+                    Some('I') => commit.timestamp.format("%Y-%m-%dT%H:%M:%S+00:00").to_string(),
+                    _ => {
+                        out.push('%');
+                        out.push(key);
+                        continue;
+                    }
+                };
+                chars.next();
+                out.push_str(&value);
+            }
+            // Unknown placeholders are printed as they are, like git does.
+            _ => {
+                out.push('%');
+                out.push(key);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// The refs pointing at each commit, as `log --decorate` labels them:
+/// `HEAD -> main` (or `HEAD` when detached) first, then the other refs,
+/// tags as `tag: v1.0`, in the order git uses.
+pub fn decorations(repo: &Repository, object_store: &ObjectStore) -> Result<HashMap<String, Vec<String>>> {
+    let mut labels: HashMap<String, Vec<String>> = HashMap::new();
+    let current = refs::read_ref(repo, "HEAD")?
+        .and_then(|head| head.strip_prefix("ref: ").map(str::to_string));
+
+    if let Some(head) = utils::get_head_commit(repo)? {
+        let label = match &current {
+            Some(branch) => format!("HEAD -> {}", rev_parse::short_ref_name(branch)),
+            None => "HEAD".to_string(),
+        };
+        labels.entry(head).or_default().push(label);
+    }
+
+    for (name, hash) in refs::list_refs(repo)?.into_iter().rev() {
+        if current.as_deref() == Some(name.as_str()) {
+            continue;
+        }
+        let label = match name.strip_prefix("refs/tags/") {
+            Some(tag) => format!("tag: {}", tag),
+            None => rev_parse::short_ref_name(&name).to_string(),
+        };
+        let commit = utils::peel_to_commit(object_store, &hash)?;
+        labels.entry(commit).or_default().push(label);
+    }
+    Ok(labels)
+}
+
+fn subject(message: &str) -> &str {
+    message.lines().next().unwrap_or("")
+}
+
+/// The message after the subject and the blank line following it.
+fn body(message: &str) -> &str {
+    message.split_once("\n\n").map(|(_, body)| body).unwrap_or("")
+}
+
+/// Splits `Name <email>` into name and email.
+fn split_identity(identity: &str) -> (&str, &str) {
+    match identity.split_once('<') {
+        Some((name, email)) => (name.trim(), email.trim_end_matches('>')),
+        None => (identity.trim(), ""),
+    }
+}
+
+fn format_date(timestamp: &DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+/// `timestamp` relative to `now`, such as `3 hours ago`.
+fn relative_date(timestamp: &DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let seconds = (now - *timestamp).num_seconds().max(0);
+    let (count, unit) = match seconds {
+        s if s < 90 => (s, "second"),
+        s if s < 90 * 60 => (s / 60, "minute"),
+        s if s < 36 * 3600 => (s / 3600, "hour"),
+        s if s < 14 * 86400 => (s / 86400, "day"),
+        s if s < 10 * 7 * 86400 => (s / (7 * 86400), "week"),
+        s if s < 365 * 86400 => (s / (30 * 86400), "month"),
+        s => (s / (365 * 86400), "year"),
+    };
+    format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tag;
+    use crate::testing::TestRepo;
+    use chrono::Duration;
+
+    fn format(test: &TestRepo, format: LogFormat, decorate: bool) -> Vec<String> {
+        let object_store = ObjectStore::new(&test.repo);
+        let commit = object_store.load_commit(&test.head()).unwrap();
+        let options = LogOptions { format, decorate, ..LogOptions::default() };
+        format_commit(&object_store, &commit, &decorations(&test.repo, &object_store).unwrap(), &options).unwrap()
+    }
+
+    #[test]
+    fn pretty_formats_parse() {
+        assert_eq!("oneline".parse(), Ok(LogFormat::Oneline));
+        assert_eq!("format:%h".parse(), Ok(LogFormat::Format("%h".to_string())));
+        assert_eq!("tformat:%s".parse(), Ok(LogFormat::Format("%s".to_string())));
+        assert_eq!("%an".parse(), Ok(LogFormat::Format("%an".to_string())));
+        assert!("fancy".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn placeholders_expand() {
+        let test = TestRepo::new();
+        test.write("a.txt", "a");
+        test.commit("subject\n\nbody text");
+        let head = test.head();
+        tag(&test.repo, Some("v1".to_string()), None, None, false, None).unwrap();
+        let branch = utils::get_current_branch(&test.repo).unwrap();
+
+        let lines = format(&test, LogFormat::Format("%H|%h|%s|%b|%an|%ae|%%|%x%n%D".to_string()), false);
+        let (name, email) = split_identity(utils::DEFAULT_IDENTITY);
+        assert_eq!(
+            lines,
+            [
+                format!("{}|{}|subject|body text|{}|{}|%|%x", head, &head[..DEFAULT_ABBREV], name, email),
+                format!("HEAD -> {}, tag: v1", branch),
+            ]
+        );
+
+        let oneline = format(&test, LogFormat::Oneline, true);
+        assert_eq!(oneline, [format!("{} (HEAD -> {}, tag: v1) subject", head, branch)]);
+    }
+
+    #[test]
+    fn built_in_formats() {
+        let test = TestRepo::new();
+        test.write("a.txt", "a");
+        test.commit("subject\n\nbody text");
+
+        let medium = format(&test, LogFormat::Medium, false);
+        assert_eq!(medium[0], format!("commit {}", test.head()));
+        assert_eq!(&medium[3..], ["", "    subject", "    ", "    body text", ""]);
+        let short = format(&test, LogFormat::Short, false);
+        assert_eq!(&short[2..], ["", "    subject", ""]);
+    }
+
+    #[test]
+    fn filters_match_author_message_and_date() {
+        let commit = Commit {
+            hash: "h".to_string(),
+            parents: Vec::new(),
+            tree: "t".to_string(),
+            author: "Alice Smith <alice@example.com>".to_string(),
+            message: "Fix the parser".to_string(),
+            timestamp: DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z").unwrap().with_timezone(&Utc),
+        };
+        let at = |value: &str| Some(DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc));
+        let filter = |authors: &[&str], grep: &[&str], ignore_case: bool| Filter {
+            authors: authors.iter().map(|author| fold_case(author, ignore_case)).collect(),
+            grep: grep.iter().map(|pattern| fold_case(pattern, ignore_case)).collect(),
+            ignore_case,
+            since: None,
+            until: None,
+        };
+
+        assert!(filter(&[], &[], false).matches(&commit));
+        assert!(filter(&["Bob", "alice@"], &[], false).matches(&commit));
+        assert!(!filter(&["Bob"], &[], false).matches(&commit));
+        assert!(!filter(&[], &["fix"], false).matches(&commit));
+        assert!(filter(&[], &["fix"], true).matches(&commit));
+        assert!(!filter(&["ALICE"], &["parser"], false).matches(&commit));
+
+        let mut dated = filter(&[], &[], false);
+        dated.since = at("2024-05-10T12:00:00Z");
+        dated.until = at("2024-05-11T00:00:00Z");
+        assert!(dated.matches(&commit));
+        dated.since = at("2024-05-10T12:00:01Z");
+        assert!(!dated.matches(&commit));
+    }
+
+    #[test]
+    fn paths_keep_commits_that_change_them() {
+        let test = TestRepo::new();
+        test.write("src/a.txt", "a");
+        test.write("notes.txt", "n");
+        test.commit("first");
+        test.write("notes.txt", "n2");
+        test.commit("notes");
+        let object_store = ObjectStore::new(&test.repo);
+        let head = object_store.load_commit(&test.head()).unwrap();
+        let first = object_store.load_commit(&head.parents[0]).unwrap();
+
+        let touches = |commit: &Commit, prefixes: &[&str]| {
+            let prefixes: Vec<String> = prefixes.iter().map(|prefix| prefix.to_string()).collect();
+            touches_paths(&object_store, commit, &prefixes).unwrap()
+        };
+        assert!(touches(&head, &[]));
+        assert!(touches(&head, &["notes.txt"]));
+        assert!(!touches(&head, &["src"]));
+        assert!(touches(&first, &["src"]));
+        assert!(!touches(&first, &["sr"]));
+    }
+
+    #[test]
+    fn arguments_that_name_files_are_paths() {
+        let test = TestRepo::new();
+        test.write("notes.txt", "n");
+        test.write("src/a.txt", "a");
+        test.commit("first");
+        let branch = utils::get_current_branch(&test.repo).unwrap();
+        let object_store = ObjectStore::new(&test.repo);
+        test.cd("src");
+
+        let mut paths = Vec::new();
+        let revisions = vec!["a.txt".to_string(), "../notes.txt".to_string(), format!("{}..{}", branch, branch)];
+        let (include, exclude) = resolve_revisions(&test.repo, &object_store, &revisions, &mut paths).unwrap();
+        assert_eq!(paths, ["a.txt", "../notes.txt"]);
+        assert_eq!((include, exclude), (vec![test.head()], vec![test.head()]));
+        let prefixes: Vec<String> = paths.iter().map(|path| utils::pathspec(&test.repo, path).unwrap()).collect();
+        assert_eq!(prefixes, ["src/a.txt", "notes.txt"]);
+
+        let missing = vec!["missing".to_string()];
+        assert!(resolve_revisions(&test.repo, &object_store, &missing, &mut paths).is_err());
+    }
+
+    #[test]
+    fn follow_finds_the_previous_name() {
+        let test = TestRepo::new();
+        test.write("old.txt", "one\ntwo\nthree\n");
+        test.commit("first");
+        std::fs::remove_file(test.path("old.txt")).unwrap();
+        test.write("new.txt", "one\ntwo\nthree\nfour\n");
+        crate::commands::add(&test.repo, vec![".".to_string()], false).unwrap();
+        test.commit("rename");
+        let object_store = ObjectStore::new(&test.repo);
+        let commit = object_store.load_commit(&test.head()).unwrap();
+        let first = object_store.load_commit(&commit.parents[0]).unwrap();
+
+        let options = DiffOptions::default();
+        assert_eq!(renamed_from(&object_store, &commit, "new.txt", &options).unwrap().as_deref(), Some("old.txt"));
+        assert_eq!(renamed_from(&object_store, &first, "old.txt", &options).unwrap(), None);
+        let no_renames = DiffOptions { renames: None, ..DiffOptions::default() };
+        assert_eq!(renamed_from(&object_store, &commit, "new.txt", &no_renames).unwrap(), None);
+    }
+
+    #[test]
+    fn commit_lines_use_the_commit_color() {
+        let test = TestRepo::new();
+        test.write("a.txt", "a");
+        test.commit("subject");
+        let object_store = ObjectStore::new(&test.repo);
+        let commit = object_store.load_commit(&test.head()).unwrap();
+        let mut options = LogOptions::default();
+        options.diff.colors = Some(crate::color::DiffColors::default());
+
+        let lines = format_commit(&object_store, &commit, &HashMap::new(), &options).unwrap();
+        assert_eq!(lines[0], format!("\x1b[33mcommit {}\x1b[0m", commit.hash));
+        options.format = LogFormat::Oneline;
+        let lines = format_commit(&object_store, &commit, &HashMap::new(), &options).unwrap();
+        assert_eq!(lines[0], format!("\x1b[33m{}\x1b[0m subject", commit.hash));
+    }
+
+    #[test]
+    fn relative_dates() {
+        let now = Utc::now();
+        assert_eq!(relative_date(&(now - Duration::seconds(1)), now), "1 second ago");
+        assert_eq!(relative_date(&(now - Duration::hours(3)), now), "3 hours ago");
+        assert_eq!(relative_date(&(now - Duration::days(20)), now), "2 weeks ago");
+        assert_eq!(relative_date(&(now - Duration::days(800)), now), "2 years ago");
+    }
+}
@@ -0,0 +1,496 @@
+use crate::{CrabGitError, Index, IndexEntry, Repository, Result, TreeEntry, object_store::ObjectStore, rev_parse, utils};
+use crate::commands::{checkout, commit};
+use crate::refs::{Expected, RefTransaction};
+use crate::diff_engine::{self, Change, DiffAlgorithm};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+
+enum MergeResult {
+    Clean(Vec<u8>),
+    Conflict(Vec<u8>),
+}
+
+pub fn merge(repo: &Repository, branch: String, author: Option<String>) -> Result<()> {
+    if repo.git_dir.join("MERGE_HEAD").exists() {
+        return Err(CrabGitError::InvalidOperation(
+            "A merge is already in progress, commit the result first".to_string(),
+        ));
+    }
+
+    let current_branch = utils::get_current_branch(repo)?;
+    if current_branch == "detached" {
+        return Err(CrabGitError::InvalidOperation("Cannot merge into a detached HEAD".to_string()));
+    }
+
+    let object_store = ObjectStore::new(repo);
+
+    let ours = utils::get_branch_commit(repo, &current_branch)?
+        .ok_or_else(|| CrabGitError::InvalidOperation("No commits yet, nothing to merge into".to_string()))?;
+    let theirs = rev_parse::resolve_commit(repo, &object_store, &branch)?;
+
+    let base = find_merge_base(&object_store, &ours, &theirs)?;
+
+    if base.as_deref() == Some(theirs.as_str()) {
+        println!("Already up to date.");
+        return Ok(());
+    }
+
+    let ours_files = commit_files(&object_store, &ours)?;
+    let index = utils::load_index(repo)?;
+    ensure_index_clean(&index, &ours_files)?;
+
+    if base.as_deref() == Some(ours.as_str()) {
+        checkout::update_working_tree(repo, &object_store, Some(&ours), &theirs, false)?;
+        let reason = format!("merge {}: Fast-forward", branch);
+        utils::update_branch(repo, &current_branch, &theirs, Expected::Value(ours.clone()), &reason)?;
+        println!("Fast-forward {}..{}", &ours[..8], &theirs[..8]);
+        return Ok(());
+    }
+
+    let base_files = match &base {
+        Some(hash) => commit_files(&object_store, hash)?,
+        None => BTreeMap::new(),
+    };
+    let theirs_files = commit_files(&object_store, &theirs)?;
+
+    let paths: BTreeSet<&String> = base_files
+        .keys()
+        .chain(ours_files.keys())
+        .chain(theirs_files.keys())
+        .collect();
+
+    let mut merged = Index::default();
+    let mut updates: BTreeMap<String, Option<Vec<u8>>> = BTreeMap::new();
+
+    for path in paths {
+        let b = base_files.get(path);
+        let o = ours_files.get(path);
+        let t = theirs_files.get(path);
+
+        let chosen = if utils::same_tree_entry(o, t) || utils::same_tree_entry(t, b) {
+            o
+        } else if utils::same_tree_entry(o, b) {
+            if let Some(entry) = t {
+                updates.insert(path.clone(), Some(object_store.load_blob(&entry.hash)?.content));
+            } else {
+                updates.insert(path.clone(), None);
+            }
+            t
+        } else {
+            match (o, t) {
+                (Some(o), Some(t)) => {
+                    let base_content = match b {
+                        Some(entry) => object_store.load_blob(&entry.hash)?.content,
+                        None => Vec::new(),
+                    };
+                    let ours_content = object_store.load_blob(&o.hash)?.content;
+                    let theirs_content = object_store.load_blob(&t.hash)?.content;
+
+                    match merge_content(&base_content, &ours_content, &theirs_content, &branch) {
+                        MergeResult::Clean(content) => {
+                            let hash = object_store.store_blob(&content)?;
+                            merged.entries.insert(
+                                path.clone(),
+                                IndexEntry { hash, mode: o.mode.clone(), path: path.clone() },
+                            );
+                            updates.insert(path.clone(), Some(content));
+                            continue;
+                        }
+                        MergeResult::Conflict(content) => {
+                            println!("CONFLICT (content): Merge conflict in {}", path);
+                            updates.insert(path.clone(), Some(content));
+                        }
+                    }
+                }
+                (Some(_), None) => {
+                    println!("CONFLICT (modify/delete): {} deleted in {}", path, branch);
+                }
+                (None, Some(t)) => {
+                    println!("CONFLICT (modify/delete): {} deleted in HEAD", path);
+                    updates.insert(path.clone(), Some(object_store.load_blob(&t.hash)?.content));
+                }
+                (None, None) => unreachable!(),
+            }
+            merged.unmerged.insert(path.clone());
+            o.or(t)
+        };
+
+        if let Some(entry) = chosen {
+            merged.entries.insert(
+                path.clone(),
+                IndexEntry { hash: entry.hash.clone(), mode: entry.mode.clone(), path: path.clone() },
+            );
+        }
+    }
+
+    ensure_worktree_clean(repo, &object_store, &ours_files, updates.keys())?;
+
+    for (path, content) in &updates {
+        let file_path = repo.work_dir.join(path);
+        match content {
+            Some(content) => {
+                if let Some(parent) = file_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&file_path, content)?;
+            }
+            None => {
+                if file_path.exists() {
+                    fs::remove_file(&file_path)?;
+                }
+            }
+        }
+    }
+
+    let message = format!("Merge branch '{}' into {}", branch, current_branch);
+
+    if !merged.unmerged.is_empty() {
+        utils::save_index(repo, &merged)?;
+        let mut transaction = RefTransaction::new(repo);
+        transaction.update("MERGE_HEAD", &theirs, Expected::Missing, None);
+        transaction.commit()?;
+        fs::write(repo.git_dir.join("MERGE_MSG"), &message)?;
+        return Err(CrabGitError::conflict(
+            "Automatic merge failed; fix conflicts and then commit the result:",
+            merged.unmerged.into_iter().collect(),
+        ));
+    }
+
+    let tree_hash = object_store.write_tree(&merged.entries)?;
+    let expected = Expected::Value(ours.clone());
+    let author = author.unwrap_or_else(|| utils::DEFAULT_IDENTITY.to_string());
+    let commit_hash = commit::create_commit(
+        &object_store,
+        tree_hash,
+        vec![ours, theirs],
+        author,
+        message,
+    )?;
+
+    let reason = format!("merge {}: Merge made by three-way merge", branch);
+    utils::update_branch(repo, &current_branch, &commit_hash, expected, &reason)?;
+    utils::save_index(repo, &merged)?;
+
+    println!("Merge made commit {}", &commit_hash[..8]);
+    Ok(())
+}
+
+/// Picks the newest of the best common ancestors of two commits.
+pub fn find_merge_base(object_store: &ObjectStore, ours: &str, theirs: &str) -> Result<Option<String>> {
+    let mut best = None;
+    for hash in merge_bases(object_store, ours, theirs)? {
+        let timestamp = object_store.load_commit(&hash)?.timestamp;
+        if best.as_ref().is_none_or(|(best_time, _)| timestamp > *best_time) {
+            best = Some((timestamp, hash));
+        }
+    }
+    Ok(best.map(|(_, hash)| hash))
+}
+
+/// Returns every common ancestor of two commits that is not itself an
+/// ancestor of another common ancestor. Criss-cross histories have several.
+pub fn merge_bases(object_store: &ObjectStore, ours: &str, theirs: &str) -> Result<Vec<String>> {
+    let ours_ancestors = ancestors(object_store, ours)?;
+    let common: HashSet<String> = ancestors(object_store, theirs)?
+        .into_iter()
+        .filter(|hash| ours_ancestors.contains(hash))
+        .collect();
+
+    // Everything reachable from a common ancestor's parents is common too,
+    // and redundant.
+    let mut redundant = HashSet::new();
+    let mut stack = Vec::new();
+    for hash in &common {
+        stack.extend(object_store.load_commit(hash)?.parents);
+    }
+    while let Some(hash) = stack.pop() {
+        if redundant.insert(hash.clone()) {
+            stack.extend(object_store.load_commit(&hash)?.parents);
+        }
+    }
+
+    let mut bases: Vec<String> = common.into_iter().filter(|hash| !redundant.contains(hash)).collect();
+    bases.sort();
+    Ok(bases)
+}
+
+/// Every commit reachable from `start`, including itself.
+pub fn ancestors(object_store: &ObjectStore, start: &str) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![start.to_string()];
+    while let Some(hash) = stack.pop() {
+        if seen.insert(hash.clone()) {
+            stack.extend(object_store.load_commit(&hash)?.parents);
+        }
+    }
+    Ok(seen)
+}
+
+pub fn commit_files(object_store: &ObjectStore, commit_hash: &str) -> Result<BTreeMap<String, TreeEntry>> {
+    let commit = object_store.load_commit(commit_hash)?;
+    object_store.read_tree(&commit.tree)
+}
+
+/// The index must match HEAD exactly: no staged additions, modifications or deletions.
+fn ensure_index_clean(index: &Index, head_files: &BTreeMap<String, TreeEntry>) -> Result<()> {
+    let paths: BTreeSet<&String> = index.entries.keys().chain(head_files.keys()).collect();
+    let staged: Vec<String> = paths
+        .into_iter()
+        .filter(|path| {
+            index.entries.get(*path).map(|e| &e.hash) != head_files.get(*path).map(|e| &e.hash)
+        })
+        .cloned()
+        .collect();
+
+    if !staged.is_empty() {
+        return Err(CrabGitError::conflict(
+            "Your staged changes would be overwritten by merge, commit them first:",
+            staged,
+        ));
+    }
+    Ok(())
+}
+
+fn ensure_worktree_clean<'a>(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    head_files: &BTreeMap<String, TreeEntry>,
+    paths: impl Iterator<Item = &'a String>,
+) -> Result<()> {
+    let mut dirty = Vec::new();
+    for path in paths {
+        let file_path = repo.work_dir.join(path);
+        let current = if file_path.is_file() {
+            Some(object_store.hash_blob(&fs::read(&file_path)?))
+        } else {
+            None
+        };
+        if current.as_ref() != head_files.get(path).map(|e| &e.hash) {
+            dirty.push(path.clone());
+        }
+    }
+
+    if !dirty.is_empty() {
+        return Err(CrabGitError::conflict(
+            "Your local changes would be overwritten by merge:",
+            dirty,
+        ));
+    }
+    Ok(())
+}
+
+/// Line-level three-way merge. Changes made on only one side are taken as-is,
+/// overlapping changes that differ are wrapped in conflict markers.
+fn merge_content(base: &[u8], ours: &[u8], theirs: &[u8], their_name: &str) -> MergeResult {
+    let base_lines = diff_engine::split_lines(base);
+    let ours_lines = diff_engine::split_lines(ours);
+    let theirs_lines = diff_engine::split_lines(theirs);
+
+    let ours_hunks = diff_engine::changes(&diff_engine::diff(&base_lines, &ours_lines, DiffAlgorithm::Histogram));
+    let theirs_hunks = diff_engine::changes(&diff_engine::diff(&base_lines, &theirs_lines, DiffAlgorithm::Histogram));
+
+    let mut output = Vec::new();
+    let mut conflict = false;
+    let mut base_pos = 0;
+    let (mut i, mut j) = (0, 0);
+
+    while i < ours_hunks.len() || j < theirs_hunks.len() {
+        // Start a group with whichever hunk begins first, then absorb every hunk
+        // from either side that overlaps or touches the group's base range.
+        let start = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+            (Some(o), Some(t)) => o.old.start.min(t.old.start),
+            (Some(o), None) => o.old.start,
+            (None, Some(t)) => t.old.start,
+            (None, None) => unreachable!(),
+        };
+        let mut end = start;
+        let (first_i, first_j) = (i, j);
+        loop {
+            if let Some(o) = ours_hunks.get(i).filter(|h| h.old.start <= end) {
+                end = end.max(o.old.end);
+                i += 1;
+            } else if let Some(t) = theirs_hunks.get(j).filter(|h| h.old.start <= end) {
+                end = end.max(t.old.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        for line in &base_lines[base_pos..start] {
+            output.extend_from_slice(line);
+        }
+        base_pos = end;
+
+        let ours_text = apply_hunks(&base_lines, &ours_lines, &ours_hunks[first_i..i], start, end);
+        let theirs_text = apply_hunks(&base_lines, &theirs_lines, &theirs_hunks[first_j..j], start, end);
+
+        if first_j == j || ours_text == theirs_text {
+            output.extend_from_slice(&ours_text);
+        } else if first_i == i {
+            output.extend_from_slice(&theirs_text);
+        } else {
+            conflict = true;
+            output.extend_from_slice(b"<<<<<<< HEAD\n");
+            push_section(&mut output, &ours_text);
+            output.extend_from_slice(b"=======\n");
+            push_section(&mut output, &theirs_text);
+            output.extend_from_slice(format!(">>>>>>> {}\n", their_name).as_bytes());
+        }
+    }
+
+    for line in &base_lines[base_pos..] {
+        output.extend_from_slice(line);
+    }
+
+    if conflict {
+        MergeResult::Conflict(output)
+    } else {
+        MergeResult::Clean(output)
+    }
+}
+
+fn push_section(output: &mut Vec<u8>, text: &[u8]) {
+    output.extend_from_slice(text);
+    if !text.is_empty() && !text.ends_with(b"\n") {
+        output.push(b'\n');
+    }
+}
+
+/// Rebuilds one side's text for base range `start..end` by replacing each of
+/// its changes in that range.
+fn apply_hunks(base: &[&[u8]], side: &[&[u8]], hunks: &[Change], start: usize, end: usize) -> Vec<u8> {
+    let mut text = Vec::new();
+    let mut pos = start;
+    for hunk in hunks {
+        for line in &base[pos..hunk.old.start] {
+            text.extend_from_slice(line);
+        }
+        for line in &side[hunk.new.clone()] {
+            text.extend_from_slice(line);
+        }
+        pos = hunk.old.end;
+    }
+    for line in &base[pos..end] {
+        text.extend_from_slice(line);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{branch, commit};
+    use crate::testing::TestRepo;
+
+    fn merged(base: &str, ours: &str, theirs: &str) -> (bool, String) {
+        match merge_content(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), "topic") {
+            MergeResult::Clean(content) => (true, String::from_utf8(content).unwrap()),
+            MergeResult::Conflict(content) => (false, String::from_utf8(content).unwrap()),
+        }
+    }
+
+    #[test]
+    fn merge_content_takes_changes_from_both_sides() {
+        let base = "a\nb\nc\nd\ne\n";
+        let (clean, content) = merged(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\n");
+        assert!(clean);
+        assert_eq!(content, "A\nb\nc\nd\nE\n");
+    }
+
+    #[test]
+    fn merge_content_accepts_identical_changes() {
+        let (clean, content) = merged("a\nb\n", "a\nB\n", "a\nB\n");
+        assert!(clean);
+        assert_eq!(content, "a\nB\n");
+    }
+
+    #[test]
+    fn merge_content_marks_overlapping_changes() {
+        let (clean, content) = merged("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc\n");
+        assert!(!clean);
+        assert_eq!(content, "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\nc\n");
+    }
+
+    #[test]
+    fn merge_content_treats_adjacent_changes_as_a_conflict() {
+        let (clean, content) = merged("a\nb\n", "A\nb\n", "a\nB\n");
+        assert!(!clean);
+        assert_eq!(content, "<<<<<<< HEAD\nA\nb\n=======\na\nB\n>>>>>>> topic\n");
+    }
+
+    #[test]
+    fn merge_content_terminates_sections_without_newline() {
+        let (clean, content) = merged("x", "ours", "theirs");
+        assert!(!clean);
+        assert_eq!(content, "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\n");
+    }
+
+    #[test]
+    fn merge_bases_of_criss_cross_history() {
+        let test = TestRepo::new();
+        let object_store = ObjectStore::new(&test.repo);
+        let tree = object_store.write_tree(&Index::default().entries).unwrap();
+        let make = |parents: &[&String], message: &str| {
+            let parents = parents.iter().map(|p| p.to_string()).collect();
+            commit::create_commit(&object_store, tree.clone(), parents, "A <a@b>".into(), message.into()).unwrap()
+        };
+
+        let root = make(&[], "root");
+        let left = make(&[&root], "left");
+        let right = make(&[&root], "right");
+        let left_merge = make(&[&left, &right], "left merge");
+        let right_merge = make(&[&right, &left], "right merge");
+
+        assert_eq!(merge_bases(&object_store, &left, &right).unwrap(), vec![root.clone()]);
+        assert_eq!(merge_bases(&object_store, &left_merge, &left).unwrap(), vec![left.clone()]);
+
+        let mut expected = vec![left.clone(), right.clone()];
+        expected.sort();
+        assert_eq!(merge_bases(&object_store, &left_merge, &right_merge).unwrap(), expected);
+        let base = find_merge_base(&object_store, &left_merge, &right_merge).unwrap().unwrap();
+        assert!(expected.contains(&base));
+    }
+
+    #[test]
+    fn conflicted_merge_is_concluded_with_the_merge_message() {
+        let test = TestRepo::new();
+        test.write("file.txt", "base\n");
+        test.commit("base");
+        branch(&test.repo, Some("topic".to_string()), false).unwrap();
+
+        test.write("file.txt", "main\n");
+        test.commit("main change");
+        let ours = test.head();
+        checkout::checkout(&test.repo, "topic".to_string(), false).unwrap();
+        test.write("file.txt", "topic\n");
+        test.commit("topic change");
+        let theirs = test.head();
+        checkout::checkout(&test.repo, "main".to_string(), false).unwrap();
+
+        assert!(merge(&test.repo, "topic".to_string(), None).is_err());
+        let merge_msg = test.repo.git_dir.join("MERGE_MSG");
+        assert_eq!(fs::read_to_string(&merge_msg).unwrap(), "Merge branch 'topic' into main");
+        assert!(fs::read_to_string(test.path("file.txt")).unwrap().contains("<<<<<<< HEAD\nmain\n"));
+
+        assert!(commit::commit(&test.repo, None, None).is_err());
+        test.write("file.txt", "resolved\n");
+        test.add_all();
+        commit::commit(&test.repo, None, None).unwrap();
+
+        let object_store = ObjectStore::new(&test.repo);
+        let merge_commit = object_store.load_commit(&test.head()).unwrap();
+        assert_eq!(merge_commit.message, "Merge branch 'topic' into main");
+        assert_eq!(merge_commit.parents, vec![ours, theirs]);
+        assert!(!merge_msg.exists());
+        assert!(!test.repo.git_dir.join("MERGE_HEAD").exists());
+    }
+
+    #[test]
+    fn commit_without_message_outside_a_merge_is_rejected() {
+        let test = TestRepo::new();
+        test.write("file.txt", "content\n");
+        test.add_all();
+        assert!(commit::commit(&test.repo, None, None).is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use crate::config::Config;
+use crate::encoding::{self, OBJECT_VERSION};
+use crate::refs::{Expected, RefTransaction};
+use crate::{CrabGitError, Commit, IndexEntry, Repository, Result, Tag, TreeEntry, object_store::ObjectStore, refs, utils};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use walkdir::WalkDir;
+
+/// Commits written before merge support had a single optional `parent`.
+#[derive(Deserialize)]
+struct LegacyCommit {
+    tree: String,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    parents: Vec<String>,
+    author: String,
+    message: String,
+    timestamp: DateTime<Utc>,
+}
+
+/// Rewrites a repository whose objects are stored as JSON into the binary
+/// object encoding. Every object reachable from a ref, `HEAD`, `MERGE_HEAD`
+/// or the index is converted and the refs and index are pointed at the new
+/// hashes; the old objects, including unreachable ones, are deleted
+/// afterwards.
+///
+/// The version in the config is written last, after the refs and the index,
+/// and objects already in the new encoding are left as they are, so an
+/// interrupted migration can simply be run again.
+pub fn migrate(repo: &Repository) -> Result<()> {
+    let mut config = Config::for_repo(repo)?;
+    if utils::object_version(repo, &config) >= OBJECT_VERSION {
+        println!("Repository already uses object format version {}", OBJECT_VERSION);
+        return Ok(());
+    }
+
+    let object_store = ObjectStore::new(repo);
+    // A previous, interrupted run may have written new objects already.
+    let mut old_loose = Vec::new();
+    for hash in object_store.loose_objects()? {
+        if encoding::is_legacy(&object_store.load_object(&hash)?) {
+            old_loose.push(hash);
+        }
+    }
+    let old_packed = object_store.packed_objects()?;
+    let pack_dir = object_store.pack_dir();
+    let old_packs: Vec<_> = if pack_dir.is_dir() {
+        fs::read_dir(&pack_dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut migration = Migration {
+        object_store: &object_store,
+        converted: HashMap::new(),
+    };
+
+    // All refs move to the new hashes together, each only if unchanged.
+    // Branches and tags may be nested, as in `refs/heads/feature/x`.
+    let mut ref_names = Vec::new();
+    for entry in WalkDir::new(repo.git_dir.join("refs")) {
+        let entry = entry.map_err(std::io::Error::other)?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || path.extension().is_some_and(|extension| extension == "lock") {
+            continue;
+        }
+        ref_names.push(path.strip_prefix(&repo.git_dir)?.to_string_lossy().replace("\\", "/"));
+    }
+    ref_names.extend(["HEAD".to_string(), "MERGE_HEAD".to_string()]);
+
+    let mut transaction = RefTransaction::new(repo);
+    for name in ref_names {
+        match refs::read_ref(repo, &name)? {
+            Some(old) if !old.starts_with("ref: ") => {
+                let new = migration.convert(&old)?;
+                transaction.update(&name, &new, Expected::Value(old), None);
+            }
+            _ => {}
+        }
+    }
+    transaction.commit()?;
+
+    // Legacy indexes only hold the paths staged since the last commit, so the
+    // converted entries go on top of the (already converted) HEAD tree.
+    let staged = utils::load_legacy_index(repo)?;
+    let mut index = utils::head_index(repo)?;
+    for (path, mut entry) in staged.entries {
+        entry.hash = migration.convert(&entry.hash)?;
+        index.entries.insert(path, entry);
+    }
+    index.unmerged = staged.unmerged;
+    utils::save_index(repo, &index)?;
+
+    config.set("core.objectversion", &OBJECT_VERSION.to_string());
+    config.save()?;
+
+    let converted = migration.converted.len();
+    let unreachable = old_loose
+        .iter()
+        .chain(&old_packed)
+        .filter(|hash| !migration.converted.contains_key(*hash))
+        .count();
+    for hash in old_loose {
+        object_store.remove_loose_object(&hash)?;
+    }
+    for path in old_packs {
+        fs::remove_file(path)?;
+    }
+
+    println!("Migrated {} objects to object format version {}", converted, OBJECT_VERSION);
+    if unreachable > 0 {
+        println!("Removed {} old objects that nothing refers to", unreachable);
+    }
+    Ok(())
+}
+
+struct Migration<'a> {
+    object_store: &'a ObjectStore,
+    /// Old object hash to new object hash.
+    converted: HashMap<String, String>,
+}
+
+impl Migration<'_> {
+    /// Converts a legacy object of any kind, returning its new hash. Objects
+    /// already in the new encoding are returned unchanged.
+    fn convert(&mut self, hash: &str) -> Result<String> {
+        if let Some(new) = self.converted.get(hash) {
+            return Ok(new.clone());
+        }
+
+        let data = self.object_store.load_object(hash)?;
+        if !encoding::is_legacy(&data) {
+            return Ok(hash.to_string());
+        }
+        let value: serde_json::Value = serde_json::from_slice(&data)
+            .map_err(|err| CrabGitError::CorruptObject {
+                hash: hash.to_string(),
+                reason: err.to_string(),
+            })?;
+
+        let new = if value.get("content").is_some() {
+            let blob = self.object_store.load_blob(hash)?;
+            self.object_store.store_blob(&blob.content)?
+        } else if value.get("entries").is_some() {
+            self.convert_tree(hash)?
+        } else if value.get("target").is_some() {
+            self.convert_tag(hash)?
+        } else if value.get("tree").is_some() {
+            self.convert_commits(hash)?
+        } else {
+            return Err(CrabGitError::CorruptObject {
+                hash: hash.to_string(),
+                reason: "unrecognised legacy object".to_string(),
+            });
+        };
+
+        self.converted.insert(hash.to_string(), new.clone());
+        Ok(new)
+    }
+
+    /// Rebuilds a tree from its files, which also turns trees written before
+    /// nested directories (one flat tree keyed by full path) into nested ones.
+    fn convert_tree(&mut self, hash: &str) -> Result<String> {
+        let mut files = BTreeMap::new();
+        self.legacy_files(hash, "", &mut files)?;
+
+        let mut entries = HashMap::new();
+        for (path, entry) in files {
+            let hash = self.convert(&entry.hash)?;
+            entries.insert(
+                path.clone(),
+                IndexEntry {
+                    hash,
+                    mode: entry.mode,
+                    path,
+                },
+            );
+        }
+        self.object_store.write_tree(&entries)
+    }
+
+    fn legacy_files(&self, hash: &str, prefix: &str, files: &mut BTreeMap<String, TreeEntry>) -> Result<()> {
+        let tree = self.object_store.load_tree(hash)?;
+        for (name, entry) in tree.entries {
+            let path = format!("{}{}", prefix, name);
+            if entry.is_file {
+                files.insert(path, entry);
+            } else {
+                self.legacy_files(&entry.hash, &format!("{}/", path), files)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn convert_tag(&mut self, hash: &str) -> Result<String> {
+        let tag = self.object_store.load_tag(hash)?;
+        let target = self.convert(&tag.target)?;
+        self.object_store.store_tag(&Tag { target, ..tag })
+    }
+
+    /// Converts a commit and its history, parents first, without recursing
+    /// once per ancestor.
+    fn convert_commits(&mut self, hash: &str) -> Result<String> {
+        let mut stack = vec![hash.to_string()];
+        while let Some(old) = stack.last().cloned() {
+            if self.converted.contains_key(&old) {
+                stack.pop();
+                continue;
+            }
+
+            let legacy: LegacyCommit = serde_json::from_slice(&self.object_store.load_object(&old)?)
+                .map_err(|err| CrabGitError::CorruptObject {
+                    hash: old.clone(),
+                    reason: err.to_string(),
+                })?;
+            let parents: Vec<String> = legacy.parent.into_iter().chain(legacy.parents).collect();
+            let pending: Vec<String> = parents
+                .iter()
+                .filter(|parent| !self.converted.contains_key(*parent))
+                .cloned()
+                .collect();
+            if !pending.is_empty() {
+                stack.extend(pending);
+                continue;
+            }
+
+            let commit = Commit {
+                hash: String::new(),
+                parents: parents.iter().map(|parent| self.converted[parent].clone()).collect(),
+                tree: self.convert(&legacy.tree)?,
+                author: legacy.author,
+                message: legacy.message,
+                timestamp: legacy.timestamp,
+            };
+            let new = self.object_store.store_commit(&commit)?;
+            self.converted.insert(old, new);
+            stack.pop();
+        }
+        Ok(self.converted[hash].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::ObjectKind;
+    use crate::object_store::HashAlgorithm;
+    use crate::testing::TestRepo;
+    use serde_json::json;
+
+    /// Stores `value` as a legacy JSON object, returning its hash.
+    fn legacy(object_store: &ObjectStore, value: serde_json::Value) -> String {
+        let content = serde_json::to_vec(&value).unwrap();
+        let hash = HashAlgorithm::Sha256.hash(&content);
+        object_store.store_object(&hash, &content).unwrap();
+        hash
+    }
+
+    fn legacy_file(path: &str, hash: &str) -> serde_json::Value {
+        json!({ "mode": "100644", "hash": hash, "name": path, "is_file": true })
+    }
+
+    fn write_ref(test: &TestRepo, name: &str, hash: &str) {
+        let path = test.repo.git_dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, hash).unwrap();
+    }
+
+    fn read_ref(test: &TestRepo, name: &str) -> String {
+        fs::read_to_string(test.repo.git_dir.join(name)).unwrap().trim_end().to_string()
+    }
+
+    /// A repository as written before the binary encoding: a flat tree keyed
+    /// by full path, single-parent commits, a nested branch, an annotated tag
+    /// and an object nothing refers to.
+    fn legacy_repo() -> TestRepo {
+        let test = TestRepo::new();
+        let mut config = Config::for_repo(&test.repo).unwrap();
+        config.unset("core.objectversion");
+        config.save().unwrap();
+
+        let object_store = ObjectStore::new(&test.repo);
+        let readme = legacy(&object_store, json!({ "hash": "", "content": b"readme\n".to_vec() }));
+        let lib = legacy(&object_store, json!({ "hash": "", "content": b"lib\n".to_vec() }));
+        legacy(&object_store, json!({ "hash": "", "content": b"unreachable\n".to_vec() }));
+
+        let first_tree = legacy(
+            &object_store,
+            json!({ "hash": "", "entries": { "README.md": legacy_file("README.md", &readme) } }),
+        );
+        let second_tree = legacy(
+            &object_store,
+            json!({ "hash": "", "entries": {
+                "README.md": legacy_file("README.md", &readme),
+                "src/lib.rs": legacy_file("src/lib.rs", &lib),
+            } }),
+        );
+        let commit = |tree: &str, parent: Option<&str>, message: &str| {
+            legacy(
+                &object_store,
+                json!({
+                    "hash": "", "parent": parent, "tree": tree, "author": "Crab <crab@example.com>",
+                    "message": message, "timestamp": "2024-01-01T00:00:00Z",
+                }),
+            )
+        };
+        let first = commit(&first_tree, None, "first");
+        let second = commit(&second_tree, Some(&first), "second");
+        let tag = legacy(
+            &object_store,
+            json!({
+                "hash": "", "name": "v1", "target": first, "target_type": "commit",
+                "tagger": "Crab <crab@example.com>", "message": "v1", "timestamp": "2024-01-01T00:00:00Z",
+            }),
+        );
+
+        write_ref(&test, "refs/heads/main", &second);
+        write_ref(&test, "refs/heads/feature/x", &first);
+        write_ref(&test, "refs/tags/v1", &tag);
+        test
+    }
+
+    #[test]
+    fn migrates_every_ref() {
+        let test = legacy_repo();
+        migrate(&test.repo).unwrap();
+
+        let object_store = ObjectStore::new(&test.repo);
+        for hash in object_store.loose_objects().unwrap() {
+            assert!(!encoding::is_legacy(&object_store.load_object(&hash).unwrap()));
+        }
+        assert_eq!(utils::object_version(&test.repo, &Config::for_repo(&test.repo).unwrap()), OBJECT_VERSION);
+
+        let second = object_store.load_commit(&read_ref(&test, "refs/heads/main")).unwrap();
+        assert_eq!(second.message, "second");
+        let files = object_store.read_tree(&second.tree).unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["README.md", "src/lib.rs"]);
+        assert_eq!(object_store.load_blob(&files["src/lib.rs"].hash).unwrap().content, b"lib\n");
+
+        let first = read_ref(&test, "refs/heads/feature/x");
+        assert_eq!(second.parents, vec![first.clone()]);
+        let tag = read_ref(&test, "refs/tags/v1");
+        assert_eq!(object_store.object_kind(&tag).unwrap(), ObjectKind::Tag);
+        assert_eq!(object_store.load_tag(&tag).unwrap().target, first);
+
+        // Two blobs, three trees (with `src`), two commits and a tag; the
+        // unreachable blob is gone.
+        assert_eq!(object_store.loose_objects().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn interrupted_migration_can_be_resumed() {
+        let test = legacy_repo();
+        migrate(&test.repo).unwrap();
+        let main = read_ref(&test, "refs/heads/main");
+
+        // As if the run stopped before recording the new version.
+        let mut config = Config::for_repo(&test.repo).unwrap();
+        config.unset("core.objectversion");
+        config.save().unwrap();
+        let object_store = ObjectStore::new(&test.repo);
+        legacy(&object_store, json!({ "hash": "", "content": b"left over\n".to_vec() }));
+
+        migrate(&test.repo).unwrap();
+        assert_eq!(read_ref(&test, "refs/heads/main"), main);
+        assert_eq!(object_store.loose_objects().unwrap().len(), 8);
+        assert_eq!(utils::object_version(&test.repo, &Config::for_repo(&test.repo).unwrap()), OBJECT_VERSION);
+    }
+}
@@ -6,6 +6,16 @@ pub mod log;
 pub mod branch;
 pub mod checkout;
 pub mod diff;
+pub mod merge;
+pub mod tag;
+pub mod check_ignore;
+pub mod gc;
+pub mod migrate;
+pub mod rm;
+pub mod mv;
+pub mod reset;
+pub mod rev_parse;
+pub mod reflog;
 
 pub use init::*;
 pub use add::*;
@@ -14,4 +24,14 @@ pub use commit::*;
 pub use log::*;
 pub use branch::*;
 pub use checkout::*;
-pub use diff::*;
\ No newline at end of file
+pub use diff::*;
+pub use merge::*;
+pub use tag::*;
+pub use check_ignore::*;
+pub use gc::*;
+pub use migrate::*;
+pub use rm::*;
+pub use mv::*;
+pub use reset::*;
+pub use rev_parse::*;
+pub use reflog::*;
\ No newline at end of file
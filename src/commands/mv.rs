@@ -0,0 +1,131 @@
+use crate::{CrabGitError, IndexEntry, Repository, Result, utils};
+use std::fs;
+
+/// Renames a tracked file or directory in the work tree and the index. Moving
+/// onto an existing directory moves the source into it; an existing file is
+/// only replaced with `force`.
+pub fn mv(repo: &Repository, source: String, destination: String, force: bool) -> Result<()> {
+    // Held until the index is written, so the rename cannot be left without
+    // its index update.
+    let lock = utils::lock_index(repo)?;
+    let mut index = utils::load_index(repo)?;
+
+    let src = utils::pathspec(repo, &source)?;
+    let src_path = repo.work_dir.join(&src);
+    if src.is_empty() || !src_path.exists() {
+        return Err(CrabGitError::InvalidOperation(format!(
+            "cannot move '{}': no such file or directory",
+            source
+        )));
+    }
+
+    let tracked: Vec<String> = index
+        .entries
+        .keys()
+        .filter(|path| utils::is_path_or_child(path, &src))
+        .cloned()
+        .collect();
+    if tracked.is_empty() {
+        return Err(CrabGitError::InvalidOperation(format!(
+            "cannot move '{}': not under version control",
+            source
+        )));
+    }
+
+    let mut dst = utils::pathspec(repo, &destination)?;
+    if repo.work_dir.join(&dst).is_dir() {
+        let name = src.rsplit('/').next().unwrap_or(&src);
+        dst = if dst.is_empty() { name.to_string() } else { format!("{}/{}", dst, name) };
+    }
+    let dst_path = repo.work_dir.join(&dst);
+
+    if utils::is_path_or_child(&dst, &src) {
+        return Err(CrabGitError::InvalidOperation(format!(
+            "cannot move '{}' into itself",
+            source
+        )));
+    }
+    if dst_path.exists() && (!force || dst_path.is_dir() || src_path.is_dir()) {
+        return Err(CrabGitError::AlreadyExists(format!("Destination '{}'", dst)));
+    }
+    if !dst_path.parent().is_some_and(|parent| parent.is_dir()) {
+        return Err(CrabGitError::InvalidOperation(format!(
+            "cannot move '{}': destination directory does not exist",
+            source
+        )));
+    }
+
+    fs::rename(&src_path, &dst_path)?;
+
+    for old_path in tracked {
+        let new_path = format!("{}{}", dst, &old_path[src.len()..]);
+        let entry = index.entries.remove(&old_path).unwrap();
+        if index.unmerged.remove(&old_path) {
+            index.unmerged.insert(new_path.clone());
+        }
+        index.entries.insert(
+            new_path.clone(),
+            IndexEntry { path: new_path, ..entry },
+        );
+    }
+    utils::write_index(repo, lock, &index)?;
+
+    println!("Renamed {} -> {}", src, dst);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    fn tracked(test: &TestRepo) -> Vec<String> {
+        let mut paths: Vec<String> = utils::load_index(&test.repo).unwrap().entries.into_keys().collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_current_directory() {
+        let test = TestRepo::new();
+        test.write("docs/guide.md", "guide\n");
+        test.write("src/lib.rs", "lib\n");
+        test.commit("initial");
+        test.cd("src");
+
+        mv(&test.repo, "lib.rs".to_string(), "main.rs".to_string(), false).unwrap();
+        assert_eq!(test.read("src/main.rs"), "lib\n");
+        mv(&test.repo, "../docs/guide.md".to_string(), ".".to_string(), false).unwrap();
+        assert!(!test.path("docs/guide.md").exists());
+        assert_eq!(tracked(&test), ["src/guide.md", "src/main.rs"]);
+
+        assert!(mv(&test.repo, "main.rs".to_string(), "../../main.rs".to_string(), false).is_err());
+    }
+
+    #[test]
+    fn directories_move_with_their_entries() {
+        let test = TestRepo::new();
+        test.write("a/one.txt", "1\n");
+        test.write("a/b/two.txt", "2\n");
+        test.write("c/keep.txt", "c\n");
+        test.commit("initial");
+
+        mv(&test.repo, "a".to_string(), "c".to_string(), false).unwrap();
+        assert_eq!(tracked(&test), ["c/a/b/two.txt", "c/a/one.txt", "c/keep.txt"]);
+        assert!(mv(&test.repo, "c".to_string(), "c/a/inner".to_string(), false).is_err());
+    }
+
+    #[test]
+    fn existing_files_are_only_replaced_with_force() {
+        let test = TestRepo::new();
+        test.write("old.txt", "old\n");
+        test.write("new.txt", "new\n");
+        test.commit("initial");
+
+        assert!(mv(&test.repo, "old.txt".to_string(), "new.txt".to_string(), false).is_err());
+        assert!(mv(&test.repo, "untracked.txt".to_string(), "x.txt".to_string(), false).is_err());
+        mv(&test.repo, "old.txt".to_string(), "new.txt".to_string(), true).unwrap();
+        assert_eq!(test.read("new.txt"), "old\n");
+        assert_eq!(tracked(&test), ["new.txt"]);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{CrabGitError, Repository, Result, object_store::ObjectStore, rev_parse, utils};
+use chrono::Utc;
+
+/// How long `reflog expire` keeps entries when no `--expire` is given.
+pub const DEFAULT_REFLOG_EXPIRY: &str = "90.days.ago";
+
+/// Shows the reflog of `reference` (HEAD when omitted), newest entry first,
+/// as `<hash> <ref>@{n}: <reason>`.
+pub fn reflog(repo: &Repository, reference: Option<String>, max_count: Option<usize>) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let reference = reference.unwrap_or_else(|| "HEAD".to_string());
+    let ref_name = log_ref_name(repo, &object_store, &reference)?;
+
+    let entries = crate::reflog::read(repo, &ref_name)?;
+    for (n, entry) in entries.iter().rev().enumerate().take(max_count.unwrap_or(usize::MAX)) {
+        println!("{} {}@{{{}}}: {}", &entry.new[..7], reference, n, entry.message);
+    }
+    Ok(())
+}
+
+/// Drops reflog entries older than `expire` (see [`utils::parse_time`]) from
+/// the given refs, or from every reflog with `all`.
+pub fn reflog_expire(repo: &Repository, expire: Option<String>, all: bool, refs: Vec<String>) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let expire = expire.unwrap_or_else(|| DEFAULT_REFLOG_EXPIRY.to_string());
+    let cutoff = utils::parse_time(&expire, Utc::now())
+        .ok_or_else(|| CrabGitError::InvalidOperation(format!("invalid expiry time '{}'", expire)))?;
+
+    let ref_names = if all {
+        crate::reflog::logged_refs(repo)?
+    } else if refs.is_empty() {
+        return Err(CrabGitError::InvalidOperation(
+            "Specify the refs to expire, or --all".to_string(),
+        ));
+    } else {
+        refs.iter()
+            .map(|reference| log_ref_name(repo, &object_store, reference))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut expired = 0;
+    for ref_name in ref_names {
+        let entries = crate::reflog::read(repo, &ref_name)?;
+        let kept: Vec<_> = entries.iter().filter(|entry| entry.timestamp >= cutoff).cloned().collect();
+        if kept.len() != entries.len() {
+            expired += entries.len() - kept.len();
+            crate::reflog::write(repo, &ref_name, &kept)?;
+        }
+    }
+
+    println!("Expired {} reflog entries", expired);
+    Ok(())
+}
+
+/// The ref whose log `reference` means: `HEAD` itself, or the full name of a
+/// branch or other ref.
+fn log_ref_name(repo: &Repository, object_store: &ObjectStore, reference: &str) -> Result<String> {
+    if reference == "HEAD" {
+        return Ok("HEAD".to_string());
+    }
+    rev_parse::full_ref_name(repo, object_store, reference)?
+        .ok_or_else(|| CrabGitError::RefNotFound(reference.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflog::{self as log, ReflogEntry};
+    use crate::testing::TestRepo;
+
+    #[test]
+    fn expire_drops_old_entries() {
+        let test = TestRepo::new();
+        test.write("file.txt", "one\n");
+        test.commit("first");
+        test.write("file.txt", "two\n");
+        test.commit("second");
+
+        let mut entries = log::read(&test.repo, "HEAD").unwrap();
+        entries[0].timestamp = Utc::now() - chrono::Duration::days(100);
+        log::write(&test.repo, "HEAD", &entries).unwrap();
+
+        reflog_expire(&test.repo, None, false, vec!["HEAD".to_string()]).unwrap();
+        let kept: Vec<ReflogEntry> = log::read(&test.repo, "HEAD").unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].message, "commit: second");
+        assert_eq!(log::read(&test.repo, "refs/heads/main").unwrap().len(), 2);
+
+        reflog_expire(&test.repo, Some("now".to_string()), true, vec![]).unwrap();
+        assert!(log::read(&test.repo, "refs/heads/main").unwrap().is_empty());
+        assert!(reflog_expire(&test.repo, None, false, vec![]).is_err());
+        assert!(reflog_expire(&test.repo, Some("whenever".to_string()), true, vec![]).is_err());
+    }
+}
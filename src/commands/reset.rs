@@ -0,0 +1,271 @@
+use crate::{CrabGitError, IndexEntry, Repository, Result, TreeEntry, object_store::ObjectStore, rev_parse, utils};
+use crate::commands::checkout;
+use crate::refs::{Expected, RefTransaction};
+use std::collections::BTreeMap;
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResetMode {
+    /// Move the branch only.
+    Soft,
+    /// Move the branch and reset the index.
+    #[default]
+    Mixed,
+    /// Move the branch and reset the index and the working tree.
+    Hard,
+}
+
+/// Points the current branch (or a detached HEAD) at `commit`, which defaults
+/// to HEAD, and resets the index and working tree according to `mode`.
+///
+/// With `paths`, only the index entries under those paths are reset to the
+/// commit's and HEAD does not move; this is how changes are unstaged.
+pub fn reset(repo: &Repository, mode: ResetMode, commit: Option<String>, paths: Vec<String>) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let head = utils::get_head_commit(repo)?;
+
+    // Like git, `reset <path>` works without `--` when the name is not a commit.
+    let (commit, paths) = match commit {
+        Some(name)
+            if paths.is_empty()
+                && rev_parse::resolve_commit(repo, &object_store, &name).is_err()
+                && is_known_path(repo, &name)? =>
+        {
+            (None, vec![name])
+        }
+        commit => (commit, paths),
+    };
+    if !paths.is_empty() {
+        if mode != ResetMode::Mixed {
+            return Err(CrabGitError::InvalidOperation(format!(
+                "Cannot do a {} reset with paths",
+                if mode == ResetMode::Soft { "soft" } else { "hard" }
+            )));
+        }
+        // Before the first commit, unstaging compares against an empty tree.
+        let target_files = match commit.as_deref().map(str::to_string).or(head) {
+            Some(name) => {
+                let target = rev_parse::resolve_commit(repo, &object_store, &name)?;
+                object_store.read_tree(&object_store.load_commit(&target)?.tree)?
+            }
+            None => BTreeMap::new(),
+        };
+        reset_paths(repo, &target_files, &paths)?;
+        return print_unstaged(repo, &object_store);
+    }
+
+    let target = match &commit {
+        Some(name) => rev_parse::resolve_commit(repo, &object_store, name)?,
+        None => head.clone().ok_or_else(|| {
+            CrabGitError::InvalidOperation("No commits yet, nothing to reset".to_string())
+        })?,
+    };
+
+    let merge_head_path = repo.git_dir.join("MERGE_HEAD");
+    if mode == ResetMode::Soft && merge_head_path.exists() {
+        return Err(CrabGitError::InvalidOperation(
+            "Cannot do a soft reset in the middle of a merge".to_string(),
+        ));
+    }
+
+    // HEAD moves first, and only if nothing else moved it since it was read:
+    // if updating the index or the work tree fails after this, the reset can
+    // simply be repeated.
+    let reason = format!("reset: moving to {}", commit.as_deref().unwrap_or("HEAD"));
+    let mut transaction = RefTransaction::new(repo);
+    transaction.update_head(&target, Expected::from(head.clone()), &reason)?;
+    if mode != ResetMode::Soft && merge_head_path.exists() {
+        transaction.delete("MERGE_HEAD", Expected::Any);
+    }
+    transaction.commit()?;
+
+    match mode {
+        ResetMode::Soft => {}
+        ResetMode::Mixed => {
+            let target_files = object_store.read_tree(&object_store.load_commit(&target)?.tree)?;
+            utils::save_index(repo, &utils::tree_to_index(&target_files))?;
+        }
+        ResetMode::Hard => {
+            // Files only staged (tracked, but in neither commit) are discarded too.
+            let staged = utils::load_index(repo)?;
+            checkout::update_working_tree(repo, &object_store, head.as_deref(), &target, true)?;
+            let index = utils::load_index(repo)?;
+            for path in staged.entries.keys().filter(|path| !index.entries.contains_key(*path)) {
+                let file_path = repo.work_dir.join(path);
+                if file_path.is_file() {
+                    fs::remove_file(&file_path)?;
+                }
+                utils::remove_empty_parents(&repo.work_dir, &file_path)?;
+            }
+        }
+    }
+
+    let merge_msg_path = repo.git_dir.join("MERGE_MSG");
+    if mode != ResetMode::Soft && merge_msg_path.exists() {
+        fs::remove_file(merge_msg_path)?;
+    }
+
+    match mode {
+        ResetMode::Soft => Ok(()),
+        ResetMode::Mixed => print_unstaged(repo, &object_store),
+        ResetMode::Hard => {
+            let commit = object_store.load_commit(&target)?;
+            let summary = commit.message.lines().next().unwrap_or("");
+            println!("HEAD is now at {} {}", &target[..8], summary);
+            Ok(())
+        }
+    }
+}
+
+/// Sets the index entries under `paths` to those of `target_files`, dropping
+/// entries the tree does not have.
+fn reset_paths(repo: &Repository, target_files: &BTreeMap<String, TreeEntry>, paths: &[String]) -> Result<()> {
+    let mut index = utils::load_index(repo)?;
+
+    for path_str in paths {
+        let path = utils::pathspec(repo, path_str)?;
+        let matches = |tracked: &String| utils::is_path_or_child(tracked, &path);
+
+        index.entries.retain(|tracked, _| !matches(tracked));
+        index.unmerged.retain(|tracked| !matches(tracked));
+        for (tracked, entry) in target_files.iter().filter(|(tracked, _)| matches(tracked)) {
+            index.entries.insert(
+                tracked.clone(),
+                IndexEntry { hash: entry.hash.clone(), mode: entry.mode.clone(), path: tracked.clone() },
+            );
+        }
+    }
+
+    utils::save_index(repo, &index)
+}
+
+/// Whether `name` is a path in the work tree or the index.
+fn is_known_path(repo: &Repository, name: &str) -> Result<bool> {
+    let path = utils::pathspec(repo, name)?;
+    let index = utils::load_index(repo)?;
+    Ok(repo.work_dir.join(&path).exists()
+        || index.entries.keys().any(|tracked| utils::is_path_or_child(tracked, &path)))
+}
+
+fn print_unstaged(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let index = utils::load_index(repo)?;
+    let mut unstaged = Vec::new();
+    for (path, entry) in &index.entries {
+        let file_path = repo.work_dir.join(path);
+        if !file_path.is_file() {
+            unstaged.push(("D", path));
+        } else if object_store.hash_blob(&fs::read(&file_path)?) != entry.hash {
+            unstaged.push(("M", path));
+        }
+    }
+
+    if !unstaged.is_empty() {
+        println!("Unstaged changes after reset:");
+        for (change, path) in unstaged {
+            println!("{}\t{}", change, path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    fn staged_hash(test: &TestRepo, path: &str) -> Option<String> {
+        utils::load_index(&test.repo).unwrap().entries.get(path).map(|entry| entry.hash.clone())
+    }
+
+    /// Two commits: `first` with `a.txt = one`, then `a.txt = two` and `b.txt`.
+    fn history() -> (TestRepo, String, String) {
+        let test = TestRepo::new();
+        test.write("a.txt", "one\n");
+        test.commit("first");
+        let first = test.head();
+        test.write("a.txt", "two\n");
+        test.write("b.txt", "b\n");
+        test.commit("second");
+        let second = test.head();
+        (test, first, second)
+    }
+
+    #[test]
+    fn soft_only_moves_the_branch() {
+        let (test, first, second) = history();
+        let staged = staged_hash(&test, "a.txt");
+
+        reset(&test.repo, ResetMode::Soft, Some(first.clone()), vec![]).unwrap();
+        assert_eq!(test.head(), first);
+        assert_eq!(staged_hash(&test, "a.txt"), staged);
+        assert_eq!(test.read("a.txt"), "two\n");
+
+        reset(&test.repo, ResetMode::Soft, Some(second.clone()), vec![]).unwrap();
+        assert_eq!(test.head(), second);
+    }
+
+    #[test]
+    fn mixed_resets_the_index_but_keeps_files() {
+        let (test, first, _) = history();
+        let second_a = staged_hash(&test, "a.txt");
+
+        reset(&test.repo, ResetMode::Mixed, Some(first.clone()), vec![]).unwrap();
+        assert_eq!(test.head(), first);
+        assert_ne!(staged_hash(&test, "a.txt"), second_a);
+        assert_eq!(staged_hash(&test, "b.txt"), None);
+        assert_eq!(test.read("a.txt"), "two\n");
+        assert_eq!(test.read("b.txt"), "b\n");
+    }
+
+    #[test]
+    fn hard_resets_files_and_drops_staged_ones() {
+        let (test, first, _) = history();
+        test.write("staged.txt", "staged\n");
+        test.write("untracked.txt", "untracked\n");
+        crate::commands::add(&test.repo, vec!["staged.txt".to_string()], false).unwrap();
+
+        reset(&test.repo, ResetMode::Hard, Some(first.clone()), vec![]).unwrap();
+        assert_eq!(test.head(), first);
+        assert_eq!(test.read("a.txt"), "one\n");
+        assert!(!test.path("b.txt").exists());
+        assert!(!test.path("staged.txt").exists());
+        assert_eq!(test.read("untracked.txt"), "untracked\n");
+    }
+
+    #[test]
+    fn paths_unstage_relative_to_the_current_directory() {
+        let test = TestRepo::new();
+        test.write("src/lib.rs", "lib\n");
+        test.write("top.txt", "top\n");
+        test.commit("first");
+        let committed = staged_hash(&test, "src/lib.rs");
+        let top_committed = staged_hash(&test, "top.txt");
+        let head = test.head();
+
+        test.write("src/lib.rs", "changed\n");
+        test.write("top.txt", "changed\n");
+        test.add_all();
+        test.cd("src");
+
+        // No `--` needed when the name is not a commit.
+        reset(&test.repo, ResetMode::Mixed, Some("lib.rs".to_string()), vec![]).unwrap();
+        assert_eq!(staged_hash(&test, "src/lib.rs"), committed);
+        assert_ne!(staged_hash(&test, "top.txt"), None);
+        assert_eq!(test.head(), head);
+        assert_eq!(test.read("src/lib.rs"), "changed\n");
+
+        reset(&test.repo, ResetMode::Mixed, None, vec!["../top.txt".to_string()]).unwrap();
+        assert_eq!(staged_hash(&test, "top.txt"), top_committed);
+        assert!(reset(&test.repo, ResetMode::Hard, None, vec!["lib.rs".to_string()]).is_err());
+    }
+
+    #[test]
+    fn detached_head_moves_itself() {
+        let (test, first, second) = history();
+        crate::commands::checkout(&test.repo, second.clone(), false).unwrap();
+
+        reset(&test.repo, ResetMode::Hard, Some(first.clone()), vec![]).unwrap();
+        assert_eq!(fs::read_to_string(test.repo.git_dir.join("HEAD")).unwrap().trim_end(), first);
+        assert_eq!(utils::get_branch_commit(&test.repo, "main").unwrap(), Some(second));
+    }
+}
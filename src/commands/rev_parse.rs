@@ -0,0 +1,64 @@
+use crate::rev_parse::{self as revs, RevRange};
+use crate::{CrabGitError, Repository, Result, object_store::ObjectStore};
+
+/// Prints the object each revision names, one per line. A range prints its
+/// included tips followed by its excluded commits prefixed with `^`.
+///
+/// `short` abbreviates hashes to at least that many characters, `verify`
+/// requires exactly one revision that is not a range, and `abbrev_ref` prints
+/// the short ref name (`main`, `v1.0`, `origin/main`) instead of a hash.
+pub fn rev_parse(
+    repo: &Repository,
+    revisions: Vec<String>,
+    short: Option<usize>,
+    verify: bool,
+    abbrev_ref: bool,
+) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+
+    if verify && (revisions.len() != 1 || revs::is_range(repo, &object_store, &revisions[0])) {
+        return Err(CrabGitError::InvalidOperation("Needed a single revision".to_string()));
+    }
+
+    let format = |hash: &str| -> Result<String> {
+        match short {
+            Some(len) => revs::abbreviate(&object_store, hash, len),
+            None => Ok(hash.to_string()),
+        }
+    };
+
+    let mut lines = Vec::new();
+    for spec in &revisions {
+        if abbrev_ref {
+            let ref_name = revs::full_ref_name(repo, &object_store, spec)?
+                .ok_or_else(|| CrabGitError::RefNotFound(spec.clone()))?;
+            lines.push(revs::short_ref_name(&ref_name).to_string());
+        } else if revs::is_range(repo, &object_store, spec) {
+            match revs::resolve_range(repo, &object_store, spec)? {
+                RevRange::Single(commit) => lines.push(format(&commit)?),
+                RevRange::Range { exclude, include } => {
+                    lines.push(format(&include)?);
+                    lines.push(format!("^{}", format(&exclude)?));
+                }
+                range @ RevRange::Symmetric { .. } => {
+                    let (tips, excluded) = range.tips(&object_store)?;
+                    for tip in tips.iter().rev() {
+                        lines.push(format(tip)?);
+                    }
+                    for commit in excluded {
+                        lines.push(format!("^{}", format(&commit)?));
+                    }
+                }
+            }
+        } else if let Some(name) = spec.strip_prefix('^').filter(|name| !name.is_empty()) {
+            lines.push(format!("^{}", format(&revs::resolve_commit(repo, &object_store, name)?)?));
+        } else {
+            lines.push(format(&revs::resolve(repo, &object_store, spec)?)?);
+        }
+    }
+
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use crate::{CrabGitError, Repository, Result, object_store::ObjectStore, utils};
+use std::collections::BTreeSet;
+use std::fs;
+
+/// Removes tracked paths from the index and, unless `cached`, from the work
+/// tree. Every path is checked before anything is removed: without `force`,
+/// files whose content would be lost (staged changes, or local modifications
+/// when deleting the file) are refused.
+pub fn rm(repo: &Repository, paths: Vec<String>, cached: bool, recursive: bool, force: bool) -> Result<()> {
+    // Held until the index is written, so no other command can change the
+    // index between the files being removed and their entries.
+    let lock = utils::lock_index(repo)?;
+    let mut index = utils::load_index(repo)?;
+    let head = utils::head_index(repo)?;
+    let object_store = ObjectStore::new(repo);
+
+    let mut targets = BTreeSet::new();
+    for path_str in &paths {
+        let path = utils::pathspec(repo, path_str)?;
+        let matched: Vec<&String> = index
+            .entries
+            .keys()
+            .filter(|tracked| utils::is_path_or_child(tracked, &path))
+            .collect();
+
+        if matched.is_empty() {
+            return Err(CrabGitError::InvalidOperation(format!(
+                "pathspec '{}' did not match any files",
+                path_str
+            )));
+        }
+        if !recursive && (matched.len() > 1 || *matched[0] != path) {
+            return Err(CrabGitError::InvalidOperation(format!(
+                "not removing '{}' recursively without -r",
+                path_str
+            )));
+        }
+        targets.extend(matched.into_iter().cloned());
+    }
+
+    if !force {
+        let mut both = Vec::new();
+        let mut staged = Vec::new();
+        let mut modified = Vec::new();
+
+        for path in &targets {
+            let entry = &index.entries[path];
+            let matches_head = head.entries.get(path).is_some_and(|e| e.hash == entry.hash);
+            let file_path = repo.work_dir.join(path);
+            let matches_worktree = !file_path.is_file()
+                || object_store.hash_blob(&fs::read(&file_path)?) == entry.hash;
+
+            match (matches_head, matches_worktree) {
+                (false, false) => both.push(path.clone()),
+                (false, true) if !cached => staged.push(path.clone()),
+                (true, false) if !cached => modified.push(path.clone()),
+                _ => {}
+            }
+        }
+
+        if !both.is_empty() {
+            return Err(CrabGitError::conflict(
+                "The following files have staged content different from both the file and HEAD \
+                 (use -f to force removal):",
+                both,
+            ));
+        }
+        if !staged.is_empty() {
+            return Err(CrabGitError::conflict(
+                "The following files have changes staged in the index \
+                 (use --cached to keep the file, or -f to force removal):",
+                staged,
+            ));
+        }
+        if !modified.is_empty() {
+            return Err(CrabGitError::conflict(
+                "The following files have local modifications \
+                 (use --cached to keep the file, or -f to force removal):",
+                modified,
+            ));
+        }
+    }
+
+    for path in &targets {
+        if !cached {
+            let file_path = repo.work_dir.join(path);
+            if file_path.is_file() {
+                fs::remove_file(&file_path)?;
+            }
+            utils::remove_empty_parents(&repo.work_dir, &file_path)?;
+        }
+        index.entries.remove(path);
+        index.unmerged.remove(path);
+    }
+    utils::write_index(repo, lock, &index)?;
+
+    for path in &targets {
+        println!("rm '{}'", path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    fn tracked(test: &TestRepo) -> Vec<String> {
+        let mut paths: Vec<String> = utils::load_index(&test.repo).unwrap().entries.into_keys().collect();
+        paths.sort();
+        paths
+    }
+
+    fn project() -> TestRepo {
+        let test = TestRepo::new();
+        test.write("README", "readme\n");
+        test.write("src/main.rs", "main\n");
+        test.write("src/util/mod.rs", "util\n");
+        test.commit("initial");
+        test
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_current_directory() {
+        let test = project();
+        test.cd("src");
+
+        rm(&test.repo, vec!["main.rs".to_string()], false, false, false).unwrap();
+        assert!(!test.path("src/main.rs").exists());
+        assert_eq!(tracked(&test), ["README", "src/util/mod.rs"]);
+
+        rm(&test.repo, vec!["../README".to_string()], true, false, false).unwrap();
+        assert!(test.path("README").exists());
+        assert_eq!(tracked(&test), ["src/util/mod.rs"]);
+
+        assert!(rm(&test.repo, vec!["README".to_string()], false, false, false).is_err());
+    }
+
+    #[test]
+    fn directories_need_recursive() {
+        let test = project();
+
+        assert!(rm(&test.repo, vec!["src".to_string()], false, false, false).is_err());
+        rm(&test.repo, vec!["src".to_string()], false, true, false).unwrap();
+        assert!(!test.path("src").exists());
+        assert_eq!(tracked(&test), ["README"]);
+    }
+
+    #[test]
+    fn changes_that_would_be_lost_need_force() {
+        let test = project();
+        test.write("README", "edited\n");
+        assert!(rm(&test.repo, vec!["README".to_string()], false, false, false).is_err());
+        rm(&test.repo, vec!["README".to_string()], true, false, false).unwrap();
+        assert_eq!(test.read("README"), "edited\n");
+
+        test.write("src/main.rs", "staged\n");
+        test.add_all();
+        assert!(rm(&test.repo, vec!["src/main.rs".to_string()], false, false, false).is_err());
+        rm(&test.repo, vec!["src/main.rs".to_string()], false, false, true).unwrap();
+        assert!(!test.path("src/main.rs").exists());
+    }
+
+    #[test]
+    fn a_held_index_lock_leaves_files_alone() {
+        let test = TestRepo::new();
+        test.write("a.txt", "a");
+        test.commit("first");
+        let lock = utils::lock_index(&test.repo).unwrap();
+
+        let result = rm(&test.repo, vec!["a.txt".to_string()], false, false, false);
+        assert!(matches!(result, Err(CrabGitError::IndexLocked(_))));
+        assert!(test.path("a.txt").exists());
+        drop(lock);
+        assert!(utils::save_index(&test.repo, &utils::load_index(&test.repo).unwrap()).is_ok());
+    }
+}
@@ -1,74 +1,841 @@
-use crate::{Repository, Result, utils};
+use crate::{Index, Repository, Result, TreeEntry, config::Config, ignore::IgnoreRules, object_store::ObjectStore, utils};
+use crate::commands::merge;
+use crate::renames::{self, FileVersion, RenameOptions};
+use serde::Serialize;
 use std::fs;
-use std::collections::HashSet;
-use walkdir::WalkDir;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
 
-pub fn status(repo: &Repository) -> Result<()> {
-    let current_branch = utils::get_current_branch(repo)?;
-    println!("# On branch {} #", current_branch);
+/// How a path differs between two of HEAD, the index and the working tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileStatus {
+    Added,
+    Modified,
+    Deleted,
+    /// Staged under a new path, `similarity` percent alike.
+    Renamed { from: String, similarity: u8 },
+}
 
-    let index = utils::load_index(repo)?;
+impl FileStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FileStatus::Added => "new file",
+            FileStatus::Modified => "modified",
+            FileStatus::Deleted => "deleted",
+            FileStatus::Renamed { .. } => "renamed",
+        }
+    }
 
-    let mut working_tree_files = HashSet::new();
-    for entry in WalkDir::new(&repo.work_dir)
-         .into_iter()
-         .filter_map(|e| e.ok())
-         .filter(|e| e.file_type().is_file())
-         {
-            let path = entry.path();
-            if path.starts_with(&repo.work_dir) {
-                continue;
-            }
+    /// The one-letter code used by the short and porcelain formats.
+    pub fn code(&self) -> char {
+        match self {
+            FileStatus::Added => 'A',
+            FileStatus::Modified => 'M',
+            FileStatus::Deleted => 'D',
+            FileStatus::Renamed { .. } => 'R',
+        }
+    }
+}
+
+/// The merge base, our and their version of an unmerged path. Without a
+/// merge in progress only `ours` is known.
+#[derive(Debug, Clone, Default)]
+pub struct Conflict {
+    pub base: Option<TreeEntry>,
+    pub ours: Option<TreeEntry>,
+    pub theirs: Option<TreeEntry>,
+}
 
-            let relative_path = path
-                 .strip_prefix(&repo.work_dir)?
-                 .to_string_lossy()
-                 .replace("\\", "/");
+impl Conflict {
+    /// Git's two-letter code for the conflict, e.g. `UU` or `DU`.
+    pub fn code(&self) -> &'static str {
+        match (self.base.is_some(), self.ours.is_some(), self.theirs.is_some()) {
+            (false, true, true) => "AA",
+            (false, true, false) => "AU",
+            (false, false, true) => "UA",
+            (true, false, true) => "DU",
+            (true, true, false) => "UD",
+            (true, false, false) | (false, false, false) => "DD",
+            (true, true, true) => "UU",
+        }
+    }
 
-            working_tree_files.insert(relative_path);
+    pub fn label(&self) -> &'static str {
+        match self.code() {
+            "AA" => "both added",
+            "AU" => "added by us",
+            "UA" => "added by them",
+            "DU" => "deleted by us",
+            "UD" => "deleted by them",
+            "DD" => "both deleted",
+            _ => "both modified",
         }
+    }
+}
 
-        let mut staged_files = Vec::new();
-        let mut modified_files = Vec::new();
+/// The branch the current branch tracks, from `branch.<name>.remote` and
+/// `branch.<name>.merge`, and how far the two have diverged.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub name: String,
+    /// `None` when the upstream ref does not exist locally.
+    pub ahead_behind: Option<(usize, usize)>,
+}
 
-        for (path, entry) in &index.entries {
-            staged_files.push(path.clone());
+/// Differences between HEAD and the index (`staged`) and between the index
+/// and the working tree (`unstaged`), keyed by path. Renames are keyed by
+/// their new path. Unmerged paths appear in neither map.
+#[derive(Debug, Default)]
+pub struct StatusReport {
+    /// The current branch, `None` when HEAD is detached.
+    pub branch: Option<String>,
+    pub head: Option<String>,
+    pub upstream: Option<Upstream>,
+    pub staged: BTreeMap<String, FileStatus>,
+    pub unstaged: BTreeMap<String, FileStatus>,
+    pub unmerged: BTreeMap<String, Conflict>,
+    pub untracked: BTreeSet<String>,
+    pub head_index: Index,
+    pub index: Index,
+}
 
-            let file_path = repo.work_dir.join(path);
-            if file_path.exists() {
-                let content = fs::read(&file_path)?;
-                let current_hash = crate::object_store::ObjectStore::hash_content(&content);
-                if current_hash != entry.hash {
-                    modified_files.push(path.clone());
+impl StatusReport {
+    pub fn is_clean(&self) -> bool {
+        self.staged.is_empty() && self.unstaged.is_empty() && self.unmerged.is_empty() && self.untracked.is_empty()
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.branch.is_none()
+    }
+
+    /// Untracked files, with directories that contain no tracked file shown
+    /// once as `dir/`.
+    pub fn untracked_collapsed(&self) -> BTreeSet<String> {
+        self.untracked
+            .iter()
+            .map(|path| {
+                let mut end = 0;
+                while let Some(slash) = path[end..].find('/') {
+                    let dir = &path[..end + slash + 1];
+                    if !self.index.entries.keys().any(|tracked| tracked.starts_with(dir)) {
+                        return dir.to_string();
+                    }
+                    end += slash + 1;
                 }
+                path.clone()
+            })
+            .collect()
+    }
+
+    /// One entry per changed path, tracked paths first, each with its staged
+    /// (`x`) and unstaged (`y`) code; `.` means unchanged.
+    pub fn entries(&self) -> Vec<StatusEntry> {
+        let mut entries = Vec::new();
+
+        let paths: BTreeSet<&String> = self
+            .staged
+            .keys()
+            .chain(self.unstaged.keys())
+            .chain(self.unmerged.keys())
+            .collect();
+        for path in paths {
+            if let Some(conflict) = self.unmerged.get(path) {
+                let code = conflict.code();
+                entries.push(StatusEntry {
+                    kind: EntryKind::Unmerged,
+                    path: path.clone(),
+                    orig_path: None,
+                    similarity: None,
+                    x: code.chars().next().unwrap(),
+                    y: code.chars().nth(1).unwrap(),
+                });
+                continue;
             }
-            working_tree_files.remove(path);
+
+            let staged = self.staged.get(path);
+            let (orig_path, similarity) = match staged {
+                Some(FileStatus::Renamed { from, similarity }) => (Some(from.clone()), Some(*similarity)),
+                _ => (None, None),
+            };
+            entries.push(StatusEntry {
+                kind: if orig_path.is_some() { EntryKind::Renamed } else { EntryKind::Changed },
+                path: path.clone(),
+                orig_path,
+                similarity,
+                x: staged.map_or('.', FileStatus::code),
+                y: self.unstaged.get(path).map_or('.', FileStatus::code),
+            });
         }
 
-        if !staged_files.is_empty() {
-            println!("Changes to be committed:");
-            for path in &staged_files {
-                println!("  new file: {}", path);
-            }
+        for path in self.untracked_collapsed() {
+            entries.push(StatusEntry { kind: EntryKind::Untracked, path, orig_path: None, similarity: None, x: '?', y: '?' });
+        }
+        entries
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Changed,
+    Renamed,
+    Unmerged,
+    Untracked,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusEntry {
+    pub kind: EntryKind,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orig_path: Option<String>,
+    /// How alike a renamed file is to `orig_path`, in percent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<u8>,
+    #[serde(rename = "index")]
+    pub x: char,
+    #[serde(rename = "worktree")]
+    pub y: char,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatusFormat {
+    #[default]
+    Long,
+    Short,
+    Porcelain(PorcelainVersion),
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PorcelainVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+impl FromStr for PorcelainVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "v1" | "1" => Ok(PorcelainVersion::V1),
+            "v2" | "2" => Ok(PorcelainVersion::V2),
+            _ => Err(format!("unsupported porcelain version '{}' (expected v1 or v2)", s)),
+        }
+    }
+}
+
+/// Version of the `--json` output. Fields are only ever added within a version.
+pub const STATUS_JSON_VERSION: u32 = 1;
+
+/// Shows the status in `format`. `show_branch` adds the branch header to the
+/// short and porcelain formats, which the long and JSON formats always have.
+pub fn status(repo: &Repository, format: StatusFormat, show_branch: bool) -> Result<()> {
+    let report = collect_status(repo)?;
+
+    match format {
+        StatusFormat::Long => print_long(&report),
+        StatusFormat::Short | StatusFormat::Porcelain(PorcelainVersion::V1) => print_short(&report, show_branch),
+        StatusFormat::Porcelain(PorcelainVersion::V2) => print_porcelain_v2(repo, &report, show_branch),
+        StatusFormat::Json => print_json(&report)?,
+    }
+
+    Ok(())
+}
+
+fn print_long(report: &StatusReport) {
+    match &report.head {
+        Some(head) if report.is_detached() => println!("# HEAD detached at {} #", &head[..8]),
+        _ => println!("# On branch {} #", report.branch.as_deref().unwrap_or("HEAD")),
+    }
+
+    if let Some(upstream) = &report.upstream {
+        match upstream.ahead_behind {
+            None => println!("Your branch is based on '{}', but the upstream is gone.", upstream.name),
+            Some((0, 0)) => println!("Your branch is up to date with '{}'.", upstream.name),
+            Some((ahead, 0)) => println!(
+                "Your branch is ahead of '{}' by {} commit{}.",
+                upstream.name, ahead, if ahead == 1 { "" } else { "s" }
+            ),
+            Some((0, behind)) => println!(
+                "Your branch is behind '{}' by {} commit{}, and can be fast-forwarded.",
+                upstream.name, behind, if behind == 1 { "" } else { "s" }
+            ),
+            Some((ahead, behind)) => println!(
+                "Your branch and '{}' have diverged, and have {} and {} different commits each, respectively.",
+                upstream.name, ahead, behind
+            ),
         }
+    }
 
-        if !modified_files.is_empty() {
-            println!("Changes not staged for commit:");
-            for path in &modified_files {
-                println!("  modified: {}", path);
+    if !report.unmerged.is_empty() {
+        println!("Unmerged paths:");
+        for (path, conflict) in &report.unmerged {
+            println!("  {}: {}", conflict.label(), path);
+        }
+    }
+
+    if !report.staged.is_empty() {
+        println!("Changes to be committed:");
+        for (path, change) in &report.staged {
+            match change {
+                FileStatus::Renamed { from, .. } => println!("  {}: {} -> {}", change.label(), from, path),
+                _ => println!("  {}: {}", change.label(), path),
             }
         }
+    }
 
-        if !working_tree_files.is_empty() {
-            println!("Untracked files:");
-            for path in &working_tree_files {
-                println!("  {}", path);
+    if !report.unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        for (path, change) in &report.unstaged {
+            println!("  {}: {}", change.label(), path);
+        }
+    }
+
+    let untracked = report.untracked_collapsed();
+    if !untracked.is_empty() {
+        println!("Untracked files:");
+        for path in &untracked {
+            println!("  {}", path);
+        }
+    }
+
+    if report.is_clean() {
+        println!("Nothing to commit, working tree clean");
+    }
+}
+
+/// `XY path` lines as printed by `git status --short` and `--porcelain=v1`.
+fn print_short(report: &StatusReport, show_branch: bool) {
+    if show_branch {
+        println!("{}", short_header(report));
+    }
+
+    for entry in report.entries() {
+        let x = if entry.x == '.' { ' ' } else { entry.x };
+        let y = if entry.y == '.' { ' ' } else { entry.y };
+        match &entry.orig_path {
+            Some(orig_path) => println!("{}{} {} -> {}", x, y, orig_path, entry.path),
+            None => println!("{}{} {}", x, y, entry.path),
+        }
+    }
+}
+
+/// The `## branch...upstream [ahead N, behind M]` line of the short format.
+fn short_header(report: &StatusReport) -> String {
+    let mut header = match (&report.branch, &report.head) {
+        (None, _) => "## HEAD (no branch)".to_string(),
+        (Some(branch), None) => format!("## No commits yet on {}", branch),
+        (Some(branch), Some(_)) => format!("## {}", branch),
+    };
+    if let Some(upstream) = &report.upstream {
+        header.push_str(&format!("...{}", upstream.name));
+        match upstream.ahead_behind {
+            None => header.push_str(" [gone]"),
+            Some((0, 0)) => {}
+            Some((ahead, 0)) => header.push_str(&format!(" [ahead {}]", ahead)),
+            Some((0, behind)) => header.push_str(&format!(" [behind {}]", behind)),
+            Some((ahead, behind)) => header.push_str(&format!(" [ahead {}, behind {}]", ahead, behind)),
+        }
+    }
+    header
+}
+
+/// The `git status --porcelain=v2` format, including modes and object names.
+fn print_porcelain_v2(repo: &Repository, report: &StatusReport, show_branch: bool) {
+    let null_hash = "0".repeat(repo.hash_algorithm.raw_len() * 2);
+    let null_mode = "000000".to_string();
+
+    if show_branch {
+        println!("# branch.oid {}", report.head.as_deref().unwrap_or("(initial)"));
+        println!("# branch.head {}", report.branch.as_deref().unwrap_or("(detached)"));
+        if let Some(upstream) = &report.upstream {
+            println!("# branch.upstream {}", upstream.name);
+            if let Some((ahead, behind)) = upstream.ahead_behind {
+                println!("# branch.ab +{} -{}", ahead, behind);
             }
         }
+    }
+
+    let mode = |mode: Option<&String>| mode.map_or(null_mode.clone(), |mode| format!("{:0>6}", mode));
+    let hash = |hash: Option<&String>| hash.cloned().unwrap_or_else(|| null_hash.clone());
 
-        if staged_files.is_empty() && modified_files.is_empty() && working_tree_files.is_empty() {
-            println!("Nothing to commit, working tree clean");
+    for entry in report.entries() {
+        let path = &entry.path;
+        match entry.kind {
+            EntryKind::Untracked => println!("? {}", path),
+            EntryKind::Unmerged => {
+                let conflict = &report.unmerged[path];
+                let stages = [&conflict.base, &conflict.ours, &conflict.theirs];
+                let worktree = repo.work_dir.join(path).is_file().then(|| report.index.entries.get(path)).flatten();
+                println!(
+                    "u {}{} N... {} {} {} {} {} {} {} {}",
+                    entry.x,
+                    entry.y,
+                    mode(stages[0].as_ref().map(|e| &e.mode)),
+                    mode(stages[1].as_ref().map(|e| &e.mode)),
+                    mode(stages[2].as_ref().map(|e| &e.mode)),
+                    mode(worktree.map(|e| &e.mode)),
+                    hash(stages[0].as_ref().map(|e| &e.hash)),
+                    hash(stages[1].as_ref().map(|e| &e.hash)),
+                    hash(stages[2].as_ref().map(|e| &e.hash)),
+                    path
+                );
+            }
+            EntryKind::Changed | EntryKind::Renamed => {
+                let head_path = entry.orig_path.as_ref().unwrap_or(path);
+                let head = report.head_index.entries.get(head_path);
+                let index = report.index.entries.get(path);
+                let worktree = if repo.work_dir.join(path).is_file() { index } else { None };
+                let fields = format!(
+                    "{}{} N... {} {} {} {} {}",
+                    entry.x,
+                    entry.y,
+                    mode(head.map(|e| &e.mode)),
+                    mode(index.map(|e| &e.mode)),
+                    mode(worktree.map(|e| &e.mode)),
+                    hash(head.map(|e| &e.hash)),
+                    hash(index.map(|e| &e.hash)),
+                );
+                match (&entry.orig_path, entry.similarity) {
+                    (Some(orig_path), Some(similarity)) => {
+                        println!("2 {} R{} {}\t{}", fields, similarity, path, orig_path)
+                    }
+                    _ => println!("1 {} {}", fields, path),
+                }
+            }
         }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonStatus<'a> {
+    version: u32,
+    branch: JsonBranch<'a>,
+    entries: Vec<StatusEntry>,
+}
 
+#[derive(Serialize)]
+struct JsonBranch<'a> {
+    head: Option<&'a str>,
+    oid: Option<&'a str>,
+    upstream: Option<&'a str>,
+    ahead: Option<usize>,
+    behind: Option<usize>,
+}
+
+fn print_json(report: &StatusReport) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&json_status(report))?);
     Ok(())
-}
\ No newline at end of file
+}
+
+fn json_status(report: &StatusReport) -> JsonStatus<'_> {
+    let ahead_behind = report.upstream.as_ref().and_then(|upstream| upstream.ahead_behind);
+    JsonStatus {
+        version: STATUS_JSON_VERSION,
+        branch: JsonBranch {
+            head: report.branch.as_deref(),
+            oid: report.head.as_deref(),
+            upstream: report.upstream.as_ref().map(|upstream| upstream.name.as_str()),
+            ahead: ahead_behind.map(|(ahead, _)| ahead),
+            behind: ahead_behind.map(|(_, behind)| behind),
+        },
+        entries: report.entries(),
+    }
+}
+
+/// Compares the HEAD tree, the index and the working tree.
+pub fn collect_status(repo: &Repository) -> Result<StatusReport> {
+    let index = utils::load_index(repo)?;
+    let head_index = utils::head_index(repo)?;
+    let object_store = ObjectStore::new(repo);
+    let branch = current_branch(repo)?;
+    let head = utils::get_head_commit(repo)?;
+
+    let untracked: BTreeSet<String> = IgnoreRules::load(repo)?
+        .unignored_files(&repo.work_dir)
+        .into_iter()
+        .filter(|path| !index.entries.contains_key(path))
+        .collect();
+
+    Ok(StatusReport {
+        upstream: match &branch {
+            Some(branch) => upstream(repo, &object_store, branch, head.as_deref())?,
+            None => None,
+        },
+        staged: staged_changes(&object_store, &head_index, &index)?,
+        unstaged: unstaged_changes(repo, &object_store, &index)?,
+        unmerged: conflicts(repo, &object_store, &index, head.as_deref())?,
+        untracked,
+        branch,
+        head,
+        head_index,
+        index,
+    })
+}
+
+/// The current branch, or `None` when HEAD holds a commit rather than a
+/// `ref: ` line, so that a branch named `detached` is still a branch.
+fn current_branch(repo: &Repository) -> Result<Option<String>> {
+    let head_path = repo.git_dir.join("HEAD");
+    if head_path.exists() && !fs::read_to_string(&head_path)?.starts_with("ref: ") {
+        return Ok(None);
+    }
+    utils::get_current_branch(repo).map(Some)
+}
+
+fn upstream(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    branch: &str,
+    head: Option<&str>,
+) -> Result<Option<Upstream>> {
+    let config = Config::for_repo(repo)?;
+    let remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+    let (Some(remote), Some(merge)) = (remote, merge) else {
+        return Ok(None);
+    };
+
+    let merge_branch = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+    let (name, ref_path) = if remote == "." {
+        (merge_branch.to_string(), repo.git_dir.join("refs").join("heads").join(merge_branch))
+    } else {
+        (
+            format!("{}/{}", remote, merge_branch),
+            repo.git_dir.join("refs").join("remotes").join(remote).join(merge_branch),
+        )
+    };
+
+    let ahead_behind = match (head, fs::read_to_string(&ref_path).ok()) {
+        (Some(head), Some(upstream)) => {
+            let ours = merge::ancestors(object_store, head)?;
+            let theirs = merge::ancestors(object_store, upstream.trim())?;
+            Some((ours.difference(&theirs).count(), theirs.difference(&ours).count()))
+        }
+        _ => None,
+    };
+
+    Ok(Some(Upstream { name, ahead_behind }))
+}
+
+/// The three versions of every unmerged path, taken from the merge base, HEAD
+/// and `MERGE_HEAD`.
+fn conflicts(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    index: &Index,
+    head: Option<&str>,
+) -> Result<BTreeMap<String, Conflict>> {
+    if index.unmerged.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let tree_of = |commit: Option<&str>| -> Result<BTreeMap<String, TreeEntry>> {
+        match commit {
+            Some(commit) => object_store.read_tree(&object_store.load_commit(commit)?.tree),
+            None => Ok(BTreeMap::new()),
+        }
+    };
+    let merge_head = fs::read_to_string(repo.git_dir.join("MERGE_HEAD"))
+        .ok()
+        .map(|content| content.trim().to_string());
+
+    let ours = tree_of(head)?;
+    let (base, theirs) = match (head, &merge_head) {
+        (Some(head), Some(theirs)) => {
+            let base = merge::find_merge_base(object_store, head, theirs)?;
+            (tree_of(base.as_deref())?, tree_of(Some(theirs))?)
+        }
+        _ => (BTreeMap::new(), BTreeMap::new()),
+    };
+
+    Ok(index
+        .unmerged
+        .iter()
+        .map(|path| {
+            let conflict = if merge_head.is_some() {
+                Conflict {
+                    base: base.get(path).cloned(),
+                    ours: ours.get(path).cloned(),
+                    theirs: theirs.get(path).cloned(),
+                }
+            } else {
+                Conflict { ours: ours.get(path).cloned(), ..Default::default() }
+            };
+            (path.clone(), conflict)
+        })
+        .collect())
+}
+
+/// HEAD -> index. A deleted path whose content was staged, whole or
+/// similar enough, under a new path is reported as a rename.
+fn staged_changes(object_store: &ObjectStore, head: &Index, index: &Index) -> Result<BTreeMap<String, FileStatus>> {
+    let mut changes = BTreeMap::new();
+    let paths: BTreeSet<&String> = head.entries.keys().chain(index.entries.keys()).collect();
+    for path in paths {
+        if index.unmerged.contains(path) {
+            continue;
+        }
+        let change = match (head.entries.get(path), index.entries.get(path)) {
+            (None, Some(_)) => FileStatus::Added,
+            (Some(_), None) => FileStatus::Deleted,
+            (Some(old), Some(new)) if old.hash != new.hash || old.mode != new.mode => FileStatus::Modified,
+            _ => continue,
+        };
+        changes.insert(path.clone(), change);
+    }
+
+    let versions = |status: FileStatus, side: &Index| -> Vec<FileVersion> {
+        changes
+            .iter()
+            .filter(|(_, change)| **change == status)
+            .map(|(path, _)| FileVersion { path: path.clone(), hash: side.entries[path].hash.clone() })
+            .collect()
+    };
+    let deleted = versions(FileStatus::Deleted, head);
+    let added = versions(FileStatus::Added, index);
+    let renames = renames::detect(&deleted, &added, &[], &RenameOptions::default(), |file| {
+        Ok(object_store.load_blob(&file.hash)?.content)
+    })?;
+    for rename in renames {
+        changes.remove(&rename.from);
+        changes.insert(rename.to, FileStatus::Renamed { from: rename.from, similarity: rename.similarity });
+    }
+
+    Ok(changes)
+}
+
+/// index -> working tree.
+fn unstaged_changes(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    index: &Index,
+) -> Result<BTreeMap<String, FileStatus>> {
+    let mut changes = BTreeMap::new();
+    for (path, entry) in &index.entries {
+        if index.unmerged.contains(path) {
+            continue;
+        }
+        let file_path = repo.work_dir.join(path);
+        if file_path.is_file() {
+            let content = fs::read(&file_path)?;
+            if object_store.hash_blob(&content) != entry.hash {
+                changes.insert(path.clone(), FileStatus::Modified);
+            }
+        } else {
+            changes.insert(path.clone(), FileStatus::Deleted);
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{add, mv, rm};
+    use crate::testing::TestRepo;
+
+    fn project() -> TestRepo {
+        let test = TestRepo::new();
+        test.write("a.txt", "a\n");
+        test.write("b.txt", "b\n");
+        test.write("src/lib.rs", "lib\n");
+        test.commit("initial");
+        test
+    }
+
+    #[test]
+    fn clean_after_commit() {
+        let test = project();
+        let report = collect_status(&test.repo).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.branch.as_deref(), Some("main"));
+        assert_eq!(report.head, Some(test.head()));
+    }
+
+    #[test]
+    fn staged_and_unstaged_changes_are_separate() {
+        let test = project();
+        test.write("a.txt", "staged\n");
+        test.write("new.txt", "new\n");
+        add(&test.repo, vec!["a.txt".to_string(), "new.txt".to_string()], false).unwrap();
+        test.write("a.txt", "staged, then edited\n");
+        rm(&test.repo, vec!["b.txt".to_string()], false, false, false).unwrap();
+        fs::remove_file(test.path("src/lib.rs")).unwrap();
+
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(
+            report.staged,
+            BTreeMap::from([
+                ("a.txt".to_string(), FileStatus::Modified),
+                ("b.txt".to_string(), FileStatus::Deleted),
+                ("new.txt".to_string(), FileStatus::Added),
+            ])
+        );
+        assert_eq!(
+            report.unstaged,
+            BTreeMap::from([
+                ("a.txt".to_string(), FileStatus::Modified),
+                ("src/lib.rs".to_string(), FileStatus::Deleted),
+            ])
+        );
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn edited_moves_are_renames_with_their_similarity() {
+        let test = TestRepo::new();
+        test.write("old.txt", "one\ntwo\nthree\nfour\n");
+        test.commit("first");
+        std::fs::remove_file(test.path("old.txt")).unwrap();
+        test.write("new.txt", "one\ntwo\nthree\nFOUR\n");
+        crate::commands::add(&test.repo, vec![".".to_string()], false).unwrap();
+
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(
+            report.staged,
+            BTreeMap::from([("new.txt".to_string(), FileStatus::Renamed { from: "old.txt".to_string(), similarity: 73 })])
+        );
+    }
+
+    #[test]
+    fn moved_files_are_staged_renames() {
+        let test = project();
+        mv(&test.repo, "b.txt".to_string(), "c.txt".to_string(), false).unwrap();
+
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(
+            report.staged,
+            BTreeMap::from([("c.txt".to_string(), FileStatus::Renamed { from: "b.txt".to_string(), similarity: 100 })])
+        );
+        assert!(report.unstaged.is_empty());
+    }
+
+    #[test]
+    fn untracked_directories_are_collapsed() {
+        let test = project();
+        test.write("notes/todo.txt", "todo\n");
+        test.write("notes/deep/more.txt", "more\n");
+        test.write("src/extra.rs", "extra\n");
+
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(report.untracked.len(), 3);
+        assert_eq!(
+            report.untracked_collapsed(),
+            BTreeSet::from(["notes/".to_string(), "src/extra.rs".to_string()])
+        );
+    }
+
+    #[test]
+    fn unmerged_paths_are_reported_separately() {
+        let test = project();
+        let mut index = utils::load_index(&test.repo).unwrap();
+        index.unmerged.insert("a.txt".to_string());
+        utils::save_index(&test.repo, &index).unwrap();
+        test.write("a.txt", "<<<<<<< ours\n");
+
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(report.unmerged.keys().collect::<Vec<_>>(), ["a.txt"]);
+        assert!(report.staged.is_empty());
+        assert!(report.unstaged.is_empty());
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn entries_carry_both_codes() {
+        let test = project();
+        test.write("a.txt", "staged\n");
+        add(&test.repo, vec!["a.txt".to_string()], false).unwrap();
+        test.write("a.txt", "edited\n");
+        test.write("b.txt", "edited\n");
+        mv(&test.repo, "src/lib.rs".to_string(), "src/main.rs".to_string(), false).unwrap();
+        test.write("new.txt", "new\n");
+
+        let report = collect_status(&test.repo).unwrap();
+        let collected = report.entries();
+        let entries: Vec<_> = collected
+            .iter()
+            .map(|entry| (entry.x, entry.y, entry.path.as_str(), entry.orig_path.as_deref()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ('M', 'M', "a.txt", None),
+                ('.', 'M', "b.txt", None),
+                ('R', '.', "src/main.rs", Some("src/lib.rs")),
+                ('?', '?', "new.txt", None),
+            ]
+        );
+        assert_eq!(collected[2].kind, EntryKind::Renamed);
+        assert_eq!(collected[3].kind, EntryKind::Untracked);
+    }
+
+    #[test]
+    fn conflict_codes() {
+        let entry = |hash: &str| {
+            Some(TreeEntry { name: "f".to_string(), hash: hash.to_string(), mode: "100644".to_string(), is_file: true })
+        };
+        let conflict = |base, ours, theirs| Conflict { base, ours, theirs };
+
+        assert_eq!(conflict(entry("b"), entry("o"), entry("t")).code(), "UU");
+        assert_eq!(conflict(None, entry("o"), entry("t")).code(), "AA");
+        assert_eq!(conflict(entry("b"), None, entry("t")).code(), "DU");
+        assert_eq!(conflict(entry("b"), entry("o"), None).label(), "deleted by them");
+    }
+
+    #[test]
+    fn upstream_tracking_in_the_short_header() {
+        let test = project();
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(short_header(&report), "## main");
+
+        crate::commands::branch(&test.repo, Some("base".to_string()), false).unwrap();
+        let mut config = Config::for_repo(&test.repo).unwrap();
+        config.set("branch.main.remote", ".");
+        config.set("branch.main.merge", "refs/heads/base");
+        config.save().unwrap();
+        test.write("a.txt", "ahead\n");
+        test.commit("ahead");
+
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(short_header(&report), "## main...base [ahead 1]");
+
+        fs::remove_file(test.repo.git_dir.join("refs/heads/base")).unwrap();
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(short_header(&report), "## main...base [gone]");
+    }
+
+    #[test]
+    fn detached_head_is_read_from_head() {
+        let test = project();
+        crate::commands::branch(&test.repo, Some("detached".to_string()), false).unwrap();
+        crate::commands::checkout(&test.repo, "detached".to_string(), false).unwrap();
+        let report = collect_status(&test.repo).unwrap();
+        assert_eq!(report.branch.as_deref(), Some("detached"));
+        assert_eq!(short_header(&report), "## detached");
+
+        crate::commands::checkout(&test.repo, test.head(), false).unwrap();
+        let report = collect_status(&test.repo).unwrap();
+        assert!(report.is_detached());
+        assert_eq!(short_header(&report), "## HEAD (no branch)");
+        assert_eq!(serde_json::to_value(json_status(&report)).unwrap()["branch"]["head"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn json_has_a_version_and_entries() {
+        let test = project();
+        test.write("a.txt", "edited\n");
+        let report = collect_status(&test.repo).unwrap();
+        let json = serde_json::to_value(json_status(&report)).unwrap();
+
+        assert_eq!(json["version"], STATUS_JSON_VERSION);
+        assert_eq!(json["branch"]["head"], "main");
+        assert_eq!(json["branch"]["oid"], test.head());
+        assert_eq!(
+            json["entries"],
+            serde_json::json!([{ "kind": "changed", "path": "a.txt", "index": ".", "worktree": "M" }])
+        );
+    }
+}
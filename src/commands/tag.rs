@@ -0,0 +1,188 @@
+use crate::refs::{Expected, RefTransaction};
+use crate::{CrabGitError, Repository, Result, Tag, object_store::ObjectStore, rev_parse, utils};
+use chrono::Utc;
+use walkdir::WalkDir;
+
+pub fn tag(
+    repo: &Repository,
+    name: Option<String>,
+    target: Option<String>,
+    message: Option<String>,
+    delete: bool,
+    tagger: Option<String>,
+) -> Result<()> {
+    let refs_tags = repo.git_dir.join("refs").join("tags");
+
+    let Some(tag_name) = name else {
+        for tag in list_tags(repo)? {
+            println!("{}", tag);
+        }
+        return Ok(());
+    };
+
+    utils::check_ref_format(&format!("refs/tags/{}", tag_name))?;
+    let tag_path = refs_tags.join(&tag_name);
+
+    if delete {
+        if let Some(target) = utils::get_tag_target(repo, &tag_name)? {
+            let mut transaction = RefTransaction::new(repo);
+            transaction.delete(&format!("refs/tags/{}", tag_name), Expected::Value(target.clone()));
+            transaction.commit()?;
+            println!("Deleted tag '{}' (was {})", tag_name, target.get(..8).unwrap_or(&target));
+            return Ok(());
+        } else {
+            return Err(CrabGitError::RefNotFound(tag_name));
+        }
+    }
+
+    if tag_path.exists() {
+        return Err(CrabGitError::AlreadyExists(format!("Tag {}", tag_name)));
+    }
+
+    let object_store = ObjectStore::new(repo);
+    let target = match target {
+        Some(target) => rev_parse::resolve(repo, &object_store, &target)?,
+        None => utils::get_head_commit(repo)?.ok_or_else(|| {
+            CrabGitError::InvalidOperation("No commits yet, cannot create tag".to_string())
+        })?,
+    };
+
+    match message {
+        Some(message) => {
+            let tagger = tagger.unwrap_or_else(|| utils::DEFAULT_IDENTITY.to_string());
+            let tag = Tag {
+                hash: String::new(),
+                name: tag_name.clone(),
+                target_type: object_store.object_kind(&target)?.to_string(),
+                target,
+                tagger,
+                message,
+                timestamp: Utc::now(),
+            };
+
+            let tag_hash = object_store.store_tag(&tag)?;
+            utils::update_tag(repo, &tag_name, &tag_hash)?;
+            println!("Created annotated tag {}", tag_name);
+        }
+        None => {
+            utils::update_tag(repo, &tag_name, &target)?;
+            println!("Created tag {}", tag_name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Names of all tags, including those nested in directories such as `release/v1`.
+pub fn list_tags(repo: &Repository) -> Result<Vec<String>> {
+    let refs_tags = repo.git_dir.join("refs").join("tags");
+    let mut tags = Vec::new();
+    if refs_tags.is_dir() {
+        for entry in WalkDir::new(&refs_tags).sort_by_file_name() {
+            let entry = entry.map_err(std::io::Error::other)?;
+            if entry.file_type().is_file() {
+                let relative = entry.path().strip_prefix(&refs_tags)?;
+                tags.push(relative.to_string_lossy().replace("\\", "/"));
+            }
+        }
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::ObjectKind;
+    use crate::testing::TestRepo;
+    use std::fs;
+
+    fn create(test: &TestRepo, name: &str, target: Option<&str>, message: Option<&str>) -> Result<()> {
+        tag(
+            &test.repo,
+            Some(name.to_string()),
+            target.map(str::to_string),
+            message.map(str::to_string),
+            false,
+            None,
+        )
+    }
+
+    #[test]
+    fn lightweight_and_annotated_tags() {
+        let test = TestRepo::new();
+        test.write("file.txt", "one\n");
+        test.commit("first");
+        let head = test.head();
+
+        create(&test, "v1", None, None).unwrap();
+        create(&test, "v2", None, Some("Release 2")).unwrap();
+        assert_eq!(utils::get_tag_target(&test.repo, "v1").unwrap(), Some(head.clone()));
+
+        let object_store = ObjectStore::new(&test.repo);
+        let annotated = utils::get_tag_target(&test.repo, "v2").unwrap().unwrap();
+        let tag_object = object_store.load_tag(&annotated).unwrap();
+        assert_eq!(tag_object.target, head);
+        assert_eq!(tag_object.target_type, "commit");
+        assert_eq!(utils::peel_to_commit(&object_store, &annotated).unwrap(), head);
+
+        assert!(create(&test, "v1", None, None).is_err());
+    }
+
+    #[test]
+    fn target_type_follows_the_target_object() {
+        let test = TestRepo::new();
+        test.write("file.txt", "one\n");
+        test.commit("first");
+        let object_store = ObjectStore::new(&test.repo);
+        let blob = object_store.store_blob(b"loose\n").unwrap();
+        let tree = object_store.load_commit(&test.head()).unwrap().tree;
+
+        create(&test, "blob-tag", Some(&blob), Some("a blob")).unwrap();
+        create(&test, "tree-tag", Some(&tree), Some("a tree")).unwrap();
+
+        let target_type = |name: &str| {
+            let hash = utils::get_tag_target(&test.repo, name).unwrap().unwrap();
+            object_store.load_tag(&hash).unwrap().target_type
+        };
+        assert_eq!(target_type("blob-tag"), "blob");
+        assert_eq!(target_type("tree-tag"), "tree");
+        assert_eq!(object_store.object_kind(&blob).unwrap(), ObjectKind::Blob);
+    }
+
+    #[test]
+    fn nested_tags_are_listed() {
+        let test = TestRepo::new();
+        test.write("file.txt", "one\n");
+        test.commit("first");
+
+        create(&test, "v1", None, None).unwrap();
+        create(&test, "release/v2", None, Some("nested")).unwrap();
+        create(&test, "release/rc/v3", None, None).unwrap();
+
+        assert_eq!(list_tags(&test.repo).unwrap(), ["release/rc/v3", "release/v2", "v1"]);
+        assert!(utils::get_tag_target(&test.repo, "release/v2").unwrap().is_some());
+    }
+
+    #[test]
+    fn invalid_tag_names_are_rejected() {
+        let test = TestRepo::new();
+        test.write("file.txt", "one\n");
+        test.commit("first");
+
+        for name in ["../../HEAD", "a..b", "v1.lock", ".hidden", "with space", "x~1", "dir/", "@{1}"] {
+            assert!(create(&test, name, None, None).is_err(), "{} was accepted", name);
+        }
+        assert_eq!(fs::read_to_string(test.repo.git_dir.join("HEAD")).unwrap().trim_end(), "ref: refs/heads/main");
+        assert!(list_tags(&test.repo).unwrap().is_empty());
+    }
+
+    #[test]
+    fn deleting_a_tag_with_a_short_target() {
+        let test = TestRepo::new();
+        utils::update_tag(&test.repo, "odd", "abc").unwrap();
+
+        tag(&test.repo, Some("odd".to_string()), None, None, true, None).unwrap();
+        assert!(list_tags(&test.repo).unwrap().is_empty());
+        assert!(tag(&test.repo, Some("odd".to_string()), None, None, true, None).is_err());
+    }
+}
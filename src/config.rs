@@ -0,0 +1,222 @@
+//! The repository config file, `.crab_git/config`, in git's INI format:
+//!
+//! ```text
+//! [core]
+//!     objectversion = 2
+//! [branch "main"]
+//!     remote = origin
+//! ```
+//!
+//! Keys are addressed as `section.key` or `section.subsection.key`. Section
+//! and key names are case-insensitive, subsection names are not. Lines that
+//! cannot be parsed are ignored.
+
+use crate::{Repository, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    path: PathBuf,
+    sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone)]
+struct Section {
+    name: String,
+    subsection: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    fn header(&self) -> String {
+        match &self.subsection {
+            Some(sub) => format!("[{} \"{}\"]", self.name, sub.replace('\\', "\\\\").replace('"', "\\\"")),
+            None => format!("[{}]", self.name),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `<git_dir>/config`; a missing file is an empty config.
+    pub fn load(git_dir: &Path) -> Result<Self> {
+        let path = git_dir.join("config");
+        let content = if path.is_file() {
+            fs::read_to_string(&path)?
+        } else {
+            String::new()
+        };
+        Ok(Self::parse(path, &content))
+    }
+
+    pub fn for_repo(repo: &Repository) -> Result<Self> {
+        Self::load(&repo.git_dir)
+    }
+
+    fn parse(path: PathBuf, content: &str) -> Self {
+        let mut sections: Vec<Section> = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if let Some(section) = parse_header(header) {
+                    sections.push(section);
+                }
+                continue;
+            }
+
+            let Some(section) = sections.last_mut() else { continue };
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), unquote(value.trim())),
+                // A key without a value is a boolean `true`.
+                None => (line, "true".to_string()),
+            };
+            if is_valid_name(key) {
+                section.entries.push((key.to_lowercase(), value));
+            }
+        }
+        Self { path, sections }
+    }
+
+    /// The last value set for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let (name, subsection, key) = split_key(key)?;
+        self.sections
+            .iter()
+            .filter(|section| section.name == name && section.subsection.as_deref() == subsection)
+            .flat_map(|section| section.entries.iter())
+            .filter(|(k, _)| *k == key)
+            .map(|(_, value)| value.as_str())
+            .next_back()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" | "" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Replaces every value of `key` with `value`, appending the key (and its
+    /// section) if it is not set yet.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let Some((name, subsection, key)) = split_key(key) else { return };
+
+        let mut replaced = false;
+        for section in self.sections.iter_mut() {
+            if section.name != name || section.subsection.as_deref() != subsection {
+                continue;
+            }
+            section.entries.retain_mut(|(k, v)| {
+                if *k != key {
+                    return true;
+                }
+                if replaced {
+                    return false;
+                }
+                *v = value.to_string();
+                replaced = true;
+                true
+            });
+        }
+        if replaced {
+            return;
+        }
+
+        let entry = (key.clone(), value.to_string());
+        match self
+            .sections
+            .iter_mut()
+            .rfind(|section| section.name == name && section.subsection.as_deref() == subsection)
+        {
+            Some(section) => section.entries.push(entry),
+            None => self.sections.push(Section {
+                name,
+                subsection: subsection.map(str::to_string),
+                entries: vec![entry],
+            }),
+        }
+    }
+
+    pub fn unset(&mut self, key: &str) {
+        let Some((name, subsection, key)) = split_key(key) else { return };
+        for section in self.sections.iter_mut() {
+            if section.name == name && section.subsection.as_deref() == subsection {
+                section.entries.retain(|(k, _)| *k != key);
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut content = String::new();
+        for section in &self.sections {
+            content.push_str(&section.header());
+            content.push('\n');
+            for (key, value) in &section.entries {
+                content.push_str(&format!("\t{} = {}\n", key, quote(value)));
+            }
+        }
+        fs::write(&self.path, content)?;
+        Ok(())
+    }
+}
+
+fn parse_header(header: &str) -> Option<Section> {
+    let (name, subsection) = match header.split_once(char::is_whitespace) {
+        Some((name, rest)) => {
+            let rest = rest.trim();
+            let quoted = rest.strip_prefix('"')?.strip_suffix('"')?;
+            (name, Some(quoted.replace("\\\"", "\"").replace("\\\\", "\\")))
+        }
+        None => (header, None),
+    };
+    is_valid_name(name).then(|| Section {
+        name: name.to_lowercase(),
+        subsection,
+        entries: Vec::new(),
+    })
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+/// Splits `section[.subsection].key` into lower-cased section and key names
+/// and the case-preserved subsection.
+fn split_key(key: &str) -> Option<(String, Option<&str>, String)> {
+    let (section, rest) = key.split_once('.')?;
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection), name),
+        None => (None, rest),
+    };
+    if section.is_empty() || name.is_empty() {
+        return None;
+    }
+    Some((section.to_lowercase(), subsection, name.to_lowercase()))
+}
+
+fn unquote(value: &str) -> String {
+    let value = match value.find([';', '#']) {
+        Some(i) if !value[..i].contains('"') => value[..i].trim_end(),
+        _ => value,
+    };
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+fn quote(value: &str) -> String {
+    let needs_quotes = value.starts_with(' ')
+        || value.ends_with(' ')
+        || value.contains(['#', ';', '"', '\\']);
+    if needs_quotes {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,849 @@
+use crate::color::{self, DiffColors};
+use crate::renames::RenameOptions;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl FromStr for DiffAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "myers" | "default" => Ok(DiffAlgorithm::Myers),
+            "patience" => Ok(DiffAlgorithm::Patience),
+            "histogram" => Ok(DiffAlgorithm::Histogram),
+            _ => Err(format!("unknown diff algorithm '{}' (expected myers, patience or histogram)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    pub context: usize,
+    pub algorithm: DiffAlgorithm,
+    /// Rename and copy detection, `None` to show renames as a deletion
+    /// and an addition.
+    pub renames: Option<RenameOptions>,
+    /// Write binary files as a `GIT binary patch` instead of just noting
+    /// that they differ.
+    pub binary: bool,
+    /// Colors for the output, `None` for plain text.
+    pub colors: Option<DiffColors>,
+    /// Show the changed words of changed lines instead of the whole lines.
+    pub word_diff: Option<WordDiff>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            algorithm: DiffAlgorithm::Myers,
+            renames: Some(RenameOptions::default()),
+            binary: false,
+            colors: None,
+            word_diff: None,
+        }
+    }
+}
+
+/// How `--word-diff` marks changed words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WordDiff {
+    /// `[-removed-]{+added+}`.
+    #[default]
+    Plain,
+    /// Removed and added words in their colors, without brackets.
+    Color,
+}
+
+impl FromStr for WordDiff {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(WordDiff::Plain),
+            "color" => Ok(WordDiff::Color),
+            _ => Err(format!("unknown word diff mode '{}' (expected plain or color)", s)),
+        }
+    }
+}
+
+/// One step of an edit script, addressing elements by index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp {
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+/// A maximal run of non-equal elements: `old` is replaced by `new`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// A unified diff hunk: the changes it covers plus surrounding context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    pub changes: Vec<Change>,
+}
+
+/// Splits content into lines, keeping each line's terminating newline.
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Computes an edit script turning `old` into `new`.
+pub fn diff<T: Eq + Hash>(old: &[T], new: &[T], algorithm: DiffAlgorithm) -> Vec<DiffOp> {
+    let mut ops = Vec::with_capacity(old.len().max(new.len()));
+    match algorithm {
+        DiffAlgorithm::Myers => myers(old, 0..old.len(), new, 0..new.len(), &mut ops),
+        DiffAlgorithm::Patience => patience(old, 0..old.len(), new, 0..new.len(), &mut ops),
+        DiffAlgorithm::Histogram => histogram(old, 0..old.len(), new, 0..new.len(), &mut ops),
+    }
+    ops
+}
+
+/// Collapses an edit script into its runs of changed elements.
+pub fn changes(ops: &[DiffOp]) -> Vec<Change> {
+    let mut changes: Vec<Change> = Vec::new();
+    let (mut old_pos, mut new_pos) = (0, 0);
+    let mut in_change = false;
+
+    for op in ops {
+        match *op {
+            DiffOp::Equal { old, new } => {
+                old_pos = old + 1;
+                new_pos = new + 1;
+                in_change = false;
+                continue;
+            }
+            DiffOp::Delete { old } => old_pos = old + 1,
+            DiffOp::Insert { new } => new_pos = new + 1,
+        }
+
+        if !in_change {
+            let old_start = if matches!(op, DiffOp::Delete { .. }) { old_pos - 1 } else { old_pos };
+            let new_start = if matches!(op, DiffOp::Insert { .. }) { new_pos - 1 } else { new_pos };
+            changes.push(Change { old: old_start..old_start, new: new_start..new_start });
+            in_change = true;
+        }
+        let change = changes.last_mut().unwrap();
+        change.old.end = old_pos;
+        change.new.end = new_pos;
+    }
+
+    changes
+}
+
+/// Groups changes into hunks, merging changes separated by at most
+/// `2 * context` unchanged elements.
+pub fn hunks(changes: &[Change], old_len: usize, new_len: usize, context: usize) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+
+    for change in changes {
+        let old_start = change.old.start.saturating_sub(context);
+        let new_start = change.new.start.saturating_sub(context);
+        let old_end = (change.old.end + context).min(old_len);
+        let new_end = (change.new.end + context).min(new_len);
+
+        if let Some(hunk) = hunks.last_mut() {
+            if old_start <= hunk.old.end {
+                hunk.old.end = old_end;
+                hunk.new.end = new_end;
+                hunk.changes.push(change.clone());
+                continue;
+            }
+        }
+
+        hunks.push(Hunk {
+            old: old_start..old_end,
+            new: new_start..new_end,
+            changes: vec![change.clone()],
+        });
+    }
+
+    hunks
+}
+
+/// Renders the `@@` hunks of a unified diff between two byte buffers. File
+/// headers are left to the caller.
+pub fn unified_diff(old: &[u8], new: &[u8], options: &DiffOptions) -> Vec<u8> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let ops = diff(&old_lines, &new_lines, options.algorithm);
+    let changes = changes(&ops);
+    let colors = options.colors.clone().unwrap_or_else(DiffColors::none);
+    // Word diffs print unchanged lines as they are.
+    let context_prefix = if options.word_diff.is_some() { None } else { Some(b' ') };
+
+    let mut out = Vec::new();
+    for hunk in hunks(&changes, old_lines.len(), new_lines.len(), options.context) {
+        let header = format!("@@ -{} +{} @@", hunk_range(&hunk.old), hunk_range(&hunk.new));
+        out.extend_from_slice(color::paint(&colors.frag, &header).as_bytes());
+        out.push(b'\n');
+
+        let mut old_pos = hunk.old.start;
+        for change in &hunk.changes {
+            for line in &old_lines[old_pos..change.old.start] {
+                push_line(&mut out, context_prefix, line, &colors.context);
+            }
+            match options.word_diff {
+                Some(mode) => {
+                    let old_text = old_lines[change.old.clone()].concat();
+                    let new_text = new_lines[change.new.clone()].concat();
+                    word_diff(&mut out, &old_text, &new_text, mode, &colors, options.algorithm);
+                }
+                None => {
+                    for line in &old_lines[change.old.clone()] {
+                        push_line(&mut out, Some(b'-'), line, &colors.old);
+                    }
+                    for line in &new_lines[change.new.clone()] {
+                        push_line(&mut out, Some(b'+'), line, &colors.new);
+                    }
+                }
+            }
+            old_pos = change.old.end;
+        }
+        for line in &old_lines[old_pos..hunk.old.end] {
+            push_line(&mut out, context_prefix, line, &colors.context);
+        }
+    }
+
+    out
+}
+
+/// Renders a block of removed and added lines word by word, as git does:
+/// the unchanged text is taken from the new side, and removed and added
+/// runs of words are marked where they occur, each line of a run marked
+/// separately. Words are runs of non-whitespace.
+fn word_diff(out: &mut Vec<u8>, old: &[u8], new: &[u8], mode: WordDiff, colors: &DiffColors, algorithm: DiffAlgorithm) {
+    let old_words = words(old);
+    let new_words = words(new);
+    let ops = diff(
+        &old_words.iter().map(|word| &old[word.clone()]).collect::<Vec<_>>(),
+        &new_words.iter().map(|word| &new[word.clone()]).collect::<Vec<_>>(),
+        algorithm,
+    );
+
+    let (old_marks, new_marks) = match mode {
+        WordDiff::Plain => (("[-", "-]"), ("{+", "+}")),
+        WordDiff::Color => (("", ""), ("", "")),
+    };
+    let start = out.len();
+    let mut new_pos = 0;
+    for change in changes(&ops) {
+        // A pure removal sits right after the preceding word.
+        let new_range = if change.new.is_empty() {
+            let at = change.new.start.checked_sub(1).map_or(0, |i| new_words[i].end);
+            at..at
+        } else {
+            new_words[change.new.start].start..new_words[change.new.end - 1].end
+        };
+        out.extend_from_slice(&new[new_pos..new_range.start]);
+        if !change.old.is_empty() {
+            let old_range = old_words[change.old.start].start..old_words[change.old.end - 1].end;
+            push_marked(out, &old[old_range], old_marks, &colors.old);
+        }
+        push_marked(out, &new[new_range.clone()], new_marks, &colors.new);
+        new_pos = new_range.end;
+    }
+    out.extend_from_slice(&new[new_pos..]);
+    if out.len() > start && !out.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+}
+
+/// Byte ranges of the runs of non-whitespace in `text`.
+fn words(text: &[u8]) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, byte) in text.iter().enumerate() {
+        match (byte.is_ascii_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(begin)) => {
+                words.push(begin..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(begin) = start {
+        words.push(begin..text.len());
+    }
+    words
+}
+
+/// Writes `text` with each of its lines wrapped in `marks` and `color`.
+fn push_marked(out: &mut Vec<u8>, text: &[u8], (open, close): (&str, &str), color: &str) {
+    for (i, line) in text.split(|&b| b == b'\n').enumerate() {
+        if i > 0 {
+            out.push(b'\n');
+        }
+        if line.is_empty() {
+            continue;
+        }
+        out.extend_from_slice(color.as_bytes());
+        out.extend_from_slice(open.as_bytes());
+        out.extend_from_slice(line);
+        out.extend_from_slice(close.as_bytes());
+        if !color.is_empty() {
+            out.extend_from_slice(color::RESET_COLOR.as_bytes());
+        }
+    }
+}
+
+/// Bytes looked at to decide whether content is binary, as in git.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Whether content looks binary rather than text: it has a NUL byte near
+/// the start, as git checks.
+pub fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// Numbers of inserted and deleted lines between two byte buffers.
+pub fn line_counts(old: &[u8], new: &[u8], algorithm: DiffAlgorithm) -> (usize, usize) {
+    let ops = diff(&split_lines(old), &split_lines(new), algorithm);
+    ops.iter().fold((0, 0), |(insertions, deletions), op| match op {
+        DiffOp::Insert { .. } => (insertions + 1, deletions),
+        DiffOp::Delete { .. } => (insertions, deletions + 1),
+        DiffOp::Equal { .. } => (insertions, deletions),
+    })
+}
+
+fn hunk_range(range: &Range<usize>) -> String {
+    match range.len() {
+        0 => format!("{},0", range.start),
+        1 => format!("{}", range.start + 1),
+        len => format!("{},{}", range.start + 1, len),
+    }
+}
+
+fn push_line(out: &mut Vec<u8>, prefix: Option<u8>, line: &[u8], color: &str) {
+    let content = line.strip_suffix(b"\n").unwrap_or(line);
+    if color.is_empty() {
+        out.extend(prefix);
+        out.extend_from_slice(content);
+    } else {
+        out.extend_from_slice(color.as_bytes());
+        out.extend(prefix);
+        out.extend_from_slice(content);
+        out.extend_from_slice(color::RESET_COLOR.as_bytes());
+    }
+    out.push(b'\n');
+    if !line.ends_with(b"\n") {
+        out.extend_from_slice(b"\\ No newline at end of file\n");
+    }
+}
+
+fn emit_equal(old: Range<usize>, new_start: usize, ops: &mut Vec<DiffOp>) {
+    for (i, old) in old.enumerate() {
+        ops.push(DiffOp::Equal { old, new: new_start + i });
+    }
+}
+
+fn emit_replace(old: Range<usize>, new: Range<usize>, ops: &mut Vec<DiffOp>) {
+    ops.extend(old.map(|old| DiffOp::Delete { old }));
+    ops.extend(new.map(|new| DiffOp::Insert { new }));
+}
+
+fn common_prefix<T: Eq>(old: &[T], old_range: &Range<usize>, new: &[T], new_range: &Range<usize>) -> usize {
+    old[old_range.clone()]
+        .iter()
+        .zip(&new[new_range.clone()])
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+fn common_suffix<T: Eq>(old: &[T], old_range: &Range<usize>, new: &[T], new_range: &Range<usize>) -> usize {
+    old[old_range.clone()]
+        .iter()
+        .rev()
+        .zip(new[new_range.clone()].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// Myers' O(ND) algorithm in its linear-space divide-and-conquer form.
+fn myers<T: Eq>(old: &[T], old_range: Range<usize>, new: &[T], new_range: Range<usize>, ops: &mut Vec<DiffOp>) {
+    let max_d = (old_range.len() + new_range.len()).div_ceil(2) + 1;
+    let mut vf = V::new(max_d);
+    let mut vb = V::new(max_d);
+    conquer(old, old_range, new, new_range, &mut vf, &mut vb, ops);
+}
+
+struct V {
+    offset: isize,
+    data: Vec<usize>,
+}
+
+impl V {
+    fn new(max_d: usize) -> Self {
+        Self {
+            offset: max_d as isize,
+            data: vec![0; 2 * max_d + 2],
+        }
+    }
+}
+
+impl std::ops::Index<isize> for V {
+    type Output = usize;
+
+    fn index(&self, k: isize) -> &usize {
+        &self.data[(k + self.offset) as usize]
+    }
+}
+
+impl std::ops::IndexMut<isize> for V {
+    fn index_mut(&mut self, k: isize) -> &mut usize {
+        &mut self.data[(k + self.offset) as usize]
+    }
+}
+
+fn conquer<T: Eq>(
+    old: &[T],
+    mut old_range: Range<usize>,
+    new: &[T],
+    mut new_range: Range<usize>,
+    vf: &mut V,
+    vb: &mut V,
+    ops: &mut Vec<DiffOp>,
+) {
+    let prefix = common_prefix(old, &old_range, new, &new_range);
+    emit_equal(old_range.start..old_range.start + prefix, new_range.start, ops);
+    old_range.start += prefix;
+    new_range.start += prefix;
+
+    let suffix = common_suffix(old, &old_range, new, &new_range);
+    let suffix_start = (old_range.end - suffix, new_range.end - suffix);
+    old_range.end -= suffix;
+    new_range.end -= suffix;
+
+    if old_range.is_empty() || new_range.is_empty() {
+        emit_replace(old_range, new_range, ops);
+    } else if let Some((x, y)) = middle_snake(old, old_range.clone(), new, new_range.clone(), vf, vb) {
+        conquer(old, old_range.start..x, new, new_range.start..y, vf, vb, ops);
+        conquer(old, x..old_range.end, new, y..new_range.end, vf, vb, ops);
+    } else {
+        emit_replace(old_range, new_range, ops);
+    }
+
+    emit_equal(suffix_start.0..suffix_start.0 + suffix, suffix_start.1, ops);
+}
+
+fn middle_snake<T: Eq>(
+    old: &[T],
+    old_range: Range<usize>,
+    new: &[T],
+    new_range: Range<usize>,
+    vf: &mut V,
+    vb: &mut V,
+) -> Option<(usize, usize)> {
+    let n = old_range.len();
+    let m = new_range.len();
+    let delta = n as isize - m as isize;
+    let odd = delta & 1 == 1;
+    let max_d = (n + m).div_ceil(2) + 1;
+
+    vf[1] = 0;
+    vb[1] = 0;
+
+    for d in 0..max_d as isize {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vf[k - 1] < vf[k + 1]) {
+                vf[k + 1]
+            } else {
+                vf[k - 1] + 1
+            };
+            let y = (x as isize - k) as usize;
+            let (x0, y0) = (x, y);
+            if x < n && y < m {
+                x += common_prefix(
+                    old,
+                    &(old_range.start + x..old_range.end),
+                    new,
+                    &(new_range.start + y..new_range.end),
+                );
+            }
+            vf[k] = x;
+
+            if odd && (k - delta).abs() < d && vf[k] + vb[-(k - delta)] >= n {
+                return Some((old_range.start + x0, new_range.start + y0));
+            }
+        }
+
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vb[k - 1] < vb[k + 1]) {
+                vb[k + 1]
+            } else {
+                vb[k - 1] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            if x < n && y < m {
+                let advance = common_suffix(
+                    old,
+                    &(old_range.start..old_range.start + n - x),
+                    new,
+                    &(new_range.start..new_range.start + m - y),
+                );
+                x += advance;
+                y += advance;
+            }
+            vb[k] = x;
+
+            if !odd && (k - delta).abs() <= d && vb[k] + vf[-(k - delta)] >= n {
+                return Some((old_range.start + n - x, new_range.start + m - y));
+            }
+        }
+    }
+
+    None
+}
+
+/// Patience diff: anchor on lines that occur exactly once on both sides,
+/// keep the longest increasing run of them and recurse between anchors.
+fn patience<T: Eq + Hash>(old: &[T], old_range: Range<usize>, new: &[T], new_range: Range<usize>, ops: &mut Vec<DiffOp>) {
+    let mut old_range = old_range;
+    let mut new_range = new_range;
+
+    let prefix = common_prefix(old, &old_range, new, &new_range);
+    emit_equal(old_range.start..old_range.start + prefix, new_range.start, ops);
+    old_range.start += prefix;
+    new_range.start += prefix;
+
+    let suffix = common_suffix(old, &old_range, new, &new_range);
+    let suffix_start = (old_range.end - suffix, new_range.end - suffix);
+    old_range.end -= suffix;
+    new_range.end -= suffix;
+
+    let anchors = unique_anchors(old, old_range.clone(), new, new_range.clone());
+    if anchors.is_empty() {
+        myers(old, old_range, new, new_range, ops);
+    } else {
+        let (mut old_pos, mut new_pos) = (old_range.start, new_range.start);
+        for (x, y) in anchors {
+            patience(old, old_pos..x, new, new_pos..y, ops);
+            ops.push(DiffOp::Equal { old: x, new: y });
+            old_pos = x + 1;
+            new_pos = y + 1;
+        }
+        patience(old, old_pos..old_range.end, new, new_pos..new_range.end, ops);
+    }
+
+    emit_equal(suffix_start.0..suffix_start.0 + suffix, suffix_start.1, ops);
+}
+
+fn unique_anchors<T: Eq + Hash>(old: &[T], old_range: Range<usize>, new: &[T], new_range: Range<usize>) -> Vec<(usize, usize)> {
+    let mut counts: HashMap<&T, (usize, usize, usize)> = HashMap::new();
+    for i in old_range {
+        let entry = counts.entry(&old[i]).or_insert((0, 0, i));
+        entry.0 += 1;
+    }
+    for j in new_range.clone() {
+        if let Some(entry) = counts.get_mut(&new[j]) {
+            entry.1 += 1;
+        }
+    }
+
+    let pairs: Vec<(usize, usize)> = new_range
+        .filter_map(|j| match counts.get(&new[j]) {
+            Some(&(1, 1, i)) => Some((i, j)),
+            _ => None,
+        })
+        .collect();
+
+    longest_increasing(&pairs)
+}
+
+/// Longest subsequence of `pairs` (ordered by new index) whose old indices
+/// are increasing, found with patience sorting.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut piles: Vec<usize> = Vec::new();
+    let mut back: Vec<Option<usize>> = vec![None; pairs.len()];
+
+    for (idx, &(old, _)) in pairs.iter().enumerate() {
+        let pile = piles.partition_point(|&top| pairs[top].0 < old);
+        back[idx] = if pile > 0 { Some(piles[pile - 1]) } else { None };
+        if pile == piles.len() {
+            piles.push(idx);
+        } else {
+            piles[pile] = idx;
+        }
+    }
+
+    let mut result = Vec::with_capacity(piles.len());
+    let mut cursor = piles.last().copied();
+    while let Some(idx) = cursor {
+        result.push(pairs[idx]);
+        cursor = back[idx];
+    }
+    result.reverse();
+    result
+}
+
+/// Elements occurring more often than this are never used as histogram anchors.
+const HISTOGRAM_MAX_OCCURRENCES: usize = 64;
+
+/// Histogram diff: split around the longest common region containing the
+/// least frequent element, recursing on both sides.
+fn histogram<T: Eq + Hash>(old: &[T], old_range: Range<usize>, new: &[T], new_range: Range<usize>, ops: &mut Vec<DiffOp>) {
+    let mut old_range = old_range;
+    let mut new_range = new_range;
+
+    let prefix = common_prefix(old, &old_range, new, &new_range);
+    emit_equal(old_range.start..old_range.start + prefix, new_range.start, ops);
+    old_range.start += prefix;
+    new_range.start += prefix;
+
+    let suffix = common_suffix(old, &old_range, new, &new_range);
+    let suffix_start = (old_range.end - suffix, new_range.end - suffix);
+    old_range.end -= suffix;
+    new_range.end -= suffix;
+
+    if old_range.is_empty() || new_range.is_empty() {
+        emit_replace(old_range, new_range, ops);
+    } else if let Some((old_match, new_match)) = histogram_region(old, old_range.clone(), new, new_range.clone()) {
+        histogram(old, old_range.start..old_match.start, new, new_range.start..new_match.start, ops);
+        emit_equal(old_match.clone(), new_match.start, ops);
+        histogram(old, old_match.end..old_range.end, new, new_match.end..new_range.end, ops);
+    } else {
+        myers(old, old_range, new, new_range, ops);
+    }
+
+    emit_equal(suffix_start.0..suffix_start.0 + suffix, suffix_start.1, ops);
+}
+
+fn histogram_region<T: Eq + Hash>(
+    old: &[T],
+    old_range: Range<usize>,
+    new: &[T],
+    new_range: Range<usize>,
+) -> Option<(Range<usize>, Range<usize>)> {
+    let mut occurrences: HashMap<&T, Vec<usize>> = HashMap::new();
+    for i in old_range.clone() {
+        occurrences.entry(&old[i]).or_default().push(i);
+    }
+
+    let mut best: Option<(usize, Range<usize>, Range<usize>)> = None;
+    for j in new_range.clone() {
+        let Some(positions) = occurrences.get(&new[j]) else { continue };
+        let count = positions.len();
+        if count > HISTOGRAM_MAX_OCCURRENCES || best.as_ref().is_some_and(|(c, _, _)| count > *c) {
+            continue;
+        }
+
+        for &i in positions {
+            let mut start = (i, j);
+            while start.0 > old_range.start && start.1 > new_range.start && old[start.0 - 1] == new[start.1 - 1] {
+                start = (start.0 - 1, start.1 - 1);
+            }
+            let mut end = (i + 1, j + 1);
+            while end.0 < old_range.end && end.1 < new_range.end && old[end.0] == new[end.1] {
+                end = (end.0 + 1, end.1 + 1);
+            }
+
+            let len = end.0 - start.0;
+            let better = match &best {
+                None => true,
+                Some((c, region, _)) => count < *c || len > region.len(),
+            };
+            if better {
+                best = Some((count, start.0..end.0, start.1..end.1));
+            }
+        }
+    }
+
+    best.map(|(_, old_match, new_match)| (old_match, new_match))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [DiffAlgorithm; 3] = [DiffAlgorithm::Myers, DiffAlgorithm::Patience, DiffAlgorithm::Histogram];
+
+    fn unified(old: &str, new: &str) -> String {
+        String::from_utf8(unified_diff(old.as_bytes(), new.as_bytes(), &DiffOptions::default())).unwrap()
+    }
+
+    /// Checks that `ops` walks both sequences in order, matching only equal
+    /// elements, and returns the number of deletions and insertions.
+    fn check_script<T: Eq + std::fmt::Debug>(old: &[T], new: &[T], ops: &[DiffOp]) -> usize {
+        let (mut old_pos, mut new_pos, mut edits) = (0, 0, 0);
+        for op in ops {
+            match *op {
+                DiffOp::Equal { old: o, new: n } => {
+                    assert_eq!((o, n), (old_pos, new_pos), "{:?} out of order", op);
+                    assert_eq!(old[o], new[n]);
+                    old_pos += 1;
+                    new_pos += 1;
+                }
+                DiffOp::Delete { old: o } => {
+                    assert_eq!(o, old_pos, "{:?} out of order", op);
+                    old_pos += 1;
+                    edits += 1;
+                }
+                DiffOp::Insert { new: n } => {
+                    assert_eq!(n, new_pos, "{:?} out of order", op);
+                    new_pos += 1;
+                    edits += 1;
+                }
+            }
+        }
+        assert_eq!((old_pos, new_pos), (old.len(), new.len()));
+        edits
+    }
+
+    /// The fewest deletions and insertions turning `old` into `new`.
+    fn edit_distance<T: Eq>(old: &[T], new: &[T]) -> usize {
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+            }
+        }
+        old.len() + new.len() - 2 * lcs[0][0]
+    }
+
+    /// A xorshift generator, so the random cases are the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn sequence(&mut self) -> Vec<u8> {
+            let len = self.below(30);
+            // A small alphabet gives many repeated and many unique elements.
+            (0..len).map(|_| self.below(6) as u8).collect()
+        }
+    }
+
+    #[test]
+    fn empty_inputs() {
+        let empty: [u8; 0] = [];
+        for algorithm in ALGORITHMS {
+            assert!(diff(&empty, &empty, algorithm).is_empty());
+            assert_eq!(diff(&empty, b"ab", algorithm), vec![DiffOp::Insert { new: 0 }, DiffOp::Insert { new: 1 }]);
+            assert_eq!(diff(b"ab", &empty, algorithm), vec![DiffOp::Delete { old: 0 }, DiffOp::Delete { old: 1 }]);
+        }
+        assert_eq!(unified("", ""), "");
+        assert_eq!(unified("a\n", "a\n"), "");
+        assert_eq!(unified("", "a\nb\n"), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+        assert_eq!(unified("a\n", ""), "@@ -1 +0,0 @@\n-a\n");
+    }
+
+    #[test]
+    fn missing_trailing_newline() {
+        assert_eq!(
+            unified("a\nb", "a\nc"),
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n"
+        );
+        assert_eq!(unified("a", "a\n"), "@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+a\n");
+        assert_eq!(unified("a\n", "a\nb"), "@@ -1 +1,2 @@\n a\n+b\n\\ No newline at end of file\n");
+        assert_eq!(unified("x\nb", "y\nb"), "@@ -1,2 +1,2 @@\n-x\n+y\n b\n\\ No newline at end of file\n");
+    }
+
+    #[test]
+    fn hunk_ranges() {
+        let old: Vec<String> = (0..20).map(|i| format!("{}\n", i)).collect();
+        let mut new = old.clone();
+        new[0] = "first\n".to_string();
+        new[19] = "last\n".to_string();
+        assert_eq!(
+            unified(&old.concat(), &new.concat()),
+            "@@ -1,4 +1,4 @@\n-0\n+first\n 1\n 2\n 3\n@@ -17,4 +17,4 @@\n 16\n 17\n 18\n-19\n+last\n"
+        );
+
+        let change = |old: Range<usize>, new: Range<usize>| Change { old, new };
+        // Six unchanged lines between the changes: the contexts meet.
+        let merged = hunks(&[change(0..1, 0..1), change(7..8, 7..8)], 20, 20, 3);
+        assert_eq!(merged.len(), 1);
+        assert_eq!((merged[0].old.clone(), merged[0].new.clone()), (0..11, 0..11));
+        // Seven: they stay apart.
+        assert_eq!(hunks(&[change(0..1, 0..1), change(8..9, 8..9)], 20, 20, 3).len(), 2);
+        // Pure insertions and deletions, at either end.
+        let ends = hunks(&[change(0..0, 0..2), change(20..22, 22..22)], 22, 22, 3);
+        assert_eq!((ends[0].old.clone(), ends[0].new.clone()), (0..3, 0..5));
+        assert_eq!((ends[1].old.clone(), ends[1].new.clone()), (17..22, 19..22));
+        assert_eq!(hunk_range(&(0..0)), "0,0");
+        assert_eq!(hunk_range(&(4..4)), "4,0");
+        assert_eq!(hunk_range(&(4..5)), "5");
+        assert_eq!(hunk_range(&(4..7)), "5,3");
+    }
+
+    #[test]
+    fn random_edit_scripts() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let old = rng.sequence();
+            let new = rng.sequence();
+            for algorithm in ALGORITHMS {
+                let edits = check_script(&old, &new, &diff(&old, &new, algorithm));
+                // Myers is minimal; the others may give longer scripts.
+                if algorithm == DiffAlgorithm::Myers {
+                    assert_eq!(edits, edit_distance(&old, &new), "{:?} -> {:?}", old, new);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nul_bytes_near_the_start_mean_binary() {
+        assert!(!is_binary(b"plain text\n"));
+        assert!(!is_binary(b""));
+        assert!(is_binary(b"PNG\0\x01"));
+        let mut late = vec![b'a'; BINARY_CHECK_LEN];
+        late.push(0);
+        assert!(!is_binary(&late));
+    }
+
+    #[test]
+    fn word_diffs_mark_changed_words() {
+        let word_diff = |old: &str, new: &str, mode| {
+            let options = DiffOptions { word_diff: Some(mode), ..DiffOptions::default() };
+            String::from_utf8(unified_diff(old.as_bytes(), new.as_bytes(), &options)).unwrap()
+        };
+
+        assert_eq!(
+            word_diff("keep\nthe quick fox\n", "keep\nthe slow fox jumps\n", WordDiff::Plain),
+            "@@ -1,2 +1,2 @@\nkeep\nthe [-quick-]{+slow+} fox {+jumps+}\n"
+        );
+        assert_eq!(word_diff("a b c\n", "a c\n", WordDiff::Plain), "@@ -1 +1 @@\na[-b-] c\n");
+
+        let options = DiffOptions {
+            word_diff: Some(WordDiff::Color),
+            colors: Some(DiffColors::default()),
+            ..DiffOptions::default()
+        };
+        let colored = String::from_utf8(unified_diff(b"one two\n", b"one three\n", &options)).unwrap();
+        assert_eq!(colored, "\x1b[36m@@ -1 +1 @@\x1b[0m\none \x1b[31mtwo\x1b[0m\x1b[32mthree\x1b[0m\n");
+    }
+
+    #[test]
+    fn colored_lines_reset_before_the_newline() {
+        let options = DiffOptions { colors: Some(DiffColors::default()), ..DiffOptions::default() };
+        let colored = String::from_utf8(unified_diff(b"a\nb\n", b"a\nc", &options)).unwrap();
+        assert_eq!(
+            colored,
+            "\x1b[36m@@ -1,2 +1,2 @@\x1b[0m\n a\n\x1b[31m-b\x1b[0m\n\x1b[32m+c\x1b[0m\n\\ No newline at end of file\n"
+        );
+    }
+}
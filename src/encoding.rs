@@ -0,0 +1,406 @@
+//! Canonical binary encoding of objects.
+//!
+//! Every object is stored as `"<kind> <body length>\0"` followed by its body,
+//! and its hash is computed over exactly those bytes:
+//!
+//! * blob — the file content, unchanged.
+//! * tree — per entry `"<mode> <name>\0"` followed by the raw entry hash,
+//!   ordered by name with sub-trees compared as if their name ended in `/`.
+//! * commit — `tree`, `parent`, `author` and `committer` header lines, a blank
+//!   line and the message.
+//! * tag — `object`, `type`, `tag` and `tagger` header lines, a blank line and
+//!   the message.
+//!
+//! This is the same encoding git uses for loose objects, so git-compatible
+//! repositories differ only in their hash function and layout. Repositories
+//! written before this format stored objects as JSON; those are still decoded
+//! so `migrate` can rewrite them.
+
+use crate::{Blob, Commit, CrabGitError, Result, Tag, Tree, TreeEntry};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Object encoding version recorded as `core.objectversion` in the repository
+/// config. Repositories without it use the legacy JSON encoding.
+pub const OBJECT_VERSION: u32 = 2;
+
+pub const TREE_MODE: &str = "40000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
+        }
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ObjectKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(ObjectKind::Blob),
+            "tree" => Ok(ObjectKind::Tree),
+            "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
+            _ => Err(format!("unknown object type '{}'", s)),
+        }
+    }
+}
+
+/// Prefixes a body with its `"<kind> <len>\0"` header.
+pub fn frame(kind: ObjectKind, body: &[u8]) -> Vec<u8> {
+    let mut data = format!("{} {}\0", kind, body.len()).into_bytes();
+    data.extend_from_slice(body);
+    data
+}
+
+/// Splits stored object bytes into kind and body, checking the recorded size.
+pub fn unframe<'a>(hash: &str, data: &'a [u8]) -> Result<(ObjectKind, &'a [u8])> {
+    let corrupt = |reason: &str| corrupt(hash, reason);
+
+    let nul = data.iter().position(|&b| b == 0).ok_or_else(|| corrupt("missing header"))?;
+    let header = std::str::from_utf8(&data[..nul]).map_err(|_| corrupt("invalid header"))?;
+    let (kind, size) = header.split_once(' ').ok_or_else(|| corrupt("invalid header"))?;
+    let kind: ObjectKind = kind.parse().map_err(|err: String| corrupt(&err))?;
+    let size: usize = size.parse().map_err(|_| corrupt("invalid object size"))?;
+
+    let body = &data[nul + 1..];
+    if body.len() != size {
+        return Err(corrupt("object size does not match header"));
+    }
+    Ok((kind, body))
+}
+
+/// Whether stored object bytes use the legacy JSON encoding.
+pub fn is_legacy(data: &[u8]) -> bool {
+    data.first() == Some(&b'{')
+}
+
+fn corrupt(hash: &str, reason: &str) -> CrabGitError {
+    CrabGitError::CorruptObject {
+        hash: hash.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+pub fn encode_tree(entries: &BTreeMap<String, TreeEntry>) -> Result<Vec<u8>> {
+    let mut sorted: Vec<&TreeEntry> = entries.values().collect();
+    sorted.sort_by_cached_key(|entry| {
+        let mut key = entry.name.clone().into_bytes();
+        if !entry.is_file {
+            key.push(b'/');
+        }
+        key
+    });
+
+    let mut body = Vec::new();
+    for entry in sorted {
+        let raw = hex_decode(&entry.hash)
+            .ok_or_else(|| corrupt(&entry.hash, "invalid object name in tree entry"))?;
+        body.extend_from_slice(format!("{} {}\0", entry.mode, entry.name).as_bytes());
+        body.extend_from_slice(&raw);
+    }
+    Ok(body)
+}
+
+pub fn decode_tree(hash: &str, body: &[u8], hash_len: usize) -> Result<Tree> {
+    let mut entries = BTreeMap::new();
+    let mut pos = 0;
+    while pos < body.len() {
+        let nul = body[pos..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| corrupt(hash, "truncated tree entry"))?;
+        let header = std::str::from_utf8(&body[pos..pos + nul])
+            .map_err(|_| corrupt(hash, "invalid tree entry"))?;
+        let (mode, name) = header
+            .split_once(' ')
+            .ok_or_else(|| corrupt(hash, "invalid tree entry"))?;
+
+        let raw = body
+            .get(pos + nul + 1..pos + nul + 1 + hash_len)
+            .ok_or_else(|| corrupt(hash, "truncated tree entry"))?;
+        pos += nul + 1 + hash_len;
+
+        entries.insert(
+            name.to_string(),
+            TreeEntry {
+                mode: mode.to_string(),
+                hash: hex_encode(raw),
+                name: name.to_string(),
+                is_file: mode != TREE_MODE,
+            },
+        );
+    }
+
+    Ok(Tree {
+        hash: hash.to_string(),
+        entries,
+    })
+}
+
+pub fn encode_commit(commit: &Commit) -> Vec<u8> {
+    let mut body = format!("tree {}\n", commit.tree);
+    for parent in &commit.parents {
+        body.push_str(&format!("parent {}\n", parent));
+    }
+    let signature = signature(&commit.author, &commit.timestamp);
+    body.push_str(&format!("author {}\n", signature));
+    body.push_str(&format!("committer {}\n", signature));
+    body.push('\n');
+    body.push_str(&commit.message);
+    body.push('\n');
+    body.into_bytes()
+}
+
+pub fn decode_commit(hash: &str, body: &[u8]) -> Result<Commit> {
+    let (headers, message) = split_message(hash, body)?;
+
+    let mut tree = None;
+    let mut parents = Vec::new();
+    let mut author = None;
+    for (key, value) in headers {
+        match key {
+            "tree" => tree = Some(value.to_string()),
+            "parent" => parents.push(value.to_string()),
+            "author" => author = Some(parse_signature(hash, value)?),
+            _ => {}
+        }
+    }
+
+    let (author, timestamp) = author.ok_or_else(|| corrupt(hash, "commit without author"))?;
+    Ok(Commit {
+        hash: hash.to_string(),
+        parents,
+        tree: tree.ok_or_else(|| corrupt(hash, "commit without tree"))?,
+        author,
+        message,
+        timestamp,
+    })
+}
+
+pub fn encode_tag(tag: &Tag) -> Vec<u8> {
+    format!(
+        "object {}\ntype {}\ntag {}\ntagger {}\n\n{}\n",
+        tag.target,
+        tag.target_type,
+        tag.name,
+        signature(&tag.tagger, &tag.timestamp),
+        tag.message
+    )
+    .into_bytes()
+}
+
+pub fn decode_tag(hash: &str, body: &[u8]) -> Result<Tag> {
+    let (headers, message) = split_message(hash, body)?;
+
+    let mut target = None;
+    let mut target_type = None;
+    let mut name = None;
+    let mut tagger = None;
+    for (key, value) in headers {
+        match key {
+            "object" => target = Some(value.to_string()),
+            "type" => target_type = Some(value.to_string()),
+            "tag" => name = Some(value.to_string()),
+            "tagger" => tagger = Some(parse_signature(hash, value)?),
+            _ => {}
+        }
+    }
+
+    let (tagger, timestamp) = tagger.ok_or_else(|| corrupt(hash, "tag without tagger"))?;
+    Ok(Tag {
+        hash: hash.to_string(),
+        name: name.ok_or_else(|| corrupt(hash, "tag without name"))?,
+        target: target.ok_or_else(|| corrupt(hash, "tag without object"))?,
+        target_type: target_type.ok_or_else(|| corrupt(hash, "tag without type"))?,
+        tagger,
+        message,
+        timestamp,
+    })
+}
+
+pub fn decode_blob(hash: &str, body: &[u8]) -> Blob {
+    Blob {
+        hash: hash.to_string(),
+        content: body.to_vec(),
+    }
+}
+
+/// Formats an identity line. Identities without an email get an empty `<>`,
+/// which git requires.
+pub fn signature(identity: &str, timestamp: &DateTime<Utc>) -> String {
+    if identity.contains('<') {
+        format!("{} {} +0000", identity, timestamp.timestamp())
+    } else {
+        format!("{} <> {} +0000", identity, timestamp.timestamp())
+    }
+}
+
+/// Splits `"<identity> <unix time> <zone>"` into identity and time. `hash`
+/// names the object in errors.
+pub fn parse_signature(hash: &str, value: &str) -> Result<(String, DateTime<Utc>)> {
+    let mut parts = value.rsplitn(3, ' ');
+    let (_zone, seconds, identity) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(zone), Some(seconds), Some(identity)) => (zone, seconds, identity),
+        _ => return Err(corrupt(hash, "invalid signature")),
+    };
+    let timestamp = seconds
+        .parse::<i64>()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .ok_or_else(|| corrupt(hash, "invalid timestamp"))?;
+    Ok((identity.to_string(), timestamp))
+}
+
+/// Splits a commit or tag body into its header lines and message.
+fn split_message<'a>(hash: &str, body: &'a [u8]) -> Result<(Vec<(&'a str, &'a str)>, String)> {
+    let text = std::str::from_utf8(body).map_err(|_| corrupt(hash, "invalid UTF-8"))?;
+    let (headers, message) = text.split_once("\n\n").unwrap_or((text, ""));
+
+    let headers = headers
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .collect();
+    let message = message.strip_suffix('\n').unwrap_or(message).to_string();
+    Ok((headers, message))
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(byte: &str) -> String {
+        byte.repeat(32)
+    }
+
+    fn entry(name: &str, is_file: bool, hash: String) -> (String, TreeEntry) {
+        let mode = if is_file { "100644" } else { TREE_MODE };
+        (name.to_string(), TreeEntry { mode: mode.to_string(), hash, name: name.to_string(), is_file })
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let data = frame(ObjectKind::Blob, b"hello\0world");
+        assert!(data.starts_with(b"blob 11\0"));
+        assert_eq!(unframe("x", &data).unwrap(), (ObjectKind::Blob, &b"hello\0world"[..]));
+        assert!(!is_legacy(&data));
+        assert!(is_legacy(br#"{"hash":"x"}"#));
+
+        for bad in [&b"blob 12\0hello"[..], b"blob", b"bush 5\0hello", b"blob x\0hello"] {
+            assert!(matches!(unframe("x", bad), Err(CrabGitError::CorruptObject { .. })), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn tree_round_trip_sorts_like_git() {
+        let entries: BTreeMap<String, TreeEntry> =
+            [entry("a.txt", true, hash("aa")), entry("a", false, hash("bb")), entry("b", true, hash("cc"))]
+                .into_iter()
+                .collect();
+
+        let body = encode_tree(&entries).unwrap();
+        // Directories sort as if their name ended in `/`, after `a.txt`.
+        let names: Vec<&[u8]> = body
+            .split(|&b| b == 0)
+            .filter_map(|part| part.rsplit(|&b| b == b' ').next())
+            .collect();
+        assert_eq!(&names[..3], [&b"a.txt"[..], b"a", b"b"]);
+
+        let tree = decode_tree("t", &body, 32).unwrap();
+        assert_eq!(tree.entries.len(), 3);
+        for (name, original) in &entries {
+            let decoded = &tree.entries[name];
+            assert_eq!((&decoded.mode, &decoded.hash, decoded.is_file), (&original.mode, &original.hash, original.is_file));
+        }
+
+        assert!(decode_tree("t", &body[..body.len() - 1], 32).is_err());
+        assert!(encode_tree(&[entry("x", true, "zz".to_string())].into_iter().collect()).is_err());
+    }
+
+    #[test]
+    fn commit_round_trip() {
+        let commit = Commit {
+            hash: String::new(),
+            parents: vec![hash("aa"), hash("bb")],
+            tree: hash("cc"),
+            author: "Crab <crab@example.com>".to_string(),
+            message: "Subject\n\nBody line\n".to_string(),
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+        let body = encode_commit(&commit);
+        let text = String::from_utf8(body.clone()).unwrap();
+        assert!(text.starts_with(&format!("tree {}\nparent {}\nparent {}\n", commit.tree, commit.parents[0], commit.parents[1])));
+        assert!(text.contains("author Crab <crab@example.com> 1700000000 +0000\n"));
+
+        let decoded = decode_commit("h", &body).unwrap();
+        assert_eq!(decoded.hash, "h");
+        assert_eq!(decoded.parents, commit.parents);
+        assert_eq!(decoded.tree, commit.tree);
+        assert_eq!(decoded.author, commit.author);
+        assert_eq!(decoded.message, commit.message);
+        assert_eq!(decoded.timestamp, commit.timestamp);
+
+        assert!(decode_commit("h", b"tree x\n\nno author\n").is_err());
+    }
+
+    #[test]
+    fn tag_round_trip() {
+        let tag = Tag {
+            hash: String::new(),
+            name: "v1.0".to_string(),
+            target: hash("aa"),
+            target_type: "commit".to_string(),
+            tagger: "Crab <crab@example.com>".to_string(),
+            message: "Release".to_string(),
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+        let decoded = decode_tag("h", &encode_tag(&tag)).unwrap();
+        assert_eq!(
+            (decoded.name, decoded.target, decoded.target_type, decoded.tagger, decoded.message, decoded.timestamp),
+            (tag.name, tag.target, tag.target_type, tag.tagger, tag.message, tag.timestamp)
+        );
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(hex_encode(&[0x00, 0xab, 0xff]), "00abff");
+        assert_eq!(hex_decode("00abff"), Some(vec![0x00, 0xab, 0xff]));
+        assert_eq!(hex_decode("abc"), None);
+        assert_eq!(hex_decode("zz"), None);
+    }
+}
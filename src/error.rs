@@ -0,0 +1,171 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum CrabGitError {
+    /// The command was valid but cannot be carried out in the current state.
+    InvalidOperation(String),
+    NotARepository(PathBuf),
+    AlreadyExists(String),
+    ObjectNotFound(String),
+    CorruptObject { hash: String, reason: String },
+    RefNotFound(String),
+    /// Another process holds the lock file guarding the index.
+    IndexLocked(PathBuf),
+    /// Another process holds the lock file guarding a ref.
+    RefLocked(PathBuf),
+    /// A ref no longer had the value an update expected; `None` means missing.
+    StaleRef { name: String, expected: Option<String>, actual: Option<String> },
+    Conflict { reason: String, paths: Vec<String> },
+    Io(io::Error),
+    Serialization(serde_json::Error),
+}
+
+impl CrabGitError {
+    pub fn conflict(reason: impl Into<String>, paths: Vec<String>) -> Self {
+        CrabGitError::Conflict { reason: reason.into(), paths }
+    }
+
+    /// Process exit code reported by the CLI. Code 2 is left to clap for
+    /// usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CrabGitError::InvalidOperation(_) => 1,
+            CrabGitError::Conflict { .. } => 3,
+            CrabGitError::NotARepository(_) => 4,
+            CrabGitError::AlreadyExists(_) => 5,
+            CrabGitError::RefNotFound(_) => 6,
+            CrabGitError::ObjectNotFound(_) => 7,
+            CrabGitError::CorruptObject { .. } => 8,
+            CrabGitError::IndexLocked(_) => 9,
+            CrabGitError::Io(_) => 10,
+            CrabGitError::Serialization(_) => 11,
+            CrabGitError::RefLocked(_) => 12,
+            CrabGitError::StaleRef { .. } => 13,
+        }
+    }
+}
+
+impl fmt::Display for CrabGitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrabGitError::InvalidOperation(message) => write!(f, "{}", message),
+            CrabGitError::NotARepository(path) => {
+                write!(f, "Not a crab git repository: {}", path.display())
+            }
+            CrabGitError::AlreadyExists(what) => write!(f, "{} already exists", what),
+            CrabGitError::ObjectNotFound(hash) => write!(f, "Object {} not found", hash),
+            CrabGitError::CorruptObject { hash, reason } => {
+                write!(f, "Object {} is corrupt: {}", hash, reason)
+            }
+            CrabGitError::RefNotFound(name) => write!(f, "'{}' not found", name),
+            CrabGitError::IndexLocked(path) | CrabGitError::RefLocked(path) => write!(
+                f,
+                "Unable to lock {}: another crab git process seems to be running.\n\
+                 If no other process is running, remove the lock file and try again",
+                path.display()
+            ),
+            CrabGitError::StaleRef { name, expected, actual } => {
+                write!(f, "Cannot update ref '{}': ", name)?;
+                match (expected, actual) {
+                    (Some(expected), Some(actual)) => write!(f, "expected {} but it is {}", expected, actual),
+                    (Some(expected), None) => write!(f, "expected {} but it does not exist", expected),
+                    (None, Some(actual)) => write!(f, "it already exists at {}", actual),
+                    (None, None) => write!(f, "it changed concurrently"),
+                }
+            }
+            CrabGitError::Conflict { reason, paths } => {
+                write!(f, "{}", reason)?;
+                for path in paths {
+                    write!(f, "\n    {}", path)?;
+                }
+                Ok(())
+            }
+            CrabGitError::Io(err) => write!(f, "I/O error: {}", err),
+            CrabGitError::Serialization(err) => write!(f, "Serialization error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CrabGitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CrabGitError::Io(err) => Some(err),
+            CrabGitError::Serialization(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CrabGitError {
+    fn from(err: io::Error) -> Self {
+        CrabGitError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for CrabGitError {
+    fn from(err: serde_json::Error) -> Self {
+        CrabGitError::Serialization(err)
+    }
+}
+
+impl From<std::path::StripPrefixError> for CrabGitError {
+    fn from(err: std::path::StripPrefixError) -> Self {
+        CrabGitError::Io(io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            CrabGitError::InvalidOperation("x".to_string()),
+            CrabGitError::conflict("x", Vec::new()),
+            CrabGitError::NotARepository(PathBuf::from("/x")),
+            CrabGitError::AlreadyExists("x".to_string()),
+            CrabGitError::RefNotFound("x".to_string()),
+            CrabGitError::ObjectNotFound("x".to_string()),
+            CrabGitError::CorruptObject { hash: "x".to_string(), reason: "y".to_string() },
+            CrabGitError::IndexLocked(PathBuf::from("/x")),
+            CrabGitError::Io(io::Error::other("x")),
+            CrabGitError::Serialization(serde_json::from_str::<u8>("x").unwrap_err()),
+        ];
+        let mut codes: Vec<u8> = errors.iter().map(|err| err.exit_code()).collect();
+        assert!(!codes.contains(&0) && !codes.contains(&2));
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+
+    #[test]
+    fn display_messages() {
+        assert_eq!(
+            CrabGitError::NotARepository(PathBuf::from("/tmp/x")).to_string(),
+            "Not a crab git repository: /tmp/x"
+        );
+        assert_eq!(CrabGitError::AlreadyExists("Tag v1".to_string()).to_string(), "Tag v1 already exists");
+        assert_eq!(
+            CrabGitError::conflict("Merge conflict in:", vec!["a.txt".to_string(), "b.txt".to_string()]).to_string(),
+            "Merge conflict in:\n    a.txt\n    b.txt"
+        );
+        assert_eq!(
+            CrabGitError::CorruptObject { hash: "ab".to_string(), reason: "bad".to_string() }.to_string(),
+            "Object ab is corrupt: bad"
+        );
+    }
+
+    #[test]
+    fn commands_report_typed_errors() {
+        let test = crate::testing::TestRepo::new();
+        let err = crate::commands::commit(&test.repo, Some("empty".to_string()), None).unwrap_err();
+        assert!(matches!(err, CrabGitError::InvalidOperation(_)));
+        let err = crate::commands::checkout(&test.repo, "missing".to_string(), false).unwrap_err();
+        assert_eq!(err.exit_code(), CrabGitError::RefNotFound(String::new()).exit_code());
+        let err = crate::utils::get_repository(Some(std::env::temp_dir().join("crabgit-no-repo"))).unwrap_err();
+        assert!(matches!(err, CrabGitError::NotARepository(_)));
+    }
+}
@@ -0,0 +1,216 @@
+//! Reading and writing git's binary index (`DIRC`) for git-compatible
+//! repositories.
+//!
+//! The file is a 12-byte header (`DIRC`, version, entry count), the entries
+//! sorted by path and a trailing hash of everything before it. Each entry is
+//! ten 32-bit stat and mode fields, the raw object hash, 16 bits of flags
+//! (merge stage and path length) and the NUL-padded path.
+//!
+//! CrabGit does not track stat data, so it writes zeros there; git then
+//! re-hashes those files the next time it compares the index with the work
+//! tree. Conflicted paths are written as stage 2 ("ours") entries. Index
+//! extensions written by git are skipped when reading.
+
+use crate::encoding::{hex_decode, hex_encode};
+use crate::object_store::HashAlgorithm;
+use crate::{CrabGitError, Index, IndexEntry, Result};
+use std::collections::BTreeMap;
+
+const SIGNATURE: &[u8] = b"DIRC";
+const VERSION: u32 = 2;
+const HEADER_LEN: usize = 12;
+/// ctime, mtime (seconds and nanoseconds), dev, ino, mode, uid, gid, size.
+const STAT_LEN: usize = 40;
+const NAME_MASK: u16 = 0x0fff;
+const EXTENDED_FLAG: u16 = 0x4000;
+
+pub fn read(data: &[u8], hash_algorithm: HashAlgorithm) -> Result<Index> {
+    let hash_len = hash_algorithm.raw_len();
+    if data.len() < HEADER_LEN + hash_len || &data[..4] != SIGNATURE {
+        return Err(invalid("not a git index"));
+    }
+
+    let (content, checksum) = data.split_at(data.len() - hash_len);
+    if hex_encode(checksum) != hash_algorithm.hash(content) {
+        return Err(invalid("index checksum mismatch"));
+    }
+
+    let version = be_u32(content, 4)?;
+    if version != 2 && version != 3 {
+        return Err(invalid(&format!("unsupported index version {}", version)));
+    }
+    let count = be_u32(content, 8)? as usize;
+
+    let mut index = Index::default();
+    let mut pos = HEADER_LEN;
+    for _ in 0..count {
+        let start = pos;
+        let mode = be_u32(content, pos + 24)?;
+        let hash = content
+            .get(pos + STAT_LEN..pos + STAT_LEN + hash_len)
+            .ok_or_else(|| invalid("truncated index entry"))?;
+        pos += STAT_LEN + hash_len;
+
+        let flags = u16::from_be_bytes(
+            content
+                .get(pos..pos + 2)
+                .ok_or_else(|| invalid("truncated index entry"))?
+                .try_into()
+                .unwrap(),
+        );
+        pos += 2;
+        if flags & EXTENDED_FLAG != 0 {
+            pos += 2;
+        }
+
+        let rest = content.get(pos..).ok_or_else(|| invalid("truncated index entry"))?;
+        let name_len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("unterminated index path"))?;
+        let path = String::from_utf8(rest[..name_len].to_vec())
+            .map_err(|_| invalid("index path is not UTF-8"))?;
+        pos += name_len;
+        // Entries are padded with 1-8 NULs to a multiple of 8 bytes.
+        pos = start + (pos - start + 8) / 8 * 8;
+
+        let stage = (flags >> 12) & 0x3;
+        if stage != 0 {
+            index.unmerged.insert(path.clone());
+            // Keep "ours" for a conflicted path, or whichever stage exists.
+            if stage != 2 && index.entries.contains_key(&path) {
+                continue;
+            }
+        }
+        index.entries.insert(
+            path.clone(),
+            IndexEntry {
+                hash: hex_encode(hash),
+                mode: format!("{:o}", mode),
+                path,
+            },
+        );
+    }
+
+    Ok(index)
+}
+
+pub fn write(index: &Index, hash_algorithm: HashAlgorithm) -> Result<Vec<u8>> {
+    let entries: BTreeMap<&str, &IndexEntry> = index
+        .entries
+        .iter()
+        .map(|(path, entry)| (path.as_str(), entry))
+        .collect();
+
+    let mut data = Vec::new();
+    data.extend_from_slice(SIGNATURE);
+    data.extend_from_slice(&VERSION.to_be_bytes());
+    data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    for (path, entry) in entries {
+        let start = data.len();
+        let mode = u32::from_str_radix(&entry.mode, 8)
+            .map_err(|_| invalid(&format!("invalid mode '{}' for {}", entry.mode, path)))?;
+        let hash = hex_decode(&entry.hash)
+            .filter(|hash| hash.len() == hash_algorithm.raw_len())
+            .ok_or_else(|| invalid(&format!("invalid object name for {}", path)))?;
+
+        data.extend_from_slice(&[0; 24]);
+        data.extend_from_slice(&mode.to_be_bytes());
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&hash);
+
+        let stage: u16 = if index.unmerged.contains(path) { 2 } else { 0 };
+        let flags = (stage << 12) | (path.len().min(NAME_MASK as usize) as u16);
+        data.extend_from_slice(&flags.to_be_bytes());
+        data.extend_from_slice(path.as_bytes());
+
+        let padded = (data.len() - start + 8) / 8 * 8;
+        data.resize(start + padded, 0);
+    }
+
+    let checksum = hex_decode(&hash_algorithm.hash(&data)).unwrap();
+    data.extend_from_slice(&checksum);
+    Ok(data)
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| invalid("truncated index"))
+}
+
+fn invalid(reason: &str) -> CrabGitError {
+    CrabGitError::InvalidOperation(format!("invalid index: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, byte: &str) -> (String, IndexEntry) {
+        (
+            path.to_string(),
+            IndexEntry { hash: byte.repeat(20), mode: "100644".to_string(), path: path.to_string() },
+        )
+    }
+
+    /// Replaces the trailing checksum after editing the content.
+    fn checksummed(mut content: Vec<u8>) -> Vec<u8> {
+        let checksum = hex_decode(&HashAlgorithm::Sha1.hash(&content)).unwrap();
+        content.extend_from_slice(&checksum);
+        content
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut index = Index::default();
+        index.entries.extend([entry("b.txt", "bb"), entry("a/long-name.rs", "aa"), entry("c", "cc")]);
+        index.unmerged.insert("c".to_string());
+
+        let data = write(&index, HashAlgorithm::Sha1).unwrap();
+        assert_eq!(&data[..4], SIGNATURE);
+
+        let read_back = read(&data, HashAlgorithm::Sha1).unwrap();
+        assert_eq!(read_back.unmerged, index.unmerged);
+        assert_eq!(read_back.entries.len(), 3);
+        for (path, entry) in &index.entries {
+            assert_eq!(read_back.entries[path].hash, entry.hash);
+            assert_eq!(read_back.entries[path].mode, entry.mode);
+        }
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let index = Index { entries: [entry("a", "aa")].into_iter().collect(), ..Index::default() };
+        let data = write(&index, HashAlgorithm::Sha1).unwrap();
+
+        let mut flipped = data.clone();
+        flipped[20] ^= 1;
+        assert!(read(&flipped, HashAlgorithm::Sha1).is_err());
+        assert!(read(b"DIRC", HashAlgorithm::Sha1).is_err());
+
+        let mut version = data[..data.len() - 20].to_vec();
+        version[7] = 4;
+        assert!(read(&checksummed(version), HashAlgorithm::Sha1).is_err());
+
+        // More entries than the file holds.
+        let mut count = data[..data.len() - 20].to_vec();
+        count[11] = 2;
+        assert!(read(&checksummed(count), HashAlgorithm::Sha1).is_err());
+    }
+
+    #[test]
+    fn extended_flags_at_the_end_are_truncated() {
+        let mut content = Vec::new();
+        content.extend_from_slice(SIGNATURE);
+        content.extend_from_slice(&3u32.to_be_bytes());
+        content.extend_from_slice(&1u32.to_be_bytes());
+        content.extend_from_slice(&[0; STAT_LEN]);
+        content.extend_from_slice(&[0xaa; 20]);
+        content.extend_from_slice(&(EXTENDED_FLAG | 1).to_be_bytes());
+
+        let err = read(&checksummed(content), HashAlgorithm::Sha1).unwrap_err();
+        assert_eq!(err.to_string(), "invalid index: truncated index entry");
+    }
+}
@@ -0,0 +1,140 @@
+//! ASCII rendering of the commit graph for `log --graph`, in git's style:
+//!
+//! ```text
+//! *   merge
+//! |\
+//! | * feature
+//! * | main
+//! |/
+//! * base
+//! ```
+//!
+//! Each column tracks the commit expected next on that line of history.
+//! Commits must be fed children first, as [`crate::rev_parse::topo_order`]
+//! returns them.
+
+/// The rows drawn for one commit.
+#[derive(Debug)]
+pub struct GraphRows {
+    /// The columns with `*` marking the commit.
+    pub commit: String,
+    /// Prefix for further lines of the commit's text.
+    pub padding: String,
+    /// Lines connecting the commit to its parents and shifting the other
+    /// columns into place.
+    pub edges: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Graph {
+    columns: Vec<String>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Graph::default()
+    }
+
+    pub fn next(&mut self, hash: &str, parents: &[String]) -> GraphRows {
+        let col = match self.columns.iter().position(|column| column == hash) {
+            Some(col) => col,
+            None => {
+                self.columns.push(hash.to_string());
+                self.columns.len() - 1
+            }
+        };
+
+        let width = self.columns.len();
+        let commit = row(width, |i| if i == col { '*' } else { '|' });
+        let padding = row(width, |i| if i == col && parents.is_empty() { ' ' } else { '|' });
+
+        // The commit's column continues as its parents; a column whose
+        // commit is already tracked elsewhere joins that column.
+        let mut next: Vec<String> = Vec::new();
+        let mut targets = Vec::new();
+        for (i, column) in self.columns.iter().enumerate() {
+            let hashes = if i == col { parents } else { std::slice::from_ref(column) };
+            for hash in hashes {
+                if !next.contains(hash) {
+                    next.push(hash.clone());
+                }
+                targets.push((i, hash.clone()));
+            }
+        }
+        let mut moves: Vec<(usize, usize)> = targets
+            .into_iter()
+            .map(|(from, hash)| (from, next.iter().position(|column| *column == hash).unwrap_or(from)))
+            .collect();
+
+        // Lines move one column per row until every line reaches its place.
+        let mut edges = Vec::new();
+        while moves.iter().any(|(from, to)| from != to) {
+            let width = moves.iter().map(|(from, to)| from.max(to) + 1).max().unwrap_or(0);
+            let mut line = vec![' '; 2 * width];
+            for (from, to) in moves.iter_mut() {
+                if *from < *to {
+                    line[2 * *from + 1] = '\\';
+                    *from += 1;
+                } else if *from > *to {
+                    line[2 * *from - 1] = '/';
+                    *from -= 1;
+                } else {
+                    line[2 * *from] = '|';
+                }
+            }
+            edges.push(line.into_iter().collect::<String>().trim_end().to_string());
+        }
+
+        self.columns = next;
+        GraphRows { commit, padding, edges }
+    }
+}
+
+/// Columns separated by spaces, `cell(i)` giving column `i`'s character.
+fn row(width: usize, cell: impl Fn(usize) -> char) -> String {
+    (0..width)
+        .map(|i| cell(i).to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(commits: &[(&str, &[&str])]) -> Vec<String> {
+        let mut graph = Graph::new();
+        let mut lines = Vec::new();
+        for (hash, parents) in commits {
+            let parents: Vec<String> = parents.iter().map(|parent| parent.to_string()).collect();
+            let rows = graph.next(hash, &parents);
+            lines.push(format!("{} {}", rows.commit, hash));
+            lines.extend(rows.edges);
+        }
+        lines
+    }
+
+    #[test]
+    fn merges_fork_and_join() {
+        let lines = draw(&[
+            ("merge", &["main", "feature"]),
+            ("feature", &["base"]),
+            ("main", &["base"]),
+            ("base", &[]),
+        ]);
+        assert_eq!(lines, ["* merge", "|\\", "| * feature", "* | main", "|/", "* base"]);
+    }
+
+    #[test]
+    fn unrelated_tips_get_their_own_column() {
+        let lines = draw(&[("b", &["a"]), ("other", &[]), ("a", &[])]);
+        assert_eq!(lines, ["* b", "| * other", "* a"]);
+    }
+
+    #[test]
+    fn padding_ends_with_the_root() {
+        let mut graph = Graph::new();
+        assert_eq!(graph.next("child", &["root".to_string()]).padding, "|");
+        assert_eq!(graph.next("root", &[]).padding, " ");
+    }
+}
@@ -0,0 +1,491 @@
+use crate::{Repository, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub const IGNORE_FILE: &str = ".crabignore";
+/// The per-directory ignore file of git-compatible repositories.
+pub const GIT_IGNORE_FILE: &str = ".gitignore";
+
+/// What `add` and `status` skipped before ignore files existed: dotfiles and
+/// `target/`. Repositories created back then have no `info/exclude`, and use
+/// these patterns in its place.
+const LEGACY_EXCLUDE: &str = ".*\n!.crabignore\n!.crabattributes\ntarget/\n";
+
+/// A single line of an ignore file.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    /// File the pattern was read from and its 1-based line number.
+    pub source: PathBuf,
+    pub line: usize,
+    /// The pattern exactly as written.
+    pub text: String,
+    pub negated: bool,
+    dir_only: bool,
+    anchored: bool,
+    glob: String,
+    /// Directory (relative to the work tree, `""` for the root) the pattern is
+    /// relative to.
+    base: String,
+}
+
+impl Pattern {
+    fn parse(text: &str, source: &Path, line: usize, base: &str) -> Option<Self> {
+        let mut glob = trim_trailing_spaces(text);
+        if glob.is_empty() || glob.starts_with('#') {
+            return None;
+        }
+
+        // A leading `!` negates the pattern, `\!` and `\#` escape a literal one.
+        let negated = glob.starts_with('!');
+        if negated || glob.starts_with("\\!") || glob.starts_with("\\#") {
+            glob = &glob[1..];
+        }
+
+        let dir_only = glob.ends_with('/');
+        let glob = glob.trim_end_matches('/');
+        if glob.is_empty() {
+            return None;
+        }
+
+        let anchored = glob.contains('/');
+        Some(Pattern {
+            source: source.to_path_buf(),
+            line,
+            text: text.to_string(),
+            negated,
+            dir_only,
+            anchored,
+            glob: glob.trim_start_matches('/').to_string(),
+            base: base.to_string(),
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(self.base.as_str()).and_then(|p| p.strip_prefix('/')) {
+                Some(relative) => relative,
+                None => return false,
+            }
+        };
+
+        if self.anchored {
+            glob_match(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            glob_match(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+fn trim_trailing_spaces(text: &str) -> &str {
+    let mut end = text.len();
+    while end > 0 && text.as_bytes()[end - 1] == b' ' {
+        if end > 1 && text.as_bytes()[end - 2] == b'\\' {
+            break;
+        }
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Ignore rules for a work tree, combining (from lowest to highest priority)
+/// the global ignore file, `info/exclude` in the repository directory and
+/// every `.crabignore` (`.gitignore` in git-compatible repositories) from the
+/// root down to the path being checked. Per-directory files are read
+/// lazily and cached. The repository directory itself is never matched.
+pub struct IgnoreRules {
+    work_dir: PathBuf,
+    git_dir_name: String,
+    ignore_file: &'static str,
+    base_patterns: Vec<Pattern>,
+    dir_patterns: RefCell<HashMap<String, Vec<Pattern>>>,
+}
+
+impl IgnoreRules {
+    pub fn load(repo: &Repository) -> Result<Self> {
+        let mut base_patterns = Vec::new();
+        if let Some(global) = global_ignore_file() {
+            base_patterns.extend(read_patterns(&global, "")?);
+        }
+        let exclude = repo.git_dir.join("info").join("exclude");
+        if exclude.exists() || repo.git_compatible {
+            base_patterns.extend(read_patterns(&exclude, "")?);
+        } else {
+            base_patterns.extend(parse_patterns(LEGACY_EXCLUDE, Path::new("<built-in exclude>"), ""));
+        }
+
+        Ok(Self {
+            work_dir: repo.work_dir.clone(),
+            git_dir_name: repo
+                .git_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            ignore_file: if repo.git_compatible { GIT_IGNORE_FILE } else { IGNORE_FILE },
+            base_patterns,
+            dir_patterns: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Whether a work-tree relative path is ignored. A path inside an ignored
+    /// directory is always ignored, as in git.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.matching_pattern(path, is_dir)
+            .is_some_and(|pattern| !pattern.negated)
+    }
+
+    /// The pattern that decides whether `path` is ignored, if any. A returned
+    /// negated pattern means the path was explicitly re-included.
+    pub fn matching_pattern(&self, path: &str, is_dir: bool) -> Option<Pattern> {
+        let path = path.trim_matches('/');
+        if path.split('/').any(|component| component == self.git_dir_name) {
+            return None;
+        }
+
+        let mut prefix_end = 0;
+        while let Some(offset) = path[prefix_end..].find('/') {
+            let dir = &path[..prefix_end + offset];
+            if let Some(pattern) = self.last_match(dir, true).filter(|p| !p.negated) {
+                return Some(pattern);
+            }
+            prefix_end += offset + 1;
+        }
+
+        self.last_match(path, is_dir)
+    }
+
+    /// Lists the files under `dir` that are not ignored, as work-tree relative
+    /// paths. Ignored directories and the repository directory are not
+    /// descended into.
+    pub fn unignored_files(&self, dir: &Path) -> Vec<String> {
+        WalkDir::new(dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| match self.relative_path(entry.path()) {
+                Some(path) if path.is_empty() => true,
+                Some(path) => path != self.git_dir_name && !self.is_ignored(&path, entry.file_type().is_dir()),
+                None => false,
+            })
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| self.relative_path(entry.path()))
+            .collect()
+    }
+
+    fn relative_path(&self, path: &Path) -> Option<String> {
+        path.strip_prefix(&self.work_dir)
+            .ok()
+            .map(|relative| relative.to_string_lossy().replace("\\", "/"))
+    }
+
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<Pattern> {
+        let mut found = self
+            .base_patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .cloned();
+
+        let parent = path.rfind('/').map(|i| &path[..i]).unwrap_or("");
+        let mut dirs = vec![""];
+        dirs.extend(parent.match_indices('/').map(|(i, _)| &parent[..i]));
+        if !parent.is_empty() {
+            dirs.push(parent);
+        }
+
+        for dir in dirs {
+            self.load_dir(dir);
+            let cache = self.dir_patterns.borrow();
+            if let Some(pattern) = cache[dir].iter().rev().find(|p| p.matches(path, is_dir)) {
+                found = Some(pattern.clone());
+            }
+        }
+
+        found
+    }
+
+    fn load_dir(&self, dir: &str) {
+        if self.dir_patterns.borrow().contains_key(dir) {
+            return;
+        }
+        let file = self.work_dir.join(dir).join(self.ignore_file);
+        let patterns = read_patterns(&file, dir).unwrap_or_default();
+        self.dir_patterns.borrow_mut().insert(dir.to_string(), patterns);
+    }
+}
+
+fn global_ignore_file() -> Option<PathBuf> {
+    if let Some(config) = std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(config).join("crab_git").join("ignore"));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("crab_git").join("ignore"))
+}
+
+fn read_patterns(file: &Path, base: &str) -> Result<Vec<Pattern>> {
+    if !file.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(parse_patterns(&fs::read_to_string(file)?, file, base))
+}
+
+fn parse_patterns(content: &str, source: &Path, base: &str) -> Vec<Pattern> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, source, i + 1, base))
+        .collect()
+}
+
+/// Matches `text` against a gitignore glob: `*` and `?` never match `/`,
+/// `[...]` is a character class and `**` spans directories when it forms a
+/// whole path component.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' if pattern.get(p + 1) == Some(&b'*') && (p == 0 || pattern[p - 1] == b'/') => {
+                let rest = &pattern[p + 2..];
+                if rest.is_empty() {
+                    return true;
+                }
+                if rest[0] == b'/' {
+                    let rest = &rest[1..];
+                    // `**/` matches zero or more leading directories.
+                    return (t..=text.len())
+                        .filter(|&i| i == t || text[i - 1] == b'/')
+                        .any(|i| glob_match(rest, &text[i..]));
+                }
+                return (t..=text.len()).any(|i| glob_match(rest, &text[i..]));
+            }
+            b'*' => {
+                let mut rest = p + 1;
+                while pattern.get(rest) == Some(&b'*') {
+                    rest += 1;
+                }
+                let mut i = t;
+                loop {
+                    if glob_match(&pattern[rest..], &text[i..]) {
+                        return true;
+                    }
+                    if i >= text.len() || text[i] == b'/' {
+                        return false;
+                    }
+                    i += 1;
+                }
+            }
+            b'?' => {
+                if t >= text.len() || text[t] == b'/' {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' => {
+                let Some(&c) = text.get(t) else { return false };
+                match match_class(&pattern[p..], c) {
+                    Some((true, len)) => {
+                        p += len;
+                        t += 1;
+                    }
+                    Some((false, _)) => return false,
+                    // An unterminated class is matched literally.
+                    None => {
+                        if c != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if text.get(t) != Some(&pattern[p + 1]) {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if text.get(t) != Some(&c) {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+/// Matches `c` against the character class at the start of `pattern`,
+/// returning whether it matched and the length of the class.
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        if pattern[i] == b']' && !first {
+            return Some((matched != negated && c != b'/', i + 1));
+        }
+        first = false;
+
+        let mut low = pattern[i];
+        if low == b'\\' && i + 1 < pattern.len() {
+            i += 1;
+            low = pattern[i];
+        }
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&b| b != b']') {
+            let high = pattern[i + 2];
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= low == c;
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        glob_match(pattern.as_bytes(), text.as_bytes())
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob("*.log", "debug.log"));
+        assert!(!glob("*.log", "logs/debug.log"));
+        assert!(glob("file?.txt", "file1.txt"));
+        assert!(!glob("file?.txt", "file/.txt"));
+        assert!(glob("[a-c]x", "bx"));
+        assert!(!glob("[!a-c]x", "bx"));
+        assert!(glob("[^a-c]x", "dx"));
+        assert!(glob("[x", "[x"));
+        assert!(glob("\\*", "*"));
+        assert!(!glob("\\*", "a"));
+    }
+
+    #[test]
+    fn glob_double_star() {
+        assert!(glob("**/foo", "foo"));
+        assert!(glob("**/foo", "a/b/foo"));
+        assert!(glob("a/**", "a/b/c"));
+        assert!(glob("a/**/b", "a/b"));
+        assert!(glob("a/**/b", "a/x/y/b"));
+        assert!(!glob("a/**/b", "ab"));
+        assert!(!glob("a*/b", "a/x/b"));
+    }
+
+    #[test]
+    fn ignore_file_rules() {
+        let test = TestRepo::new();
+        test.write(
+            ".crabignore",
+            "# comment\n*.log\n!keep.log\nbuild/\n/root-only.txt\ndocs/*.tmp\n\\#hash\ntrailing \n",
+        );
+        let rules = IgnoreRules::load(&test.repo).unwrap();
+
+        assert!(rules.is_ignored("debug.log", false));
+        assert!(rules.is_ignored("src/debug.log", false));
+        assert!(!rules.is_ignored("keep.log", false));
+        assert!(rules.is_ignored("build", true));
+        assert!(!rules.is_ignored("build", false));
+        assert!(rules.is_ignored("build/out.txt", false));
+        assert!(rules.is_ignored("root-only.txt", false));
+        assert!(!rules.is_ignored("sub/root-only.txt", false));
+        assert!(rules.is_ignored("docs/a.tmp", false));
+        assert!(!rules.is_ignored("docs/sub/a.tmp", false));
+        assert!(rules.is_ignored("#hash", false));
+        assert!(rules.is_ignored("trailing", false));
+        assert!(!rules.is_ignored("# comment", false));
+
+        let pattern = rules.matching_pattern("keep.log", false).unwrap();
+        assert!(pattern.negated);
+        assert_eq!((pattern.line, pattern.text.as_str()), (3, "!keep.log"));
+    }
+
+    #[test]
+    fn files_in_ignored_directories_cannot_be_re_included() {
+        let test = TestRepo::new();
+        test.write(".crabignore", "build/\n!build/keep.txt\n");
+        let rules = IgnoreRules::load(&test.repo).unwrap();
+        assert!(rules.is_ignored("build/keep.txt", false));
+    }
+
+    #[test]
+    fn nested_ignore_files_are_relative_and_take_priority() {
+        let test = TestRepo::new();
+        test.write(".crabignore", "*.txt\n");
+        test.write("sub/.crabignore", "!notes.txt\n/local.md\n");
+        fs::write(test.repo.git_dir.join("info").join("exclude"), "*.md\n!readme.md\n").unwrap();
+        let rules = IgnoreRules::load(&test.repo).unwrap();
+
+        assert!(rules.is_ignored("notes.txt", false));
+        assert!(!rules.is_ignored("sub/notes.txt", false));
+        assert!(rules.is_ignored("sub/other.txt", false));
+        assert!(rules.is_ignored("sub/local.md", false));
+        assert!(!rules.is_ignored("readme.md", false));
+        assert!(rules.is_ignored("sub/deeper/local2.md", false));
+        assert!(rules.matching_pattern(".crab_git/HEAD", false).is_none());
+    }
+
+    #[test]
+    fn unignored_files_skip_ignored_directories() {
+        let test = TestRepo::new();
+        test.write(".crabignore", "target/\n*.o\n");
+        test.write("src/main.rs", "");
+        test.write("src/main.o", "");
+        test.write("target/debug/app", "");
+        let rules = IgnoreRules::load(&test.repo).unwrap();
+
+        assert_eq!(rules.unignored_files(&test.root), [".crabignore", "src/main.rs"]);
+    }
+
+    #[test]
+    fn repositories_without_an_exclude_file_keep_the_old_exclusions() {
+        let test = TestRepo::new();
+        fs::remove_file(test.repo.git_dir.join("info").join("exclude")).unwrap();
+        let rules = IgnoreRules::load(&test.repo).unwrap();
+
+        assert!(rules.is_ignored(".env", false));
+        assert!(rules.is_ignored(".idea/workspace.xml", false));
+        assert!(rules.is_ignored("target", true));
+        assert!(!rules.is_ignored(".crabignore", false));
+        assert!(!rules.is_ignored(".crabattributes", false));
+        assert!(!rules.is_ignored("src/lib.rs", false));
+        drop(test);
+
+        let test = TestRepo::new();
+        let rules = IgnoreRules::load(&test.repo).unwrap();
+        assert!(!rules.is_ignored(".env", false));
+    }
+
+    #[test]
+    fn git_compatible_repositories_read_gitignore() {
+        let test = TestRepo::git();
+        test.write(".gitignore", "*.log\n");
+        test.write(".crabignore", "*.tmp\n");
+        let rules = IgnoreRules::load(&test.repo).unwrap();
+
+        assert!(rules.is_ignored("debug.log", false));
+        assert!(!rules.is_ignored("scratch.tmp", false));
+    }
+}
@@ -1,10 +1,32 @@
+#![allow(non_snake_case)]
+
 pub mod commands;
 pub mod utils;
 pub mod object_store;
+pub mod diff_engine;
+pub mod error;
+pub mod ignore;
+pub mod pack;
+pub mod encoding;
+pub mod config;
+pub mod git_index;
+pub mod rev_parse;
+pub mod reflog;
+pub mod lockfile;
+pub mod refs;
+pub mod graph;
+pub mod renames;
+pub mod attributes;
+pub mod binary_patch;
+pub mod color;
+
+#[cfg(test)]
+pub(crate) mod testing;
 
 use chrono::{DateTime, Utc};
+use object_store::HashAlgorithm;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,7 +38,7 @@ pub struct Blob {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tree {
     pub hash: String,
-    pub entries: HashMap<String, TreeEntry>,
+    pub entries: BTreeMap<String, TreeEntry>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,7 +52,7 @@ pub struct TreeEntry {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Commit {
    pub hash: String,
-   pub parent: Option<String>,
+   pub parents: Vec<String>,
    pub tree: String,
    pub author: String,
    pub message: String,
@@ -38,8 +60,22 @@ pub struct Commit {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub hash: String,
+    pub name: String,
+    pub target: String,
+    pub target_type: String,
+    pub tagger: String,
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Index {
     pub entries: HashMap<String, IndexEntry>,
+    /// Paths left with conflicts by a merge; they must be re-added before committing.
+    #[serde(default)]
+    pub unmerged: BTreeSet<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,6 +89,9 @@ pub struct IndexEntry {
 pub struct Repository {
     pub git_dir: PathBuf,
     pub work_dir: PathBuf,
+    pub hash_algorithm: HashAlgorithm,
+    /// Whether the repository uses git's `.git` layout and index format.
+    pub git_compatible: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,4 +106,6 @@ pub struct Remote {
     pub url: String,
 }
 
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub use error::CrabGitError;
+
+pub type Result<T> = std::result::Result<T, CrabGitError>;
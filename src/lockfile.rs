@@ -0,0 +1,118 @@
+//! Lock files make rewrites of refs and the index atomic. The new content is
+//! written to `<file>.lock`, which is created exclusively so only one process
+//! can hold it, and then renamed over the file. A crash leaves either the old
+//! or the new file, never a truncated one; a lock that is dropped without
+//! being committed is removed.
+
+use crate::Result;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: File,
+    committed: bool,
+}
+
+impl LockFile {
+    /// Takes the lock for `path`, creating missing parent directories.
+    /// Returns `None` when another process holds it.
+    pub fn acquire(path: &Path) -> Result<Option<Self>> {
+        let lock_path = lock_path(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+            Ok(file) => Ok(Some(LockFile { path: path.to_path_buf(), lock_path, file, committed: false })),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn lock_path(&self) -> &Path {
+        &self.lock_path
+    }
+
+    pub fn write_all(&mut self, content: &[u8]) -> Result<()> {
+        self.file.write_all(content)?;
+        Ok(())
+    }
+
+    /// Flushes the new content to disk and renames it over the file.
+    pub fn commit(mut self) -> Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.lock_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Removes the file instead of replacing it, then releases the lock.
+    pub fn commit_delete(mut self) -> Result<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        fs::remove_file(&self.lock_path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crabgit-lockfile-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("sub/file")
+    }
+
+    #[test]
+    fn only_one_holder_at_a_time() {
+        let path = scratch("holder");
+        let lock = LockFile::acquire(&path).unwrap().unwrap();
+        assert!(LockFile::acquire(&path).unwrap().is_none());
+
+        drop(lock);
+        assert!(!lock_path(&path).exists());
+        assert!(LockFile::acquire(&path).unwrap().is_some());
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn commit_replaces_and_commit_delete_removes() {
+        let path = scratch("commit");
+        let mut lock = LockFile::acquire(&path).unwrap().unwrap();
+        lock.write_all(b"new").unwrap();
+        assert!(!path.exists());
+        lock.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!lock_path(&path).exists());
+
+        LockFile::acquire(&path).unwrap().unwrap().commit_delete().unwrap();
+        assert!(!path.exists());
+        assert!(!lock_path(&path).exists());
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+}
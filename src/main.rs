@@ -1,6 +1,9 @@
+#![allow(non_snake_case)]
+
 use clap::{Parser, Subcommand};
-use CrabGit::{Result, commands, utils};
+use CrabGit::{Repository, Result, commands::{self, DiffSummary, LogFormat, LogOptions, PorcelainVersion, ResetMode, StatusFormat}, color::{ColorMode, DiffColors, RESET_COLOR, RUST_COLOR}, diff_engine::{DiffAlgorithm, DiffOptions, WordDiff}, object_store::HashAlgorithm, renames::{RenameOptions, Similarity}, utils};
 use std::path::PathBuf;
+use std::process::ExitCode;
 
 pub const BANNER: &str = r#"
  $$$$$$\  $$$$$$$\   $$$$$$\  $$$$$$$\   $$$$$$\  $$$$$$\ $$$$$$$$\ 
@@ -16,13 +19,10 @@ $$ |  $$\ $$ |  $$ |$$ |  $$ |$$ |  $$ |$$ |  $$ |  $$ |     $$ |
                                                                     
 "#;
 
-pub const RUST_COLOR: &str = "\x1b[38;2;217;155;121m";
-pub const RESET_COLOR: &str = "\x1b[0m";               
 
 #[derive(Parser)]
 #[command(name = "crab_git")]
 #[command(about = "A simple git implementation in Rust", version)]
-
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -32,27 +32,179 @@ struct Cli {
 enum Commands {
     Init {
         #[arg(help = "Directory to initialize")]
-        path: Option<PathBuf>
+        path: Option<PathBuf>,
+
+        #[arg(long, help = "Use git's .git layout and index so git can open the repository")]
+        git: bool,
+
+        #[arg(long, help = "Object hash: sha1 or sha256 (default sha1 with --git, sha256 otherwise)")]
+        object_format: Option<HashAlgorithm>
     },
 
     Add {
         #[arg(help = "Files to add")]
-        paths: Vec<String>
+        paths: Vec<String>,
+
+        #[arg(short, long, help = "Add files even if they are ignored")]
+        force: bool
     },
 
     Commit {
-        #[arg(help = "Commit message")]
-        message: String,
+        #[arg(help = "Commit message, defaults to the pending merge message")]
+        message: Option<String>,
         
         #[arg(short, long, help = "Author name")]
         author: Option<String>
     },
 
-    Status,
+    Rm {
+        #[arg(required = true, help = "Files to remove")]
+        paths: Vec<String>,
+
+        #[arg(long, help = "Only remove from the index, keep the files")]
+        cached: bool,
+
+        #[arg(short, help = "Allow recursive removal of directories")]
+        recursive: bool,
+
+        #[arg(short, long, help = "Remove even if the files have staged or local changes")]
+        force: bool
+    },
+
+    Mv {
+        #[arg(help = "Tracked file or directory to move")]
+        source: String,
+
+        #[arg(help = "New path, or an existing directory to move into")]
+        destination: String,
+
+        #[arg(short, long, help = "Overwrite an existing destination file")]
+        force: bool
+    },
+
+    Reset {
+        #[arg(long, group = "mode", help = "Only move the branch, keep the index and working tree")]
+        soft: bool,
+
+        #[arg(long, group = "mode", help = "Also reset the index (default)")]
+        mixed: bool,
+
+        #[arg(long, group = "mode", help = "Also reset the working tree, discarding local changes")]
+        hard: bool,
+
+        #[arg(help = "Branch, tag or commit to reset to (defaults to HEAD)")]
+        commit: Option<String>,
+
+        #[arg(last = true, help = "Only reset the index entries of these paths")]
+        paths: Vec<String>
+    },
+
+    Status {
+        #[arg(short, long, group = "format", help = "Show one `XY path` line per changed path")]
+        short: bool,
+
+        #[arg(
+            long,
+            group = "format",
+            value_name = "VERSION",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "v1",
+            help = "Stable output for scripts: v1 (default) or v2"
+        )]
+        porcelain: Option<PorcelainVersion>,
+
+        #[arg(long, group = "format", help = "Print the status as JSON")]
+        json: bool,
+
+        #[arg(short, long, help = "Include the branch and its upstream in short and porcelain output")]
+        branch: bool
+    },
 
     Log {
+        #[arg(help = "Revisions or ranges (A..B, A...B, ^A) to show, defaults to HEAD")]
+        revisions: Vec<String>,
+
         #[arg(short, long, help = "Maximum number of commits to display")]
-        max_count: Option<usize>
+        max_count: Option<usize>,
+
+        #[arg(long, group = "pretty_format", help = "One line per commit: abbreviated hash and subject")]
+        oneline: bool,
+
+        #[arg(
+            long,
+            visible_alias = "pretty",
+            group = "pretty_format",
+            value_name = "FORMAT",
+            help = "oneline, short, medium (default) or a format string such as \"%h %an %s\""
+        )]
+        format: Option<LogFormat>,
+
+        #[arg(long, help = "Abbreviate commit hashes")]
+        abbrev_commit: bool,
+
+        #[arg(long, conflicts_with = "reverse", help = "Draw the branch and merge graph")]
+        graph: bool,
+
+        #[arg(long, help = "Show the branches and tags pointing at each commit")]
+        decorate: bool,
+
+        #[arg(long, help = "Show the oldest commits first")]
+        reverse: bool,
+
+        #[arg(long, value_name = "PATTERN", help = "Only commits whose author contains PATTERN (repeatable)")]
+        author: Vec<String>,
+
+        #[arg(long, value_name = "PATTERN", help = "Only commits whose message contains PATTERN (repeatable)")]
+        grep: Vec<String>,
+
+        #[arg(short = 'i', long = "regexp-ignore-case", help = "Match --author and --grep case-insensitively")]
+        ignore_case: bool,
+
+        #[arg(long, visible_alias = "after", value_name = "DATE", help = "Only commits at or after DATE (2026-01-31, 2.weeks.ago, yesterday)")]
+        since: Option<String>,
+
+        #[arg(long, visible_alias = "before", value_name = "DATE", help = "Only commits at or before DATE")]
+        until: Option<String>,
+
+        #[arg(short, long, help = "Show the patch of each commit")]
+        patch: bool,
+
+        #[arg(long, group = "summary", help = "Show the files each commit changed, with line counts")]
+        stat: bool,
+
+        #[arg(long, group = "summary", help = "Show inserted and deleted line counts per file, tab-separated")]
+        numstat: bool,
+
+        #[arg(long, group = "summary", help = "Show the names of the files each commit changed")]
+        name_only: bool,
+
+        #[arg(long, group = "summary", help = "Show the names and status (A, M, D, R...) of changed files")]
+        name_status: bool,
+
+        #[arg(long, help = "Follow the history of a single file across renames")]
+        follow: bool,
+
+        #[arg(long, value_name = "WHEN", num_args = 0..=1, require_equals = true, default_missing_value = "always", help = "Color the output: auto (on a terminal), always or never")]
+        color: Option<ColorMode>,
+
+        #[arg(long, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "plain", help = "Show changed words instead of lines: plain ([-old-]{+new+}) or color")]
+        word_diff: Option<WordDiff>,
+
+        #[arg(short = 'M', long = "find-renames", value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "", help = "Detect renames of files at least N similar (default 50%)")]
+        find_renames: Option<Similarity>,
+
+        #[arg(short = 'C', long = "find-copies", value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "", help = "Detect copies as well as renames")]
+        find_copies: Option<Similarity>,
+
+        #[arg(long, conflicts_with_all = ["find_renames", "find_copies"], help = "Show renamed files as a deletion and an addition")]
+        no_renames: bool,
+
+        #[arg(long, help = "Write binary files as patches that can be applied")]
+        binary: bool,
+
+        #[arg(last = true, help = "Only commits changing these files or directories")]
+        paths: Vec<String>
     },
 
     Branch {
@@ -64,32 +216,346 @@ enum Commands {
     },
 
     Diff {
-        #[arg(help = "Files to diff (optional)")]
-        paths: Vec<String>
+        #[arg(help = "Commits (one to compare the working tree with, two or A..B to compare with each other), then files to diff")]
+        args: Vec<String>,
+
+        #[arg(long, visible_alias = "staged", help = "Compare the index with HEAD, or with the given commit")]
+        cached: bool,
+
+        #[arg(last = true, help = "Files to diff")]
+        paths: Vec<String>,
+
+        #[arg(short = 'U', long = "unified", default_value_t = 3, help = "Number of context lines")]
+        context: usize,
+
+        #[arg(long, default_value = "myers", help = "Diff algorithm: myers, patience or histogram")]
+        diff_algorithm: DiffAlgorithm,
+
+        #[arg(short = 'M', long = "find-renames", value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "", help = "Detect renames of files at least N similar (default 50%)")]
+        find_renames: Option<Similarity>,
+
+        #[arg(short = 'C', long = "find-copies", value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "", help = "Detect copies as well as renames")]
+        find_copies: Option<Similarity>,
+
+        #[arg(long, conflicts_with_all = ["find_renames", "find_copies"], help = "Show renamed files as a deletion and an addition")]
+        no_renames: bool,
+
+        #[arg(long, help = "Write binary files as patches that can be applied")]
+        binary: bool,
+
+        #[arg(long, group = "summary", help = "Show a histogram of changed lines per file instead of the patch")]
+        stat: bool,
+
+        #[arg(long, group = "summary", help = "Show inserted and deleted line counts per file, tab-separated")]
+        numstat: bool,
+
+        #[arg(long, group = "summary", help = "Show only the names of changed files")]
+        name_only: bool,
+
+        #[arg(long, group = "summary", help = "Show the names and status (A, M, D, R...) of changed files")]
+        name_status: bool,
+
+        #[arg(short, long, help = "Show the patch after the summary")]
+        patch: bool,
+
+        #[arg(long, value_name = "WHEN", num_args = 0..=1, require_equals = true, default_missing_value = "always", help = "Color the output: auto (on a terminal), always or never")]
+        color: Option<ColorMode>,
+
+        #[arg(long, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "plain", help = "Show changed words instead of lines: plain ([-old-]{+new+}) or color")]
+        word_diff: Option<WordDiff>
     },
 
     Checkout {
         #[arg(help = "Branch or commit to checkout")]
-        branch_or_commit: String
+        branch_or_commit: String,
+
+        #[arg(short, long, help = "Discard local changes that would be overwritten")]
+        force: bool
+    },
+
+    Merge {
+        #[arg(help = "Branch or commit to merge into the current branch")]
+        branch: String,
+
+        #[arg(short, long, help = "Author name")]
+        author: Option<String>
+    },
+
+    Tag {
+        #[arg(help = "Tag name (lists tags when omitted)")]
+        name: Option<String>,
+
+        #[arg(help = "Branch, tag or commit to tag (defaults to HEAD)")]
+        target: Option<String>,
+
+        #[arg(short, long, help = "Create an annotated tag with this message")]
+        message: Option<String>,
+
+        #[arg(short, long, help = "Delete tag")]
+        delete: bool,
+
+        #[arg(short, long, help = "Tagger name for annotated tags")]
+        tagger: Option<String>
+    },
+
+    #[command(about = "Print the object names that revisions resolve to")]
+    RevParse {
+        #[arg(required = true, help = "Revisions such as HEAD~2, main^2, v1.0, a1b2c3d or A..B")]
+        revisions: Vec<String>,
+
+        #[arg(
+            long,
+            value_name = "LENGTH",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "7",
+            help = "Abbreviate hashes to a unique prefix of at least LENGTH characters"
+        )]
+        short: Option<usize>,
+
+        #[arg(long, help = "Require exactly one revision")]
+        verify: bool,
+
+        #[arg(long, help = "Print the short ref name instead of a hash")]
+        abbrev_ref: bool
+    },
+
+    #[command(about = "Show or expire the history of HEAD and branch updates", args_conflicts_with_subcommands = true)]
+    Reflog {
+        #[command(subcommand)]
+        action: Option<ReflogAction>,
+
+        #[arg(help = "Ref whose log to show (defaults to HEAD)")]
+        reference: Option<String>,
+
+        #[arg(short = 'n', long, help = "Maximum number of entries to display")]
+        max_count: Option<usize>
+    },
+
+    CheckIgnore {
+        #[arg(required = true, help = "Paths to check against the ignore rules")]
+        paths: Vec<String>,
+
+        #[arg(short, long, help = "Show the matching pattern and where it is defined")]
+        verbose: bool
+    },
+
+    #[command(alias = "repack", about = "Pack loose objects into a delta-compressed packfile")]
+    Gc,
+
+    #[command(about = "Convert a repository with JSON-encoded objects to the binary object format")]
+    Migrate
+}
+
+#[derive(Subcommand)]
+enum ReflogAction {
+    #[command(about = "Show the log of a ref, newest entry first")]
+    Show {
+        #[arg(help = "Ref whose log to show (defaults to HEAD)")]
+        reference: Option<String>,
+
+        #[arg(short = 'n', long, help = "Maximum number of entries to display")]
+        max_count: Option<usize>
+    },
+
+    #[command(about = "Remove old reflog entries")]
+    Expire {
+        #[arg(long, value_name = "TIME", help = "Remove entries older than TIME (default 90.days.ago; `now` removes all)")]
+        expire: Option<String>,
+
+        #[arg(long, help = "Expire the logs of all refs")]
+        all: bool,
+
+        #[arg(help = "Refs whose logs to expire")]
+        refs: Vec<String>
     }
 }
 
+/// Rename detection as `-M`, `-C` and `--no-renames` ask; renames are
+/// detected at the default similarity when none is given.
+fn rename_options(find_renames: Option<Similarity>, find_copies: Option<Similarity>, no_renames: bool) -> Option<RenameOptions> {
+    if no_renames {
+        return None;
+    }
+    let threshold = find_copies.or(find_renames).map_or(RenameOptions::default().threshold, |Similarity(n)| n);
+    Some(RenameOptions { threshold, copies: find_copies.is_some() })
+}
+
+/// The diff colors `--color` or the config ask for; `--word-diff=color`
+/// turns colors on unless `--color` says otherwise.
+fn diff_colors(repo: &Repository, color: Option<ColorMode>, word_diff: Option<WordDiff>) -> Result<Option<DiffColors>> {
+    let color = color.or((word_diff == Some(WordDiff::Color)).then_some(ColorMode::Always));
+    DiffColors::for_repo(repo, color)
+}
+
+fn diff_summary(stat: bool, numstat: bool, name_only: bool, name_status: bool) -> Option<DiffSummary> {
+    [
+        (stat, DiffSummary::Stat),
+        (numstat, DiffSummary::Numstat),
+        (name_only, DiffSummary::NameOnly),
+        (name_status, DiffSummary::NameStatus),
+    ]
+    .into_iter()
+    .find_map(|(chosen, summary)| chosen.then_some(summary))
+}
+
 fn execute_with_repo(command: Commands) -> Result<()> {
     let repo = utils::get_repository(None)?;
-    
+    if !matches!(command, Commands::Migrate) {
+        utils::ensure_current_format(&repo)?;
+    }
+
     match command {
-        Commands::Add { paths } => commands::add(&repo, paths),
+        Commands::Add { paths, force } => commands::add(&repo, paths, force),
         Commands::Commit { message, author } => commands::commit(&repo, message, author),
-        Commands::Status => commands::status(&repo),
-        Commands::Log { max_count } => commands::log(&repo, max_count),
+        Commands::Rm { paths, cached, recursive, force } => commands::rm(&repo, paths, cached, recursive, force),
+        Commands::Mv { source, destination, force } => commands::mv(&repo, source, destination, force),
+        Commands::Reset { soft, mixed: _, hard, commit, paths } => {
+            let mode = if soft {
+                ResetMode::Soft
+            } else if hard {
+                ResetMode::Hard
+            } else {
+                ResetMode::Mixed
+            };
+            commands::reset(&repo, mode, commit, paths)
+        }
+        Commands::Status { short, porcelain, json, branch } => {
+            let format = match porcelain {
+                Some(version) => StatusFormat::Porcelain(version),
+                None if json => StatusFormat::Json,
+                None if short => StatusFormat::Short,
+                None => StatusFormat::Long,
+            };
+            commands::status(&repo, format, branch)
+        }
+        Commands::Log {
+            revisions,
+            max_count,
+            oneline,
+            format,
+            abbrev_commit,
+            graph,
+            decorate,
+            reverse,
+            author,
+            grep,
+            ignore_case,
+            since,
+            until,
+            patch,
+            stat,
+            numstat,
+            name_only,
+            name_status,
+            follow,
+            color,
+            word_diff,
+            find_renames,
+            find_copies,
+            no_renames,
+            binary,
+            paths,
+        } => {
+            let options = LogOptions {
+                max_count,
+                format: if oneline { LogFormat::Oneline } else { format.unwrap_or_default() },
+                abbrev_commit: abbrev_commit || oneline,
+                graph,
+                decorate,
+                reverse,
+                authors: author,
+                grep,
+                ignore_case,
+                since,
+                until,
+                patch,
+                summary: diff_summary(stat, numstat, name_only, name_status),
+                follow,
+                diff: DiffOptions {
+                    renames: rename_options(find_renames, find_copies, no_renames),
+                    binary,
+                    colors: diff_colors(&repo, color, word_diff)?,
+                    word_diff,
+                    ..DiffOptions::default()
+                },
+            };
+            commands::log(&repo, revisions, paths, options)
+        }
         Commands::Branch { name, delete } => commands::branch(&repo, name, delete),
-        Commands::Diff { paths } => commands::diff(&repo, paths),
-        Commands::Checkout { branch_or_commit } => commands::checkout(&repo, branch_or_commit),
+        Commands::Diff {
+            args,
+            cached,
+            paths,
+            context,
+            diff_algorithm,
+            find_renames,
+            find_copies,
+            no_renames,
+            binary,
+            stat,
+            numstat,
+            name_only,
+            name_status,
+            patch,
+            color,
+            word_diff,
+        } => {
+            let options = DiffOptions {
+                context,
+                algorithm: diff_algorithm,
+                renames: rename_options(find_renames, find_copies, no_renames),
+                binary,
+                colors: diff_colors(&repo, color, word_diff)?,
+                word_diff,
+            };
+            let summary = diff_summary(stat, numstat, name_only, name_status);
+            commands::diff(&repo, args, paths, cached, options, summary, patch)
+        }
+        Commands::Checkout { branch_or_commit, force } => commands::checkout(&repo, branch_or_commit, force),
+        Commands::Merge { branch, author } => commands::merge(&repo, branch, author),
+        Commands::Tag { name, target, message, delete, tagger } => {
+            commands::tag(&repo, name, target, message, delete, tagger)
+        }
+        Commands::RevParse { revisions, short, verify, abbrev_ref } => {
+            commands::rev_parse(&repo, revisions, short, verify, abbrev_ref)
+        }
+        Commands::Reflog { action, reference, max_count } => match action {
+            Some(ReflogAction::Show { reference, max_count }) => commands::reflog(&repo, reference, max_count),
+            Some(ReflogAction::Expire { expire, all, refs }) => commands::reflog_expire(&repo, expire, all, refs),
+            None => commands::reflog(&repo, reference, max_count),
+        },
+        Commands::CheckIgnore { paths, verbose } => commands::check_ignore(&repo, paths, verbose),
+        Commands::Gc => commands::gc(&repo),
+        Commands::Migrate => commands::migrate(&repo),
         _ => Ok(()),
     }
 }
 
-fn main() -> Result<()> {
+/// Rewrites git's `-M50%` and `-C50%` as `-M=50%` and `-C=50%`, the form
+/// the parser accepts for an optional value.
+fn attach_similarity(args: Vec<String>) -> Vec<String> {
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    args.into_iter()
+        .enumerate()
+        .map(|(i, arg)| match arg.strip_prefix("-M").or_else(|| arg.strip_prefix("-C")) {
+            Some(value) if i < end && value.starts_with(|c: char| c.is_ascii_digit()) => {
+                format!("{}={}", &arg[..2], value)
+            }
+            _ => arg,
+        })
+        .collect()
+}
+
+fn run(command: Commands) -> Result<()> {
+    if let Commands::Init { path, git, object_format } = command {
+        return commands::init(path, git, object_format);
+    }
+
+    execute_with_repo(command)
+}
+
+fn main() -> ExitCode {
     let args: Vec<String> = std::env::args().collect();
     
     if args.len() == 1 {
@@ -97,15 +563,16 @@ fn main() -> Result<()> {
         println!("{}🦀 CrabGit - Git Implementation from Scratch in Rust{}", RUST_COLOR, RESET_COLOR);
         println!("{}Usage: {} <COMMAND>{}", RUST_COLOR, args[0], RESET_COLOR);
         println!("{}Run with --help for more information{}", RUST_COLOR, RESET_COLOR);
-        return Ok(());
+        return ExitCode::SUCCESS;
     }
     
-    let cli = Cli::parse();
+    let cli = Cli::parse_from(attach_similarity(args));
 
-    if let Commands::Init { path } = cli.command {
-        return commands::init(path);
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(err.exit_code())
+        }
     }
-
-
-    execute_with_repo(cli.command)
-}
\ No newline at end of file
+}
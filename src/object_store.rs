@@ -1,57 +1,218 @@
-use crate::{Blob, Tree, Commit, Result, Repository};
+use crate::{Blob, Tree, TreeEntry, Commit, Tag, IndexEntry, CrabGitError, Result, Repository};
+use crate::encoding::{self, ObjectKind};
+use crate::pack::{self, Pack};
+use sha1::Sha1;
 use sha2::{Sha256, Digest};
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
 use flate2::read::ZlibDecoder;
 use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Hash function used to name objects. Git repositories default to SHA-1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha1,
+    #[default]
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn hash(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha1 => format!("{:x}", Sha1::digest(data)),
+            HashAlgorithm::Sha256 => format!("{:x}", Sha256::digest(data)),
+        }
+    }
+
+    /// Length of a raw hash in bytes.
+    pub fn raw_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(format!("unknown object format '{}', expected sha1 or sha256", s)),
+        }
+    }
+}
 
 pub struct  ObjectStore {
     objects_dir: std::path::PathBuf,
+    hash_algorithm: HashAlgorithm,
+    packs: OnceCell<Vec<Pack>>,
 }
 
 impl ObjectStore {
     pub fn new(repo: &Repository) -> Self {
         Self {
             objects_dir: repo.git_dir.join("objects"),
+            hash_algorithm: repo.hash_algorithm,
+            packs: OnceCell::new(),
         }
     }
 
+    pub fn pack_dir(&self) -> std::path::PathBuf {
+        self.objects_dir.join("pack")
+    }
+
+    /// Packs are opened on first use and cached for the lifetime of the store.
+    fn packs(&self) -> Result<&[Pack]> {
+        if self.packs.get().is_none() {
+            let packs = pack::open_packs(&self.pack_dir())?;
+            let _ = self.packs.set(packs);
+        }
+        Ok(self.packs.get().unwrap())
+    }
+
     pub fn init(&self) -> Result<()> {
         fs::create_dir_all(&self.objects_dir)?;
         Ok(())
     }
 
-    pub fn hash_content(content: &[u8]) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(content);
-        format!("{:x}", hasher.finalize())
+    /// Hash of an object with the given kind and body, computed over its
+    /// canonical `"<kind> <len>\0<body>"` encoding.
+    pub fn hash_object(&self, kind: ObjectKind, body: &[u8]) -> String {
+        self.hash_algorithm.hash(&encoding::frame(kind, body))
+    }
+
+    /// Hash a file's content would have as a blob, without storing it.
+    pub fn hash_blob(&self, content: &[u8]) -> String {
+        self.hash_object(ObjectKind::Blob, content)
     }
 
     pub fn store_blob(&self, content: &[u8]) -> Result<String> {
-        let hash = Self::hash_content(content);
-        let blob = Blob {
-            hash: hash.clone(),
-            content: content.to_vec(),
-        };
-
-        let serialized = serde_json::to_vec(&blob)?;
-        self.store_object(&hash, &serialized)?;
-        Ok(hash)
+        self.store_encoded(ObjectKind::Blob, content)
     }
 
+    /// Stores a tree object, returning its hash. `tree.hash` is ignored.
     pub fn store_tree(&self, tree: &Tree) -> Result<String> {
-        let serialized = serde_json::to_vec(tree)?;
-        self.store_object(&tree.hash, &serialized)?;
-        Ok(tree.hash.clone())
+        self.store_encoded(ObjectKind::Tree, &encoding::encode_tree(&tree.entries)?)
+    }
+
+    /// Builds one tree object per directory from a flat `path -> entry` map and
+    /// stores them bottom-up, returning the hash of the root tree.
+    pub fn write_tree(&self, entries: &HashMap<String, IndexEntry>) -> Result<String> {
+        let entries: Vec<(&str, &IndexEntry)> = entries
+            .iter()
+            .map(|(path, entry)| (path.as_str(), entry))
+            .collect();
+        self.write_subtree(&entries)
+    }
+
+    fn write_subtree(&self, entries: &[(&str, &IndexEntry)]) -> Result<String> {
+        let mut tree_entries = BTreeMap::new();
+        let mut subdirs: BTreeMap<&str, Vec<(&str, &IndexEntry)>> = BTreeMap::new();
+
+        for (path, entry) in entries {
+            match path.split_once('/') {
+                Some((dir, rest)) => subdirs.entry(dir).or_default().push((rest, entry)),
+                None => {
+                    tree_entries.insert(
+                        path.to_string(),
+                        TreeEntry {
+                            mode: entry.mode.clone(),
+                            hash: entry.hash.clone(),
+                            name: path.to_string(),
+                            is_file: true,
+                        },
+                    );
+                }
+            }
+        }
+
+        for (dir, children) in subdirs {
+            let hash = self.write_subtree(&children)?;
+            tree_entries.insert(
+                dir.to_string(),
+                TreeEntry {
+                    mode: encoding::TREE_MODE.to_string(),
+                    hash,
+                    name: dir.to_string(),
+                    is_file: false,
+                },
+            );
+        }
+
+        let body = encoding::encode_tree(&tree_entries)?;
+        let hash = self.hash_object(ObjectKind::Tree, &body);
+
+        // Identical directories hash identically, so an unchanged sub-tree is already stored.
+        if !self.object_exists(&hash) {
+            self.store_object(&hash, &encoding::frame(ObjectKind::Tree, &body))?;
+        }
+        Ok(hash)
     }
 
+    /// Walks a tree and all of its sub-trees, returning every file entry keyed
+    /// by its path relative to the root tree.
+    pub fn read_tree(&self, hash: &str) -> Result<BTreeMap<String, TreeEntry>> {
+        let mut files = BTreeMap::new();
+        self.read_subtree(hash, "", &mut files)?;
+        Ok(files)
+    }
+
+    fn read_subtree(
+        &self,
+        hash: &str,
+        prefix: &str,
+        files: &mut BTreeMap<String, TreeEntry>,
+    ) -> Result<()> {
+        let tree = self.load_tree(hash)?;
+        for (name, entry) in tree.entries {
+            let path = format!("{}{}", prefix, name);
+            if entry.is_file {
+                files.insert(path, entry);
+            } else {
+                self.read_subtree(&entry.hash, &format!("{}/", path), files)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores a commit object, returning its hash. `commit.hash` is ignored.
     pub fn store_commit(&self, commit: &Commit) -> Result<String> {
-        let serialized = serde_json::to_vec(commit)?;
-        self.store_object(&commit.hash, &serialized)?;
-        Ok(commit.hash.clone())
+        self.store_encoded(ObjectKind::Commit, &encoding::encode_commit(commit))
     }
 
+    /// Stores an annotated tag object, returning its hash. `tag.hash` is ignored.
+    pub fn store_tag(&self, tag: &Tag) -> Result<String> {
+        self.store_encoded(ObjectKind::Tag, &encoding::encode_tag(tag))
+    }
+
+    fn store_encoded(&self, kind: ObjectKind, body: &[u8]) -> Result<String> {
+        let hash = self.hash_object(kind, body);
+        self.store_object(&hash, &encoding::frame(kind, body))?;
+        Ok(hash)
+    }
+
+    /// Writes already encoded object bytes as a loose object.
     pub fn store_object(&self, hash: &str, content: &[u8]) -> Result<()> {
         let (dir_name, file_name) = hash.split_at(2);
         let obj_dir = self.objects_dir.join(dir_name);
@@ -67,39 +228,266 @@ impl ObjectStore {
     }
 
     pub fn load_blob(&self, hash: &str) -> Result<Blob> {
-        let content = self.load_object(hash)?;
-        let blob: Blob = serde_json::from_slice(&content)?;
-        Ok(blob)
+        let data = self.load_object(hash)?;
+        if encoding::is_legacy(&data) {
+            return Self::decode_legacy(hash, &data);
+        }
+        let body = Self::expect_kind(hash, &data, ObjectKind::Blob)?;
+        Ok(encoding::decode_blob(hash, body))
     }
 
     pub fn load_tree(&self, hash: &str) -> Result<Tree> {
-        let content = self.load_object(hash)?;
-        let tree: Tree = serde_json::from_slice(&content)?;
-        Ok(tree)
+        let data = self.load_object(hash)?;
+        if encoding::is_legacy(&data) {
+            return Self::decode_legacy(hash, &data);
+        }
+        let body = Self::expect_kind(hash, &data, ObjectKind::Tree)?;
+        encoding::decode_tree(hash, body, self.hash_algorithm.raw_len())
     }
 
     pub fn load_commit(&self, hash: &str) -> Result<Commit> {
-        let content = self.load_object(hash)?;
-        let commit: Commit = serde_json::from_slice(&content)?;
-        Ok(commit)
+        let data = self.load_object(hash)?;
+        if encoding::is_legacy(&data) {
+            return Self::decode_legacy(hash, &data);
+        }
+        let body = Self::expect_kind(hash, &data, ObjectKind::Commit)?;
+        encoding::decode_commit(hash, body)
+    }
+
+    pub fn load_tag(&self, hash: &str) -> Result<Tag> {
+        let data = self.load_object(hash)?;
+        if encoding::is_legacy(&data) {
+            return Self::decode_legacy(hash, &data);
+        }
+        let body = Self::expect_kind(hash, &data, ObjectKind::Tag)?;
+        encoding::decode_tag(hash, body)
+    }
+
+    /// The kind of a stored object, read from its header.
+    pub fn object_kind(&self, hash: &str) -> Result<ObjectKind> {
+        let data = self.load_object(hash)?;
+        if encoding::is_legacy(&data) {
+            // Legacy objects carry no kind, only the fields of their struct.
+            let object: serde_json::Value = Self::decode_legacy(hash, &data)?;
+            let kind = if object.get("target_type").is_some() {
+                ObjectKind::Tag
+            } else if object.get("parents").is_some() {
+                ObjectKind::Commit
+            } else if object.get("entries").is_some() {
+                ObjectKind::Tree
+            } else {
+                ObjectKind::Blob
+            };
+            return Ok(kind);
+        }
+        Ok(encoding::unframe(hash, &data)?.0)
     }
 
-    fn load_object(&self, hash: &str) -> Result<Vec<u8>> {
+    fn expect_kind<'a>(hash: &str, data: &'a [u8], expected: ObjectKind) -> Result<&'a [u8]> {
+        let (kind, body) = encoding::unframe(hash, data)?;
+        if kind != expected {
+            return Err(CrabGitError::CorruptObject {
+                hash: hash.to_string(),
+                reason: format!("expected a {}, found a {}", expected, kind),
+            });
+        }
+        Ok(body)
+    }
+
+    fn decode_legacy<T: serde::de::DeserializeOwned>(hash: &str, content: &[u8]) -> Result<T> {
+        serde_json::from_slice(content).map_err(|err| CrabGitError::CorruptObject {
+            hash: hash.to_string(),
+            reason: err.to_string(),
+        })
+    }
+
+    /// Reads an object's raw content, from its loose file or from a pack.
+    pub fn load_object(&self, hash: &str) -> Result<Vec<u8>> {
         let (dir_name, file_name) = hash.split_at(2);
         let obj_path = self.objects_dir.join(dir_name).join(file_name);
 
+        if !obj_path.exists() {
+            for pack in self.packs()? {
+                if let Some(offset) = pack.find(hash) {
+                    return pack.read(offset);
+                }
+            }
+            return Err(CrabGitError::ObjectNotFound(hash.to_string()));
+        }
+
         let compressed = fs::read(obj_path)?;
         let mut decoder = ZlibDecoder::new(&compressed[..]);
         let mut content = Vec::new();
-        decoder.read_to_end(&mut content)?;
+        decoder.read_to_end(&mut content).map_err(|err| CrabGitError::CorruptObject {
+            hash: hash.to_string(),
+            reason: err.to_string(),
+        })?;
 
         Ok(content)
     }
 
     pub fn object_exists(&self, hash: &str) -> bool {
+        if hash.len() != self.hash_algorithm.raw_len() * 2 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return false;
+        }
         let (dir_name, file_name) = hash.split_at(2);
         let obj_path = self.objects_dir.join(dir_name).join(file_name);
         obj_path.exists()
+            || self
+                .packs()
+                .is_ok_and(|packs| packs.iter().any(|pack| pack.find(hash).is_some()))
+    }
+
+    /// Hashes of all objects stored as individual loose files.
+    pub fn loose_objects(&self) -> Result<Vec<String>> {
+        let mut hashes = Vec::new();
+        if !self.objects_dir.is_dir() {
+            return Ok(hashes);
+        }
+
+        for dir in fs::read_dir(&self.objects_dir)? {
+            let dir = dir?;
+            let dir_name = dir.file_name().to_string_lossy().to_string();
+            if dir_name.len() != 2 || !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file_name = file?.file_name().to_string_lossy().to_string();
+                hashes.push(format!("{}{}", dir_name, file_name));
+            }
+        }
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    /// Hashes of all objects stored in packs.
+    pub fn packed_objects(&self) -> Result<Vec<String>> {
+        Ok(self.packs()?.iter().flat_map(|pack| pack.hashes()).collect())
+    }
+
+    /// Hashes of all objects whose name starts with the hex `prefix`, which
+    /// must be at least two characters long.
+    pub fn objects_with_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let prefix = prefix.to_lowercase();
+        if prefix.len() < 2 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Ok(Vec::new());
+        }
+
+        let mut hashes = Vec::new();
+        let (dir_name, file_prefix) = prefix.split_at(2);
+        let dir = self.objects_dir.join(dir_name);
+        if dir.is_dir() {
+            for file in fs::read_dir(dir)? {
+                let file_name = file?.file_name().to_string_lossy().to_string();
+                if file_name.starts_with(file_prefix) {
+                    hashes.push(format!("{}{}", dir_name, file_name));
+                }
+            }
+        }
+        hashes.extend(self.packed_objects()?.into_iter().filter(|hash| hash.starts_with(&prefix)));
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
     }
 
-}
\ No newline at end of file
+    pub fn remove_loose_object(&self, hash: &str) -> Result<()> {
+        let (dir_name, file_name) = hash.split_at(2);
+        let obj_dir = self.objects_dir.join(dir_name);
+        fs::remove_file(obj_dir.join(file_name))?;
+        if fs::read_dir(&obj_dir)?.next().is_none() {
+            fs::remove_dir(obj_dir)?;
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    fn entry(object_store: &ObjectStore, path: &str, content: &str) -> (String, IndexEntry) {
+        let hash = object_store.store_blob(content.as_bytes()).unwrap();
+        (
+            path.to_string(),
+            IndexEntry { hash, mode: "100644".to_string(), path: path.to_string() },
+        )
+    }
+
+    #[test]
+    fn write_tree_nests_directories() {
+        let test = TestRepo::new();
+        let object_store = ObjectStore::new(&test.repo);
+        let entries: HashMap<_, _> = [
+            entry(&object_store, "README.md", "readme"),
+            entry(&object_store, "src/main.rs", "main"),
+            entry(&object_store, "src/commands/add.rs", "add"),
+        ]
+        .into_iter()
+        .collect();
+
+        let root = object_store.load_tree(&object_store.write_tree(&entries).unwrap()).unwrap();
+        assert_eq!(root.entries.keys().collect::<Vec<_>>(), ["README.md", "src"]);
+        assert!(root.entries["README.md"].is_file);
+
+        let src = &root.entries["src"];
+        assert!(!src.is_file);
+        assert_eq!(src.mode, encoding::TREE_MODE);
+        let src = object_store.load_tree(&src.hash).unwrap();
+        assert_eq!(src.entries.keys().collect::<Vec<_>>(), ["commands", "main.rs"]);
+    }
+
+    #[test]
+    fn read_tree_round_trips_paths() {
+        let test = TestRepo::new();
+        let object_store = ObjectStore::new(&test.repo);
+        let entries: HashMap<_, _> = [
+            entry(&object_store, "a.txt", "a"),
+            entry(&object_store, "dir/b.txt", "b"),
+            entry(&object_store, "dir/sub/c.txt", "c"),
+        ]
+        .into_iter()
+        .collect();
+
+        let files = object_store.read_tree(&object_store.write_tree(&entries).unwrap()).unwrap();
+        assert_eq!(files.len(), entries.len());
+        for (path, entry) in &entries {
+            assert_eq!(files[path].hash, entry.hash);
+            assert_eq!(files[path].name, path.rsplit('/').next().unwrap());
+        }
+    }
+
+    #[test]
+    fn unchanged_directories_share_a_tree() {
+        let test = TestRepo::new();
+        let object_store = ObjectStore::new(&test.repo);
+        let mut entries: HashMap<_, _> = [
+            entry(&object_store, "lib/x.rs", "x"),
+            entry(&object_store, "top.txt", "one"),
+        ]
+        .into_iter()
+        .collect();
+        let first = object_store.load_tree(&object_store.write_tree(&entries).unwrap()).unwrap();
+
+        let (path, changed) = entry(&object_store, "top.txt", "two");
+        entries.insert(path, changed);
+        let second = object_store.load_tree(&object_store.write_tree(&entries).unwrap()).unwrap();
+
+        assert_ne!(first.hash, second.hash);
+        assert_eq!(first.entries["lib"].hash, second.entries["lib"].hash);
+    }
+
+    #[test]
+    fn commits_store_the_nested_tree() {
+        let test = TestRepo::new();
+        test.write("docs/guide.md", "guide");
+        test.write("notes.txt", "notes");
+        test.commit("first");
+
+        let object_store = ObjectStore::new(&test.repo);
+        let commit = object_store.load_commit(&test.head()).unwrap();
+        let files = object_store.read_tree(&commit.tree).unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["docs/guide.md", "notes.txt"]);
+    }
+}
@@ -0,0 +1,551 @@
+//! Packfiles: many objects stored in one file, similar objects as deltas.
+//!
+//! A pack is a pair of files in `objects/pack/`:
+//!
+//! * `pack-<checksum>.pack` — `CPACK`, a version byte and the object count,
+//!   followed by one entry per object: a kind byte (full or delta), the
+//!   uncompressed size, for deltas the absolute offset of the base entry, the
+//!   compressed length and the zlib-compressed payload.
+//! * `pack-<checksum>.idx` — `CIDX`, a version byte, the hash length in bytes
+//!   and the object count, followed by fixed-size `(raw hash, u64 offset)`
+//!   records sorted by hash so lookups are a binary search.
+
+use crate::encoding::{hex_decode, hex_encode};
+use crate::{CrabGitError, Result};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const PACK_MAGIC: &[u8] = b"CPACK";
+const IDX_MAGIC: &[u8] = b"CIDX";
+const VERSION: u8 = 1;
+
+const KIND_FULL: u8 = 0;
+const KIND_DELTA: u8 = 1;
+
+/// How many previously written objects are tried as delta bases.
+const DELTA_WINDOW: usize = 10;
+/// Longest chain of deltas that must be applied to rebuild an object.
+const MAX_DELTA_DEPTH: usize = 50;
+/// Length of the blocks indexed when searching a base for copyable data.
+const BLOCK_SIZE: usize = 16;
+
+pub struct Pack {
+    pack_path: PathBuf,
+    /// The pack file, opened once and kept for every read.
+    reader: RefCell<BufReader<File>>,
+    pack_len: u64,
+    hash_len: usize,
+    count: usize,
+    records: Vec<u8>,
+}
+
+impl Pack {
+    pub fn open(idx_path: &Path) -> Result<Self> {
+        let idx = fs::read(idx_path)?;
+        let corrupt = |reason: &str| CrabGitError::CorruptObject {
+            hash: idx_path.display().to_string(),
+            reason: reason.to_string(),
+        };
+
+        let header_len = IDX_MAGIC.len() + 1 + 1 + 4;
+        if idx.len() < header_len || &idx[..IDX_MAGIC.len()] != IDX_MAGIC {
+            return Err(corrupt("not a pack index"));
+        }
+        if idx[IDX_MAGIC.len()] != VERSION {
+            return Err(corrupt("unsupported pack index version"));
+        }
+
+        let hash_len = idx[IDX_MAGIC.len() + 1] as usize;
+        let count = u32::from_be_bytes(idx[header_len - 4..header_len].try_into().unwrap()) as usize;
+        let records = idx[header_len..].to_vec();
+        if records.len() != count * (hash_len + 8) {
+            return Err(corrupt("truncated pack index"));
+        }
+
+        let pack_path = idx_path.with_extension("pack");
+        let file = File::open(&pack_path)?;
+        let pack_len = file.metadata()?.len();
+        Ok(Self {
+            pack_path,
+            reader: RefCell::new(BufReader::new(file)),
+            pack_len,
+            hash_len,
+            count,
+            records,
+        })
+    }
+
+    fn record(&self, i: usize) -> (&[u8], u64) {
+        let start = i * (self.hash_len + 8);
+        let hash = &self.records[start..start + self.hash_len];
+        let offset = u64::from_be_bytes(
+            self.records[start + self.hash_len..start + self.hash_len + 8]
+                .try_into()
+                .unwrap(),
+        );
+        (hash, offset)
+    }
+
+    /// Offset of an object in the pack, found by binary search of the index.
+    pub fn find(&self, hash: &str) -> Option<u64> {
+        let raw = hex_decode(hash)?;
+        if raw.len() != self.hash_len {
+            return None;
+        }
+
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = (low + high) / 2;
+            let (candidate, offset) = self.record(mid);
+            match candidate.cmp(raw.as_slice()) {
+                std::cmp::Ordering::Equal => return Some(offset),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+
+    pub fn hashes(&self) -> Vec<String> {
+        (0..self.count).map(|i| hex_encode(self.record(i).0)).collect()
+    }
+
+    /// Reads and fully resolves the object stored at `offset`.
+    pub fn read(&self, offset: u64) -> Result<Vec<u8>> {
+        let mut reader = self.reader.borrow_mut();
+        self.read_at(&mut reader, offset, 0)
+    }
+
+    fn read_at(&self, reader: &mut BufReader<File>, offset: u64, depth: usize) -> Result<Vec<u8>> {
+        if depth > MAX_DELTA_DEPTH {
+            return Err(self.corrupt(offset, "delta chain too long"));
+        }
+
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut kind = [0u8];
+        reader.read_exact(&mut kind)?;
+        let size = read_varint(reader)? as usize;
+        let base_offset = if kind[0] == KIND_DELTA {
+            Some(read_varint(reader)?)
+        } else {
+            None
+        };
+        let compressed_len = read_varint(reader)?;
+        // Lengths come from the file, so a corrupt one must not be trusted
+        // with an allocation.
+        if compressed_len > self.pack_len.saturating_sub(reader.stream_position()?) {
+            return Err(self.corrupt(offset, "entry extends past the end of the pack"));
+        }
+
+        let mut compressed = vec![0u8; compressed_len as usize];
+        reader.read_exact(&mut compressed)?;
+        let mut data = Vec::new();
+        ZlibDecoder::new(&compressed[..])
+            .take(size as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(|err| self.corrupt(offset, &err.to_string()))?;
+        if data.len() != size {
+            return Err(self.corrupt(offset, "size mismatch"));
+        }
+
+        match (kind[0], base_offset) {
+            (KIND_FULL, _) => Ok(data),
+            (KIND_DELTA, Some(base_offset)) => {
+                let base = self.read_at(reader, base_offset, depth + 1)?;
+                apply_delta(&base, &data).ok_or_else(|| self.corrupt(offset, "invalid delta"))
+            }
+            _ => Err(self.corrupt(offset, "unknown entry kind")),
+        }
+    }
+
+    fn corrupt(&self, offset: u64, reason: &str) -> CrabGitError {
+        CrabGitError::CorruptObject {
+            hash: format!("{}@{}", self.pack_path.display(), offset),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Lists the packs in `pack_dir` that have an index.
+pub fn open_packs(pack_dir: &Path) -> Result<Vec<Pack>> {
+    let mut packs = Vec::new();
+    if !pack_dir.is_dir() {
+        return Ok(packs);
+    }
+
+    for entry in fs::read_dir(pack_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "idx")
+            && path.with_extension("pack").exists()
+            && is_crab_pack_index(&path)?
+        {
+            packs.push(Pack::open(&path)?);
+        }
+    }
+    Ok(packs)
+}
+
+/// Packs written by git in a git-compatible repository use a different format
+/// and are skipped.
+fn is_crab_pack_index(idx_path: &Path) -> Result<bool> {
+    let mut magic = [0; IDX_MAGIC.len()];
+    let mut file = File::open(idx_path)?;
+    Ok(file.read_exact(&mut magic).is_ok() && magic == IDX_MAGIC)
+}
+
+pub struct PackStats {
+    pub name: String,
+    pub objects: usize,
+    pub deltas: usize,
+}
+
+/// Writes `objects` (hash, content) into a new pack and its index. Objects are
+/// written largest first and each one is tried as a delta against the
+/// previous `DELTA_WINDOW` objects, keeping the smallest encoding.
+pub fn write_pack(pack_dir: &Path, mut objects: Vec<(String, Vec<u8>)>) -> Result<PackStats> {
+    fs::create_dir_all(pack_dir)?;
+    objects.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+
+    let mut pack = Vec::new();
+    pack.extend_from_slice(PACK_MAGIC);
+    pack.push(VERSION);
+    pack.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+
+    let mut offsets: Vec<u64> = Vec::with_capacity(objects.len());
+    let mut depths: Vec<usize> = Vec::with_capacity(objects.len());
+    let mut deltas = 0;
+
+    for (i, (_, content)) in objects.iter().enumerate() {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for j in i.saturating_sub(DELTA_WINDOW)..i {
+            if depths[j] >= MAX_DELTA_DEPTH {
+                continue;
+            }
+            let delta = encode_delta(&objects[j].1, content);
+            let limit = best.as_ref().map_or(content.len() / 2, |(_, d)| d.len());
+            if delta.len() < limit {
+                best = Some((j, delta));
+            }
+        }
+
+        offsets.push(pack.len() as u64);
+        match best {
+            Some((base, delta)) => {
+                pack.push(KIND_DELTA);
+                write_varint(&mut pack, delta.len() as u64);
+                write_varint(&mut pack, offsets[base]);
+                write_compressed(&mut pack, &delta)?;
+                depths.push(depths[base] + 1);
+                deltas += 1;
+            }
+            None => {
+                pack.push(KIND_FULL);
+                write_varint(&mut pack, content.len() as u64);
+                write_compressed(&mut pack, content)?;
+                depths.push(0);
+            }
+        }
+    }
+
+    let checksum = format!("{:x}", Sha256::digest(&pack));
+    let name = format!("pack-{}", checksum);
+
+    let mut records: Vec<(Vec<u8>, u64)> = objects
+        .iter()
+        .zip(&offsets)
+        .map(|((hash, _), &offset)| {
+            let raw = hex_decode(hash).ok_or_else(|| CrabGitError::CorruptObject {
+                hash: hash.clone(),
+                reason: "invalid object name".to_string(),
+            })?;
+            Ok((raw, offset))
+        })
+        .collect::<Result<_>>()?;
+    records.sort();
+
+    let hash_len = records.first().map_or(0, |(raw, _)| raw.len());
+    let mut idx = Vec::new();
+    idx.extend_from_slice(IDX_MAGIC);
+    idx.push(VERSION);
+    idx.push(hash_len as u8);
+    idx.extend_from_slice(&(records.len() as u32).to_be_bytes());
+    for (raw, offset) in &records {
+        idx.extend_from_slice(raw);
+        idx.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    // The pack must be complete before its index makes it visible to readers.
+    write_file(&pack_dir.join(format!("{}.pack", name)), &pack)?;
+    write_file(&pack_dir.join(format!("{}.idx", name)), &idx)?;
+
+    Ok(PackStats {
+        name,
+        objects: objects.len(),
+        deltas,
+    })
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn write_compressed(out: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    write_varint(out, compressed.len() as u64);
+    out.extend_from_slice(&compressed);
+    Ok(())
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(reader: &mut impl Read) -> Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift > 63 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "varint too long").into());
+        }
+    }
+}
+
+fn take_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut slice = data.get(*pos..)?;
+    let before = slice.len();
+    let value = read_varint(&mut slice).ok()?;
+    *pos += before - slice.len();
+    Some(value)
+}
+
+const OP_COPY: u8 = 0;
+const OP_INSERT: u8 = 1;
+
+/// Encodes `target` as copy/insert instructions against `base`: the target
+/// size, then `copy <offset> <len>` and `insert <len> <bytes>` operations.
+pub fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for start in (0..base.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks.entry(&base[start..start + BLOCK_SIZE]).or_insert(start);
+    }
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len() as u64);
+
+    let mut insert_start = 0;
+    let mut i = 0;
+    while i + BLOCK_SIZE <= target.len() {
+        let Some(&base_start) = blocks.get(&target[i..i + BLOCK_SIZE]) else {
+            i += 1;
+            continue;
+        };
+
+        // Extend the match backwards into pending literal bytes and forwards
+        // as far as the data agrees.
+        let mut back = 0;
+        while back < base_start && i - back > insert_start && base[base_start - back - 1] == target[i - back - 1] {
+            back += 1;
+        }
+        let mut len = BLOCK_SIZE;
+        while base_start + len < base.len() && i + len < target.len() && base[base_start + len] == target[i + len] {
+            len += 1;
+        }
+
+        push_insert(&mut delta, &target[insert_start..i - back]);
+        delta.push(OP_COPY);
+        write_varint(&mut delta, (base_start - back) as u64);
+        write_varint(&mut delta, (len + back) as u64);
+
+        i += len;
+        insert_start = i;
+    }
+    push_insert(&mut delta, &target[insert_start..]);
+
+    delta
+}
+
+fn push_insert(delta: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    delta.push(OP_INSERT);
+    write_varint(delta, bytes.len() as u64);
+    delta.extend_from_slice(bytes);
+}
+
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let size = take_varint(delta, &mut pos)? as usize;
+    // Copies can repeat the base, so this is only a guess at the output size,
+    // bounded so that a corrupt size cannot allocate much.
+    let mut out = Vec::with_capacity(size.min(base.len() + delta.len()));
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        match op {
+            OP_COPY => {
+                let offset = take_varint(delta, &mut pos)? as usize;
+                let len = take_varint(delta, &mut pos)? as usize;
+                out.extend_from_slice(base.get(offset..offset.checked_add(len)?)?);
+            }
+            OP_INSERT => {
+                let len = take_varint(delta, &mut pos)? as usize;
+                out.extend_from_slice(delta.get(pos..pos.checked_add(len)?)?);
+                pos += len;
+            }
+            _ => return None,
+        }
+    }
+
+    (out.len() == size).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that rarely repeat a block, from a xorshift generator.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let delta = encode_delta(base, target);
+        assert_eq!(apply_delta(base, &delta).as_deref(), Some(target));
+        delta
+    }
+
+    /// A pack directory of its own under the system temp directory.
+    fn temp_pack_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crab_git-pack-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let base = noise(1, 4096);
+        round_trip(b"", b"");
+        round_trip(b"", &base);
+        round_trip(&base, b"");
+        round_trip(b"short", b"shorter");
+
+        // Identical content is a single copy.
+        assert!(round_trip(&base, &base).len() < 16);
+
+        // Edits in the middle, at both ends and moved blocks keep most of
+        // the base as copies.
+        let mut edited = base.clone();
+        edited[100..110].copy_from_slice(b"0123456789");
+        edited.splice(2000..2000, noise(2, 50));
+        edited.truncate(4000);
+        edited.splice(0..0, *b"prefix");
+        edited.extend_from_slice(b"suffix");
+        assert!(round_trip(&base, &edited).len() < 200);
+
+        let moved = [&base[2048..], &base[..2048]].concat();
+        assert!(round_trip(&base, &moved).len() < 64);
+
+        // Unrelated content falls back to inserts.
+        round_trip(&base, &noise(3, 1000));
+    }
+
+    #[test]
+    fn apply_delta_rejects_invalid_deltas() {
+        let base = b"0123456789";
+        let mut copy_past_end = Vec::new();
+        write_varint(&mut copy_past_end, 5);
+        copy_past_end.push(OP_COPY);
+        write_varint(&mut copy_past_end, 8);
+        write_varint(&mut copy_past_end, 5);
+        assert_eq!(apply_delta(base, &copy_past_end), None);
+
+        let mut wrong_size = encode_delta(base, b"01234");
+        wrong_size[0] = 6;
+        assert_eq!(apply_delta(base, &wrong_size), None);
+
+        let mut huge_size = Vec::new();
+        write_varint(&mut huge_size, u64::MAX >> 1);
+        assert_eq!(apply_delta(base, &huge_size), None);
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let dir = temp_pack_dir("round-trip");
+        let base = noise(4, 3000);
+        let mut similar = base.clone();
+        similar[1500] ^= 1;
+        let objects = vec![
+            ("aa".repeat(32), base.clone()),
+            ("bb".repeat(32), similar.clone()),
+            ("cc".repeat(32), b"small".to_vec()),
+        ];
+
+        let stats = write_pack(&dir, objects.clone()).unwrap();
+        assert_eq!((stats.objects, stats.deltas), (3, 1));
+        let packs = open_packs(&dir).unwrap();
+        assert_eq!(packs.len(), 1);
+        for (hash, content) in &objects {
+            let offset = packs[0].find(hash).unwrap();
+            assert_eq!(&packs[0].read(offset).unwrap(), content);
+        }
+        assert_eq!(packs[0].find(&"dd".repeat(32)), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_entry_length_is_an_error() {
+        let dir = temp_pack_dir("corrupt");
+        let stats = write_pack(&dir, vec![("aa".repeat(32), b"content".to_vec())]).unwrap();
+        let pack_path = dir.join(format!("{}.pack", stats.name));
+        let offset = open_packs(&dir).unwrap()[0].find(&"aa".repeat(32)).unwrap();
+
+        // Replace the entry with one claiming a huge compressed length.
+        let mut pack = fs::read(&pack_path).unwrap();
+        pack.truncate(offset as usize);
+        pack.push(KIND_FULL);
+        write_varint(&mut pack, 7);
+        write_varint(&mut pack, 1 << 40);
+        fs::write(&pack_path, pack).unwrap();
+
+        let packs = open_packs(&dir).unwrap();
+        assert!(matches!(packs[0].read(offset), Err(CrabGitError::CorruptObject { .. })));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
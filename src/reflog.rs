@@ -0,0 +1,202 @@
+//! Reflogs record every value HEAD and each branch have had, in git's format
+//! under `logs/` (`logs/HEAD`, `logs/refs/heads/<branch>`). Each update
+//! appends one line:
+//!
+//! ```text
+//! <old hash> <new hash> <identity> <unix time> +0000\t<reason>
+//! ```
+//!
+//! The old hash is all zeros when the update created the ref.
+
+use crate::lockfile::{self, LockFile};
+use crate::{CrabGitError, Repository, Result, encoding, utils};
+use chrono::{DateTime, Utc};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub identity: String,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn to_line(&self) -> String {
+        format!(
+            "{} {} {}\t{}\n",
+            self.old,
+            self.new,
+            encoding::signature(&self.identity, &self.timestamp),
+            self.message
+        )
+    }
+
+    fn parse(ref_name: &str, line: &str) -> Option<Self> {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut parts = header.splitn(3, ' ');
+        let (old, new, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        let (identity, timestamp) = encoding::parse_signature(ref_name, signature).ok()?;
+        Some(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            identity,
+            timestamp,
+            message: message.to_string(),
+        })
+    }
+}
+
+pub fn log_path(repo: &Repository, ref_name: &str) -> PathBuf {
+    repo.git_dir.join("logs").join(ref_name)
+}
+
+/// Records that `ref_name` moved from `old` (`None` when it was created) to
+/// `new` because of `reason`.
+pub fn append(repo: &Repository, ref_name: &str, old: Option<&str>, new: &str, reason: &str) -> Result<()> {
+    let null_hash = "0".repeat(repo.hash_algorithm.raw_len() * 2);
+    let entry = ReflogEntry {
+        old: old.unwrap_or(&null_hash).to_string(),
+        new: new.to_string(),
+        identity: utils::identity(repo)?,
+        timestamp: Utc::now(),
+        // A reason spans a single line, like a commit subject.
+        message: reason.lines().next().unwrap_or("").to_string(),
+    };
+
+    let path = log_path(repo, ref_name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(entry.to_line().as_bytes())?;
+    Ok(())
+}
+
+/// The entries of `ref_name`'s reflog, oldest first. Lines that cannot be
+/// parsed are skipped.
+pub fn read(repo: &Repository, ref_name: &str) -> Result<Vec<ReflogEntry>> {
+    let path = log_path(repo, ref_name);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| ReflogEntry::parse(ref_name, line))
+        .collect())
+}
+
+/// Replaces the reflog of `ref_name` with `entries`.
+pub fn write(repo: &Repository, ref_name: &str, entries: &[ReflogEntry]) -> Result<()> {
+    let path = log_path(repo, ref_name);
+    let mut lock = LockFile::acquire(&path)?
+        .ok_or_else(|| CrabGitError::RefLocked(lockfile::lock_path(&path)))?;
+    let content: String = entries.iter().map(ReflogEntry::to_line).collect();
+    lock.write_all(content.as_bytes())?;
+    lock.commit()
+}
+
+pub fn delete(repo: &Repository, ref_name: &str) -> Result<()> {
+    let path = log_path(repo, ref_name);
+    if path.is_file() {
+        fs::remove_file(&path)?;
+        utils::remove_empty_parents(&repo.git_dir.join("logs"), &path)?;
+    }
+    Ok(())
+}
+
+/// Names of all refs that have a reflog, `HEAD` first.
+pub fn logged_refs(repo: &Repository) -> Result<Vec<String>> {
+    let logs_dir = repo.git_dir.join("logs");
+    let mut refs = Vec::new();
+    if logs_dir.join("HEAD").is_file() {
+        refs.push("HEAD".to_string());
+    }
+
+    let refs_dir = logs_dir.join("refs");
+    if refs_dir.is_dir() {
+        for entry in walkdir::WalkDir::new(&refs_dir).sort_by_file_name() {
+            let entry = entry.map_err(std::io::Error::other)?;
+            if entry.file_type().is_file() {
+                let relative = entry.path().strip_prefix(&logs_dir)?;
+                refs.push(relative.to_string_lossy().replace("\\", "/"));
+            }
+        }
+    }
+    Ok(refs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{branch, checkout, reset::{self, ResetMode}};
+    use crate::testing::TestRepo;
+
+    fn messages(test: &TestRepo, ref_name: &str) -> Vec<String> {
+        read(&test.repo, ref_name).unwrap().into_iter().map(|entry| entry.message).collect()
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let test = TestRepo::new();
+        let new = "1".repeat(64);
+        append(&test.repo, "refs/heads/x", None, &new, "first line\nsecond line").unwrap();
+
+        let entries = read(&test.repo, "refs/heads/x").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].old, "0".repeat(64));
+        assert_eq!(entries[0].new, new);
+        assert_eq!(entries[0].identity, utils::DEFAULT_IDENTITY);
+        assert_eq!(entries[0].message, "first line");
+
+        // Lines that are not reflog entries are skipped.
+        let path = log_path(&test.repo, "refs/heads/x");
+        fs::write(&path, format!("garbage\n{}", fs::read_to_string(&path).unwrap())).unwrap();
+        assert_eq!(read(&test.repo, "refs/heads/x").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn identity_comes_from_the_config() {
+        let test = TestRepo::new();
+        let mut config = crate::config::Config::for_repo(&test.repo).unwrap();
+        config.set("user.name", "Ferris");
+        config.set("user.email", "ferris@example.com");
+        config.save().unwrap();
+
+        append(&test.repo, "HEAD", None, &"1".repeat(64), "test").unwrap();
+        assert_eq!(read(&test.repo, "HEAD").unwrap()[0].identity, "Ferris <ferris@example.com>");
+    }
+
+    #[test]
+    fn commands_record_their_moves() {
+        let test = TestRepo::new();
+        test.write("file.txt", "one\n");
+        test.commit("first");
+        let first = test.head();
+        test.write("file.txt", "two\n");
+        test.commit("second");
+        branch(&test.repo, Some("topic".to_string()), false).unwrap();
+        checkout(&test.repo, "topic".to_string(), false).unwrap();
+        reset::reset(&test.repo, ResetMode::Hard, Some(first.clone()), vec![]).unwrap();
+
+        assert_eq!(
+            messages(&test, "HEAD"),
+            [
+                "commit (initial): first",
+                "commit: second",
+                "checkout: moving from main to topic",
+                &format!("reset: moving to {}", first),
+            ]
+        );
+        assert_eq!(messages(&test, "refs/heads/topic").len(), 2);
+        assert_eq!(messages(&test, "refs/heads/main").len(), 2);
+        assert_eq!(logged_refs(&test.repo).unwrap(), ["HEAD", "refs/heads/main", "refs/heads/topic"]);
+
+        checkout(&test.repo, "main".to_string(), false).unwrap();
+        branch(&test.repo, Some("topic".to_string()), true).unwrap();
+        assert!(!log_path(&test.repo, "refs/heads/topic").exists());
+    }
+}
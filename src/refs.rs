@@ -0,0 +1,326 @@
+//! Ref transactions. Every ref write goes through a [`RefTransaction`]: each
+//! ref is locked with a [`LockFile`], its current value is checked against
+//! the value the caller expects, and only when every check passes are the new
+//! values renamed into place. A concurrent writer therefore either finds the
+//! lock held or fails the check, instead of silently losing an update.
+//!
+//! Updates of `HEAD` and branches are recorded in their reflogs.
+
+use crate::lockfile::{self, LockFile};
+use crate::{CrabGitError, Repository, Result, reflog, utils};
+use std::fs;
+
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The value a ref must have for an update to go ahead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    /// Update whatever the ref holds.
+    Any,
+    /// The ref must not exist yet.
+    Missing,
+    /// The ref must hold exactly this value.
+    Value(String),
+}
+
+impl From<Option<&str>> for Expected {
+    fn from(value: Option<&str>) -> Self {
+        match value {
+            Some(value) => Expected::Value(value.to_string()),
+            None => Expected::Missing,
+        }
+    }
+}
+
+impl From<Option<String>> for Expected {
+    fn from(value: Option<String>) -> Self {
+        Expected::from(value.as_deref())
+    }
+}
+
+#[derive(Debug)]
+struct RefUpdate {
+    name: String,
+    /// The new content of the ref file, or `None` to delete it.
+    new: Option<String>,
+    expected: Expected,
+    reason: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct RefTransaction<'a> {
+    repo: &'a Repository,
+    updates: Vec<RefUpdate>,
+}
+
+impl<'a> RefTransaction<'a> {
+    pub fn new(repo: &'a Repository) -> Self {
+        RefTransaction { repo, updates: Vec::new() }
+    }
+
+    /// Points `name` (`HEAD`, `MERGE_HEAD` or a full `refs/...` name) at
+    /// `hash`. A `reason` is recorded in the reflog of `HEAD` and branches.
+    pub fn update(&mut self, name: &str, hash: &str, expected: Expected, reason: Option<&str>) -> &mut Self {
+        self.push(name, Some(hash.to_string()), expected, reason)
+    }
+
+    /// Makes `name` a symbolic ref to `target`, as `HEAD` is to the current
+    /// branch.
+    pub fn symbolic(&mut self, name: &str, target: &str, reason: Option<&str>) -> &mut Self {
+        self.push(name, Some(format!("ref: {}", target)), Expected::Any, reason)
+    }
+
+    /// Points the current branch at `commit`, or `HEAD` itself when it is
+    /// detached.
+    pub fn update_head(&mut self, commit: &str, expected: Expected, reason: &str) -> Result<&mut Self> {
+        let name = match read_ref(self.repo, "HEAD")?.as_deref().and_then(|head| head.strip_prefix("ref: ")) {
+            Some(target) => target.to_string(),
+            None => "HEAD".to_string(),
+        };
+        Ok(self.update(&name, commit, expected, Some(reason)))
+    }
+
+    pub fn delete(&mut self, name: &str, expected: Expected) -> &mut Self {
+        self.push(name, None, expected, None)
+    }
+
+    fn push(&mut self, name: &str, new: Option<String>, expected: Expected, reason: Option<&str>) -> &mut Self {
+        self.updates.push(RefUpdate {
+            name: name.to_string(),
+            new,
+            expected,
+            reason: reason.map(str::to_string),
+        });
+        self
+    }
+
+    /// Locks every ref, verifies the expected values and writes the new ones.
+    /// Nothing is written when a name is invalid, a lock is held or a ref
+    /// has moved.
+    pub fn commit(mut self) -> Result<()> {
+        for update in &self.updates {
+            utils::check_ref_format(&update.name)?;
+            if let Some(target) = update.new.as_deref().and_then(|value| value.strip_prefix("ref: ")) {
+                utils::check_ref_format(target)?;
+            }
+        }
+
+        // A fixed locking order keeps two transactions from deadlocking.
+        self.updates.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(pair) = self.updates.windows(2).find(|pair| pair[0].name == pair[1].name) {
+            return Err(CrabGitError::InvalidOperation(format!(
+                "Multiple updates for ref '{}' are not allowed",
+                pair[0].name
+            )));
+        }
+
+        let mut locked = Vec::new();
+        for update in &self.updates {
+            let path = self.repo.git_dir.join(&update.name);
+            let lock = LockFile::acquire(&path)?
+                .ok_or_else(|| CrabGitError::RefLocked(lockfile::lock_path(&path)))?;
+
+            let old = read_ref(self.repo, &update.name)?;
+            let stale = match &update.expected {
+                Expected::Any => false,
+                Expected::Missing => old.is_some(),
+                Expected::Value(value) => old.as_ref() != Some(value),
+            };
+            if stale {
+                return Err(CrabGitError::StaleRef {
+                    name: update.name.clone(),
+                    expected: match &update.expected {
+                        Expected::Value(value) => Some(value.clone()),
+                        _ => None,
+                    },
+                    actual: old,
+                });
+            }
+            locked.push((lock, old));
+        }
+
+        let head = read_ref(self.repo, "HEAD")?;
+        let updates_head = self.updates.iter().any(|update| update.name == "HEAD");
+        let mut logs = Vec::new();
+        for (update, (mut lock, old)) in self.updates.iter().zip(locked) {
+            let old_commit = old.as_deref().map(|value| self.peel(value)).transpose()?.flatten();
+            match &update.new {
+                Some(value) => {
+                    lock.write_all(format!("{}\n", value).as_bytes())?;
+                    lock.commit()?;
+                }
+                None => {
+                    lock.commit_delete()?;
+                    if update.name.starts_with("refs/heads/") {
+                        reflog::delete(self.repo, &update.name)?;
+                    }
+                }
+            }
+
+            let (Some(reason), Some(new)) = (&update.reason, &update.new) else {
+                continue;
+            };
+            if update.name != "HEAD" && !update.name.starts_with("refs/heads/") {
+                continue;
+            }
+            logs.push((update.name.clone(), old_commit.clone(), new.clone(), reason.clone()));
+
+            // HEAD's log also records moves of the branch it points to.
+            if !updates_head && head.as_deref() == Some(&format!("ref: {}", update.name)) {
+                logs.push(("HEAD".to_string(), old_commit, new.clone(), reason.clone()));
+            }
+        }
+
+        for (name, old, new, reason) in logs {
+            if let Some(new) = self.peel(&new)? {
+                reflog::append(self.repo, &name, old.as_deref(), &new, &reason)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The commit a ref value points to, following a symbolic ref.
+    fn peel(&self, value: &str) -> Result<Option<String>> {
+        match value.strip_prefix("ref: ") {
+            Some(target) => resolve_ref(self.repo, target),
+            None => Ok(Some(value.to_string())),
+        }
+    }
+}
+
+/// The content of the ref file `name` without its trailing newline, or
+/// `None` when the ref does not exist.
+pub fn read_ref(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let path = repo.git_dir.join(name);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?.trim().to_string()))
+}
+
+/// The hash `name` points to, following symbolic refs.
+pub fn resolve_ref(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let mut name = name.to_string();
+    // Bounded so a cycle of symbolic refs cannot loop forever.
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read_ref(repo, &name)? {
+            Some(value) => match value.strip_prefix("ref: ") {
+                Some(target) => name = target.to_string(),
+                None => return Ok(Some(value)),
+            },
+            None => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+/// Every ref under `refs/` with its full name and hash, sorted by name.
+/// Symbolic refs are resolved; dangling ones are left out.
+pub fn list_refs(repo: &Repository) -> Result<Vec<(String, String)>> {
+    let refs_dir = repo.git_dir.join("refs");
+    let mut refs = Vec::new();
+    if !refs_dir.is_dir() {
+        return Ok(refs);
+    }
+    for entry in walkdir::WalkDir::new(&refs_dir).sort_by_file_name() {
+        let entry = entry.map_err(std::io::Error::other)?;
+        if !entry.file_type().is_file() || entry.path().extension().is_some_and(|extension| extension == "lock") {
+            continue;
+        }
+        let name = entry.path().strip_prefix(&repo.git_dir)?.to_string_lossy().replace("\\", "/");
+        if let Some(hash) = resolve_ref(repo, &name)? {
+            refs.push((name, hash));
+        }
+    }
+    refs.sort();
+    Ok(refs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRepo;
+
+    fn update(test: &TestRepo, name: &str, hash: &str, expected: Expected, reason: Option<&str>) -> Result<()> {
+        let mut transaction = RefTransaction::new(&test.repo);
+        transaction.update(name, hash, expected, reason);
+        transaction.commit()
+    }
+
+    #[test]
+    fn updates_check_the_expected_value() {
+        let test = TestRepo::new();
+        let (a, b) = ("a".repeat(64), "b".repeat(64));
+        update(&test, "refs/tags/v1", &a, Expected::Missing, None).unwrap();
+
+        let result = update(&test, "refs/tags/v1", &b, Expected::Missing, None);
+        assert!(matches!(result, Err(CrabGitError::StaleRef { actual: Some(ref actual), .. }) if *actual == a));
+        let result = update(&test, "refs/tags/v1", &b, Expected::Value(b.clone()), None);
+        assert!(matches!(result, Err(CrabGitError::StaleRef { .. })));
+
+        update(&test, "refs/tags/v1", &b, Expected::Value(a), None).unwrap();
+        assert_eq!(read_ref(&test.repo, "refs/tags/v1").unwrap(), Some(b));
+    }
+
+    #[test]
+    fn nothing_is_written_when_a_lock_is_held() {
+        let test = TestRepo::new();
+        let hash = "a".repeat(64);
+        let held = LockFile::acquire(&test.repo.git_dir.join("refs/tags/b")).unwrap().unwrap();
+
+        let mut transaction = RefTransaction::new(&test.repo);
+        transaction.update("refs/tags/a", &hash, Expected::Any, None);
+        transaction.update("refs/tags/b", &hash, Expected::Any, None);
+        assert!(matches!(transaction.commit(), Err(CrabGitError::RefLocked(_))));
+        assert_eq!(read_ref(&test.repo, "refs/tags/a").unwrap(), None);
+        assert!(!lockfile::lock_path(&test.repo.git_dir.join("refs/tags/a")).exists());
+        drop(held);
+    }
+
+    #[test]
+    fn duplicate_and_invalid_names_are_rejected() {
+        let test = TestRepo::new();
+        let hash = "a".repeat(64);
+
+        let mut transaction = RefTransaction::new(&test.repo);
+        transaction.update("refs/tags/a", &hash, Expected::Any, None);
+        transaction.delete("refs/tags/a", Expected::Any);
+        assert!(matches!(transaction.commit(), Err(CrabGitError::InvalidOperation(_))));
+
+        for name in ["refs/heads/a..b", "refs/heads/x.lock", "refs/heads/.hidden", "../outside", "refs/heads/a b"] {
+            let result = update(&test, name, &hash, Expected::Any, None);
+            assert!(matches!(result, Err(CrabGitError::InvalidOperation(_))), "{}", name);
+        }
+        let mut transaction = RefTransaction::new(&test.repo);
+        transaction.symbolic("HEAD", "refs/heads/a~1", None);
+        let result = transaction.commit();
+        assert!(matches!(result, Err(CrabGitError::InvalidOperation(_))));
+        assert!(!test.repo.git_dir.join("outside").exists());
+    }
+
+    #[test]
+    fn branch_moves_are_logged_to_head_and_dropped_on_delete() {
+        let test = TestRepo::new();
+        test.write("a.txt", "a");
+        test.commit("first");
+        let branch = format!("refs/heads/{}", utils::get_current_branch(&test.repo).unwrap());
+        let first = test.head();
+        let before = reflog::read(&test.repo, "HEAD").unwrap().len();
+
+        update(&test, &branch, &first, Expected::Any, Some("moved")).unwrap();
+        let head_log = reflog::read(&test.repo, "HEAD").unwrap();
+        assert_eq!(head_log.len(), before + 1);
+        assert_eq!(head_log.last().unwrap().message, "moved");
+        assert_eq!(reflog::read(&test.repo, &branch).unwrap().last().unwrap().message, "moved");
+
+        update(&test, "refs/heads/other", &first, Expected::Missing, Some("created")).unwrap();
+        assert_eq!(reflog::read(&test.repo, "HEAD").unwrap().len(), before + 1);
+        assert_eq!(reflog::read(&test.repo, "refs/heads/other").unwrap().len(), 1);
+
+        let mut transaction = RefTransaction::new(&test.repo);
+        transaction.delete("refs/heads/other", Expected::Value(first));
+        transaction.commit().unwrap();
+        assert_eq!(read_ref(&test.repo, "refs/heads/other").unwrap(), None);
+        assert!(!reflog::log_path(&test.repo, "refs/heads/other").exists());
+    }
+}
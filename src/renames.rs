@@ -0,0 +1,351 @@
+//! Rename and copy detection between the two sides of a diff.
+//!
+//! A file that disappeared from the old side and a file that appeared on the
+//! new side are paired as a rename when their contents are similar enough.
+//! Identical contents are paired first by hash; the remaining files are
+//! scored by how many bytes of lines they share, relative to the larger
+//! file. With copy detection, files that still exist on both sides can also
+//! be the source of a new file.
+
+use crate::Result;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Minimum similarity, in percent, for a rename or copy when none is given.
+pub const DEFAULT_SIMILARITY: u8 = 50;
+
+/// Above this many candidate pairs only exact renames are detected.
+const MAX_INEXACT_PAIRS: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenameOptions {
+    /// Minimum similarity in percent.
+    pub threshold: u8,
+    /// Also look for files copied from files that still exist.
+    pub copies: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        RenameOptions { threshold: DEFAULT_SIMILARITY, copies: false }
+    }
+}
+
+/// A similarity threshold as `-M` and `-C` take it: `50%`, or digits read as
+/// a fraction the way git does (`5` and `50` both mean 50%, `05` means 5%).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Similarity(pub u8);
+
+impl FromStr for Similarity {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid similarity '{}', expected e.g. 50%", value);
+        if value.is_empty() {
+            return Ok(Similarity(DEFAULT_SIMILARITY));
+        }
+        let percent = match value.strip_suffix('%') {
+            Some(percent) => percent.parse::<f64>().map_err(|_| invalid())?,
+            None if value.bytes().all(|b| b.is_ascii_digit()) => {
+                format!("0.{}", value).parse::<f64>().map_err(|_| invalid())? * 100.0
+            }
+            None => return Err(invalid()),
+        };
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(invalid());
+        }
+        Ok(Similarity(percent.round() as u8))
+    }
+}
+
+/// A file on one side of a diff.
+#[derive(Debug, Clone)]
+pub struct FileVersion {
+    pub path: String,
+    pub hash: String,
+}
+
+/// A new file found to be a rename or copy of an old one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+    /// Similarity in percent.
+    pub similarity: u8,
+    /// Whether the source still exists, making this a copy.
+    pub copy: bool,
+}
+
+/// Pairs `added` files with `deleted` ones (renames) and, with copy
+/// detection, with `sources` that still exist (copies). Each deleted file
+/// is renamed at most once: with copy detection, as in git, its last match
+/// by path is the rename and the others are copies; without, only its first
+/// match counts. `load` returns a file's content.
+pub fn detect<F>(
+    deleted: &[FileVersion],
+    added: &[FileVersion],
+    sources: &[FileVersion],
+    options: &RenameOptions,
+    mut load: F,
+) -> Result<Vec<Rename>>
+where
+    F: FnMut(&FileVersion) -> Result<Vec<u8>>,
+{
+    let candidates: Vec<(&FileVersion, bool)> = deleted
+        .iter()
+        .map(|file| (file, true))
+        .chain(sources.iter().filter(|_| options.copies).map(|file| (file, false)))
+        .collect();
+    let mut pairing = Pairing {
+        candidates: &candidates,
+        added,
+        copies: options.copies,
+        renamed: vec![false; candidates.len()],
+        matched: vec![None; added.len()],
+    };
+
+    // Identical content: no need to look at it.
+    let mut by_hash: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (file, _)) in candidates.iter().enumerate() {
+        by_hash.entry(file.hash.as_str()).or_default().push(i);
+    }
+    for (target, file) in added.iter().enumerate() {
+        let Some(sources) = by_hash.get(file.hash.as_str()) else {
+            continue;
+        };
+        // Prefer a deleted file that has not been renamed yet.
+        let source = sources
+            .iter()
+            .copied()
+            .find(|&i| candidates[i].1 && !pairing.renamed[i])
+            .unwrap_or(sources[0]);
+        pairing.pair(source, target, 100);
+    }
+
+    let remaining: Vec<usize> = (0..added.len()).filter(|&i| pairing.matched[i].is_none()).collect();
+    if remaining.is_empty() || remaining.len() * candidates.len() > MAX_INEXACT_PAIRS {
+        return Ok(pairing.finish());
+    }
+
+    let source_contents = candidates.iter().map(|(file, _)| load(file)).collect::<Result<Vec<_>>>()?;
+    let source_lines: Vec<LineCounts> = source_contents.iter().map(|content| LineCounts::new(content)).collect();
+    let mut scores = Vec::new();
+    for &target in &remaining {
+        let content = load(&added[target])?;
+        let lines = LineCounts::new(&content);
+        for (source, source_lines) in source_lines.iter().enumerate() {
+            let similarity = source_lines.similarity(&lines, options.threshold);
+            if similarity >= options.threshold {
+                scores.push((similarity, target, source));
+            }
+        }
+    }
+
+    // Best matches first; ties go to the earlier path.
+    scores.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    for (similarity, target, source) in scores {
+        if pairing.matched[target].is_none() {
+            pairing.pair(source, target, similarity);
+        }
+    }
+
+    Ok(pairing.finish())
+}
+
+/// The matches found so far. `candidates` are the possible sources, flagged
+/// whether they were deleted.
+struct Pairing<'a> {
+    candidates: &'a [(&'a FileVersion, bool)],
+    added: &'a [FileVersion],
+    copies: bool,
+    renamed: Vec<bool>,
+    matched: Vec<Option<Rename>>,
+}
+
+impl Pairing<'_> {
+    /// Records `added[target]` as a rename or copy of `candidates[source]`,
+    /// unless that would be a copy without copy detection.
+    fn pair(&mut self, source: usize, target: usize, similarity: u8) {
+        let (file, deleted) = self.candidates[source];
+        if (!deleted || self.renamed[source]) && !self.copies {
+            return;
+        }
+        self.renamed[source] |= deleted;
+        self.matched[target] = Some(Rename {
+            from: file.path.clone(),
+            to: self.added[target].path.clone(),
+            similarity,
+            copy: !deleted,
+        });
+    }
+
+    /// The matches sorted by new path, all but the last match of each
+    /// deleted file turned into copies.
+    fn finish(self) -> Vec<Rename> {
+        let mut renames: Vec<Rename> = self.matched.into_iter().flatten().collect();
+        renames.sort_by(|a, b| a.to.cmp(&b.to));
+        for i in 0..renames.len() {
+            if !renames[i].copy && renames[i + 1..].iter().any(|later| !later.copy && later.from == renames[i].from) {
+                renames[i].copy = true;
+            }
+        }
+        renames
+    }
+}
+
+/// How often each line occurs in a file, with the file's size.
+struct LineCounts<'a> {
+    lines: HashMap<&'a [u8], usize>,
+    size: usize,
+}
+
+impl<'a> LineCounts<'a> {
+    fn new(content: &'a [u8]) -> Self {
+        let mut lines = HashMap::new();
+        for line in content.split_inclusive(|&b| b == b'\n') {
+            *lines.entry(line).or_insert(0) += 1;
+        }
+        LineCounts { lines, size: content.len() }
+    }
+
+    /// Bytes of lines both files share, in percent of the larger file. Pairs
+    /// that cannot reach `threshold` given their sizes are not compared.
+    fn similarity(&self, other: &LineCounts, threshold: u8) -> u8 {
+        let (smaller, larger) = (self.size.min(other.size), self.size.max(other.size));
+        // Empty files carry no content to recognise.
+        if smaller == 0 || smaller * 100 < larger * threshold as usize {
+            return 0;
+        }
+        let shared: usize = self
+            .lines
+            .iter()
+            .map(|(line, count)| count.min(other.lines.get(line).unwrap_or(&0)) * line.len())
+            .sum();
+        (shared * 100 / larger) as u8
+    }
+}
+
+/// `old => new` with a common leading and trailing directory written once,
+/// as in `src/{a.rs => b.rs}`.
+pub fn display_rename(from: &str, to: &str) -> String {
+    let prefix_len = from
+        .char_indices()
+        .zip(to.chars())
+        .take_while(|((_, a), b)| a == b)
+        .filter(|((_, a), _)| *a == '/')
+        .map(|((i, _), _)| i + 1)
+        .last()
+        .unwrap_or(0);
+    let (from_rest, to_rest) = (&from[prefix_len..], &to[prefix_len..]);
+    let suffix_len = from_rest
+        .char_indices()
+        .rev()
+        .zip(to_rest.chars().rev())
+        .take_while(|((_, a), b)| a == b)
+        .filter(|((_, a), _)| *a == '/')
+        .map(|((i, _), _)| from_rest.len() - i)
+        .last()
+        .unwrap_or(0)
+        .min(from_rest.len().min(to_rest.len()));
+
+    if prefix_len == 0 && suffix_len == 0 {
+        return format!("{} => {}", from, to);
+    }
+    format!(
+        "{}{{{} => {}}}{}",
+        &from[..prefix_len],
+        &from_rest[..from_rest.len() - suffix_len],
+        &to_rest[..to_rest.len() - suffix_len],
+        &from_rest[from_rest.len() - suffix_len..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, content: &str) -> (FileVersion, String) {
+        (FileVersion { path: path.to_string(), hash: format!("{:?}", content) }, content.to_string())
+    }
+
+    fn run(deleted: &[(FileVersion, String)], added: &[(FileVersion, String)], sources: &[(FileVersion, String)], options: RenameOptions) -> Vec<(String, String, u8, bool)> {
+        let contents: HashMap<String, String> = deleted
+            .iter()
+            .chain(added)
+            .chain(sources)
+            .map(|(file, content)| (file.hash.clone(), content.clone()))
+            .collect();
+        let versions = |files: &[(FileVersion, String)]| files.iter().map(|(file, _)| file.clone()).collect::<Vec<_>>();
+        detect(&versions(deleted), &versions(added), &versions(sources), &options, |file| {
+            Ok(contents[&file.hash].clone().into_bytes())
+        })
+        .unwrap()
+        .into_iter()
+        .map(|rename| (rename.from, rename.to, rename.similarity, rename.copy))
+        .collect()
+    }
+
+    fn pair(from: &str, to: &str, similarity: u8, copy: bool) -> (String, String, u8, bool) {
+        (from.to_string(), to.to_string(), similarity, copy)
+    }
+
+    #[test]
+    fn similarity_arguments() {
+        assert_eq!("".parse(), Ok(Similarity(DEFAULT_SIMILARITY)));
+        assert_eq!("75%".parse(), Ok(Similarity(75)));
+        assert_eq!("5".parse(), Ok(Similarity(50)));
+        assert_eq!("05".parse(), Ok(Similarity(5)));
+        assert_eq!("100%".parse(), Ok(Similarity(100)));
+        assert!("101%".parse::<Similarity>().is_err());
+        assert!("half".parse::<Similarity>().is_err());
+    }
+
+    #[test]
+    fn renames_are_scored_by_shared_lines() {
+        let old = "one\ntwo\nthree\nfour\n";
+        let deleted = [file("a.txt", old), file("other.txt", "unrelated\n")];
+        let added = [file("b.txt", old), file("c.txt", "one\ntwo\nthree\nFOUR\n"), file("d.txt", "new\n")];
+
+        // The exact match is the rename; the edited copy is left alone
+        // without copy detection, since a.txt is already taken.
+        assert_eq!(run(&deleted, &added, &[], RenameOptions::default()), [pair("a.txt", "b.txt", 100, false)]);
+
+        let edited = [file("c.txt", "one\ntwo\nthree\nFOUR\n")];
+        assert_eq!(run(&deleted, &edited, &[], RenameOptions::default()), [pair("a.txt", "c.txt", 73, false)]);
+        let strict = RenameOptions { threshold: 80, copies: false };
+        assert!(run(&deleted, &edited, &[], strict).is_empty());
+    }
+
+    #[test]
+    fn copies_come_from_kept_or_renamed_files() {
+        let content = "shared\ncontent\n";
+        let kept = [file("kept.txt", content)];
+        let added = [file("copy.txt", content)];
+        assert!(run(&[], &added, &kept, RenameOptions::default()).is_empty());
+        let copies = RenameOptions { copies: true, ..RenameOptions::default() };
+        assert_eq!(run(&[], &added, &kept, copies), [pair("kept.txt", "copy.txt", 100, true)]);
+
+        // One deleted file matching two new ones: the last is the rename.
+        let deleted = [file("old.txt", content)];
+        let added = [file("a.txt", content), file("b.txt", content)];
+        assert_eq!(
+            run(&deleted, &added, &[], copies),
+            [pair("old.txt", "a.txt", 100, true), pair("old.txt", "b.txt", 100, false)]
+        );
+    }
+
+    #[test]
+    fn empty_files_are_not_renames() {
+        let deleted = [file("a.txt", "a\n")];
+        let added = [file("b.txt", "")];
+        assert!(run(&deleted, &added, &[], RenameOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn rename_display() {
+        assert_eq!(display_rename("a.txt", "b.txt"), "a.txt => b.txt");
+        assert_eq!(display_rename("src/a.rs", "src/b.rs"), "src/{a.rs => b.rs}");
+        assert_eq!(display_rename("src/old/mod.rs", "src/new/mod.rs"), "src/{old => new}/mod.rs");
+        assert_eq!(display_rename("a/x.rs", "b/x.rs"), "{a => b}/x.rs");
+    }
+}
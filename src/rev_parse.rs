@@ -0,0 +1,504 @@
+//! Revision expressions, shared by `checkout`, `log`, `diff`, `reset`, `tag`
+//! and `rev-parse`.
+//!
+//! A revision is a name followed by any number of suffixes:
+//!
+//! * names — `HEAD` (or `@`), a full ref such as `refs/heads/main`, a tag,
+//!   branch or remote-tracking branch, a full object hash, or an abbreviated
+//!   hash of at least [`MIN_ABBREV`] characters that names a single object.
+//!   Like git, tags are tried before branches.
+//! * `@{n}` — the n-th previous value of the ref from its reflog, straight
+//!   after the name. `HEAD@{n}` reads HEAD's reflog, `@{n}` the current
+//!   branch's.
+//! * `~n` — the n-th first-parent ancestor (`~` is `~1`).
+//! * `^n` — the n-th parent (`^` is `^1`, `^0` is the commit itself).
+//! * `^{}`, `^{commit}`, `^{tree}` — peel tags, or a commit to its tree.
+//!
+//! Ranges are `A..B`, the commits reachable from `B` but not from `A`, and
+//! `A...B`, those reachable from exactly one side. An omitted side is `HEAD`.
+
+use crate::commands::merge;
+use crate::encoding::ObjectKind;
+use crate::{Commit, CrabGitError, Repository, Result, object_store::ObjectStore, reflog, utils};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+
+/// Shortest abbreviated hash that is looked up among the objects.
+pub const MIN_ABBREV: usize = 4;
+
+/// A revision range, with both ends resolved to commits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevRange {
+    /// Everything reachable from the commit.
+    Single(String),
+    /// `A..B`: reachable from `include` but not from `exclude`.
+    Range { exclude: String, include: String },
+    /// `A...B`: reachable from one side but not both.
+    Symmetric { left: String, right: String },
+}
+
+impl RevRange {
+    /// The commits to start from and the commits whose ancestors are left out.
+    pub fn tips(&self, object_store: &ObjectStore) -> Result<(Vec<String>, Vec<String>)> {
+        match self {
+            RevRange::Single(commit) => Ok((vec![commit.clone()], Vec::new())),
+            RevRange::Range { exclude, include } => Ok((vec![include.clone()], vec![exclude.clone()])),
+            // Every merge base is excluded: after a criss-cross merge the
+            // commits reachable from either base are reachable from both sides.
+            RevRange::Symmetric { left, right } => {
+                let bases = merge::merge_bases(object_store, left, right)?;
+                Ok((vec![left.clone(), right.clone()], bases))
+            }
+        }
+    }
+}
+
+/// Resolves a revision to an object hash. Annotated tags resolve to the tag
+/// object itself unless a suffix peels them.
+pub fn resolve(repo: &Repository, object_store: &ObjectStore, spec: &str) -> Result<String> {
+    let not_found = || CrabGitError::RefNotFound(spec.to_string());
+
+    let base_end = spec.find(['~', '^']).unwrap_or(spec.len());
+    let (mut name, mut suffixes) = spec.split_at(base_end);
+    let mut reflog = None;
+    if let Some(at) = name.find("@{") {
+        let count = name[at + 2..].strip_suffix('}').ok_or_else(not_found)?;
+        reflog = Some(count.parse::<usize>().map_err(|_| not_found())?);
+        name = &name[..at];
+    }
+
+    let (mut hash, ref_name) = match resolve_name(repo, object_store, name)? {
+        Some(resolved) => resolved,
+        None => return Err(not_found()),
+    };
+    if let Some(n) = reflog {
+        // `HEAD@{n}` reads HEAD's own log, `@{n}` the current branch's.
+        let ref_name = if name == "HEAD" { "HEAD".to_string() } else { ref_name.ok_or_else(not_found)? };
+        hash = nth_ref_value(repo, &ref_name, &hash, n)?;
+    }
+
+    while let Some(operator) = suffixes.chars().next() {
+        suffixes = &suffixes[operator.len_utf8()..];
+
+        if operator == '^' && suffixes.starts_with('{') {
+            let end = suffixes.find('}').ok_or_else(not_found)?;
+            hash = peel(object_store, &hash, &suffixes[1..end]).map_err(|_| not_found())?;
+            suffixes = &suffixes[end + 1..];
+            continue;
+        }
+
+        let digits = suffixes.find(|c: char| !c.is_ascii_digit()).unwrap_or(suffixes.len());
+        let count = match &suffixes[..digits] {
+            "" => 1,
+            digits => digits.parse::<usize>().map_err(|_| not_found())?,
+        };
+        suffixes = &suffixes[digits..];
+
+        let commit = peel(object_store, &hash, "commit").map_err(|_| not_found())?;
+        hash = match operator {
+            '~' => {
+                let mut commit = commit;
+                for _ in 0..count {
+                    commit = object_store.load_commit(&commit)?.parents.first().cloned().ok_or_else(not_found)?;
+                }
+                commit
+            }
+            '^' if count == 0 => commit,
+            '^' => object_store.load_commit(&commit)?.parents.get(count - 1).cloned().ok_or_else(not_found)?,
+            _ => return Err(not_found()),
+        };
+    }
+
+    Ok(hash)
+}
+
+/// Resolves a revision and peels it to a commit.
+pub fn resolve_commit(repo: &Repository, object_store: &ObjectStore, spec: &str) -> Result<String> {
+    let hash = resolve(repo, object_store, spec)?;
+    peel(object_store, &hash, "commit")
+        .map_err(|_| CrabGitError::InvalidOperation(format!("'{}' does not name a commit", spec)))
+}
+
+/// Whether `spec` is a range: it contains `..` or `...` and both sides name
+/// commits, so that a path such as `../notes` is not taken for one.
+pub fn is_range(repo: &Repository, object_store: &ObjectStore, spec: &str) -> bool {
+    let resolves = |name: &str| resolve_commit(repo, object_store, if name.is_empty() { "HEAD" } else { name }).is_ok();
+    match spec.split_once("...").or_else(|| spec.split_once("..")) {
+        Some((left, right)) => resolves(left) && resolves(right),
+        None => false,
+    }
+}
+
+/// Parses `A..B`, `A...B` or a single revision.
+pub fn resolve_range(repo: &Repository, object_store: &ObjectStore, spec: &str) -> Result<RevRange> {
+    let side = |name: &str| resolve_commit(repo, object_store, if name.is_empty() { "HEAD" } else { name });
+
+    if let Some((left, right)) = spec.split_once("...") {
+        Ok(RevRange::Symmetric { left: side(left)?, right: side(right)? })
+    } else if let Some((exclude, include)) = spec.split_once("..") {
+        Ok(RevRange::Range { exclude: side(exclude)?, include: side(include)? })
+    } else {
+        Ok(RevRange::Single(resolve_commit(repo, object_store, spec)?))
+    }
+}
+
+/// The commits reachable from `include` but not from `exclude`, newest first.
+pub fn rev_list(object_store: &ObjectStore, include: &[String], exclude: &[String]) -> Result<Vec<Commit>> {
+    let mut excluded = HashSet::new();
+    for commit in exclude {
+        excluded.extend(merge::ancestors(object_store, commit)?);
+    }
+
+    // Newest first; equal timestamps keep the order the commits were found in.
+    let mut queue = BinaryHeap::new();
+    let mut found: HashMap<String, Commit> = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut enqueue = |hash: &String, queue: &mut BinaryHeap<_>, found: &mut HashMap<_, _>| -> Result<()> {
+        if seen.insert(hash.clone()) {
+            let commit = object_store.load_commit(hash)?;
+            queue.push((commit.timestamp, Reverse(seen.len()), hash.clone()));
+            found.insert(hash.clone(), commit);
+        }
+        Ok(())
+    };
+    for hash in include {
+        enqueue(hash, &mut queue, &mut found)?;
+    }
+
+    let mut commits = Vec::new();
+    while let Some((_, _, hash)) = queue.pop() {
+        let commit = found.remove(&hash).expect("queued commits are loaded");
+        for parent in &commit.parents {
+            enqueue(parent, &mut queue, &mut found)?;
+        }
+        if !excluded.contains(&commit.hash) {
+            commits.push(commit);
+        }
+    }
+    Ok(commits)
+}
+
+/// Reorders `commits` (as returned by [`rev_list`]) so that no commit comes
+/// before one of its children. Like git's `--topo-order`, a line of history
+/// is followed to its end before the next one starts, so branches are not
+/// interleaved.
+pub fn topo_order(commits: Vec<Commit>) -> Vec<Commit> {
+    let positions: HashMap<&str, usize> = commits
+        .iter()
+        .enumerate()
+        .map(|(i, commit)| (commit.hash.as_str(), i))
+        .collect();
+    let mut children = vec![0; commits.len()];
+    for commit in &commits {
+        for parent in &commit.parents {
+            if let Some(&i) = positions.get(parent.as_str()) {
+                children[i] += 1;
+            }
+        }
+    }
+
+    // A stack: the newest tip comes first, and of a merge's parents the
+    // last one, the merged branch, is shown first.
+    let mut ready: Vec<usize> = (0..commits.len()).rev().filter(|&i| children[i] == 0).collect();
+    let mut order = Vec::with_capacity(commits.len());
+    while let Some(i) = ready.pop() {
+        order.push(i);
+        for parent in &commits[i].parents {
+            if let Some(&j) = positions.get(parent.as_str()) {
+                children[j] -= 1;
+                if children[j] == 0 {
+                    ready.push(j);
+                }
+            }
+        }
+    }
+
+    let mut commits: Vec<Option<Commit>> = commits.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| commits[i].take()).collect()
+}
+
+/// The full ref name, e.g. `refs/heads/main`, that a revision without
+/// suffixes names; `HEAD` gives the current branch's ref, or `HEAD` when
+/// detached. `None` for object hashes and unknown names.
+pub fn full_ref_name(repo: &Repository, object_store: &ObjectStore, name: &str) -> Result<Option<String>> {
+    Ok(resolve_name(repo, object_store, name)?.and_then(|(_, ref_name)| ref_name))
+}
+
+/// A full ref name without its `refs/heads/`, `refs/tags/` or
+/// `refs/remotes/` prefix.
+pub fn short_ref_name(ref_name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| ref_name.strip_prefix(prefix))
+        .unwrap_or(ref_name)
+}
+
+/// The shortest prefix of `hash`, at least `min_len` characters long, that
+/// names no other object.
+pub fn abbreviate(object_store: &ObjectStore, hash: &str, min_len: usize) -> Result<String> {
+    let mut len = min_len.clamp(MIN_ABBREV, hash.len());
+    while len < hash.len() && object_store.objects_with_prefix(&hash[..len])?.len() > 1 {
+        len += 1;
+    }
+    Ok(hash[..len].to_string())
+}
+
+/// Resolves a name without suffixes to an object hash and, when it names a
+/// ref, the full ref name.
+fn resolve_name(repo: &Repository, object_store: &ObjectStore, name: &str) -> Result<Option<(String, Option<String>)>> {
+    let name = if name.is_empty() || name == "@" { "HEAD" } else { name };
+
+    if name == "HEAD" {
+        let ref_name = match utils::get_current_branch(repo)?.as_str() {
+            "detached" => "HEAD".to_string(),
+            branch => format!("refs/heads/{}", branch),
+        };
+        return Ok(utils::get_head_commit(repo)?.map(|hash| (hash, Some(ref_name))));
+    }
+    if object_store.object_exists(name) {
+        return Ok(Some((name.to_string(), None)));
+    }
+
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for candidate in candidates {
+        // Only ALL_CAPS names like MERGE_HEAD live directly in the repository directory.
+        if !candidate.starts_with("refs/") && !candidate.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+            continue;
+        }
+        if candidate.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            continue;
+        }
+        let path = repo.git_dir.join(&candidate);
+        if path.is_file() {
+            let hash = fs::read_to_string(path)?.trim().to_string();
+            return Ok(Some((hash, Some(candidate))));
+        }
+    }
+
+    if name.len() >= MIN_ABBREV {
+        let matches = object_store.objects_with_prefix(name)?;
+        match matches.len() {
+            0 => {}
+            1 => return Ok(matches.into_iter().next().map(|hash| (hash, None))),
+            _ => {
+                return Err(CrabGitError::InvalidOperation(format!(
+                    "short object ID {} is ambiguous",
+                    name
+                )))
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The value `ref_name` had `n` updates ago according to its reflog;
+/// `n == 0` is its current value.
+fn nth_ref_value(repo: &Repository, ref_name: &str, current: &str, n: usize) -> Result<String> {
+    if n == 0 {
+        return Ok(current.to_string());
+    }
+    let entries = reflog::read(repo, ref_name)?;
+    match entries.len().checked_sub(n + 1) {
+        Some(index) => Ok(entries[index].new.clone()),
+        None => Err(CrabGitError::InvalidOperation(format!(
+            "log for '{}' only has {} entries",
+            ref_name,
+            entries.len()
+        ))),
+    }
+}
+
+/// Follows tags until an object of `kind` is reached; `""` stops at the first
+/// object that is not a tag. A commit peels to its tree.
+fn peel(object_store: &ObjectStore, hash: &str, kind: &str) -> Result<String> {
+    let hash = utils::peel_to_commit(object_store, hash)?;
+    let actual = object_store.object_kind(&hash)?;
+    match (kind, actual) {
+        ("", _) => Ok(hash),
+        ("commit", ObjectKind::Commit) => Ok(hash),
+        ("tree", ObjectKind::Tree) => Ok(hash),
+        ("tree", ObjectKind::Commit) => Ok(object_store.load_commit(&hash)?.tree),
+        _ => Err(CrabGitError::InvalidOperation(format!("{} is not a {}", hash, kind))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{commit, tag};
+    use crate::refs::Expected;
+    use crate::testing::TestRepo;
+
+    /// `root <- a <- merge`, where `merge` also has `side` (a child of
+    /// `root`) as its second parent. `main` points at `merge`.
+    struct History {
+        test: TestRepo,
+        root: String,
+        a: String,
+        side: String,
+        merge: String,
+    }
+
+    fn history() -> History {
+        let test = TestRepo::new();
+        let object_store = ObjectStore::new(&test.repo);
+        test.write("file.txt", "root\n");
+        test.commit("root");
+        let root = test.head();
+        let tree = object_store.load_commit(&root).unwrap().tree;
+        let make = |parents: Vec<String>, message: &str| {
+            commit::create_commit(&object_store, tree.clone(), parents, "A <a@b>".into(), message.into()).unwrap()
+        };
+
+        let a = make(vec![root.clone()], "a");
+        let side = make(vec![root.clone()], "side");
+        let merge = make(vec![a.clone(), side.clone()], "merge");
+        utils::update_branch(&test.repo, "main", &merge, Expected::Any, "reset: moving to merge").unwrap();
+        utils::update_branch(&test.repo, "side", &side, Expected::Missing, "branch: Created from root").unwrap();
+        History { test, root, a, side, merge }
+    }
+
+    fn rev(history: &History, spec: &str) -> Result<String> {
+        resolve(&history.test.repo, &ObjectStore::new(&history.test.repo), spec)
+    }
+
+    #[test]
+    fn names_and_suffixes() {
+        let h = history();
+        assert_eq!(rev(&h, "HEAD").unwrap(), h.merge);
+        assert_eq!(rev(&h, "@").unwrap(), h.merge);
+        assert_eq!(rev(&h, "refs/heads/side").unwrap(), h.side);
+        assert_eq!(rev(&h, &h.side[..10]).unwrap(), h.side);
+        assert_eq!(rev(&h, "main~").unwrap(), h.a);
+        assert_eq!(rev(&h, "main~2").unwrap(), h.root);
+        assert_eq!(rev(&h, "main^").unwrap(), h.a);
+        assert_eq!(rev(&h, "main^2").unwrap(), h.side);
+        assert_eq!(rev(&h, "main^2~1").unwrap(), h.root);
+        assert_eq!(rev(&h, "main^0").unwrap(), h.merge);
+        assert_eq!(rev(&h, "HEAD@{0}").unwrap(), h.merge);
+
+        let object_store = ObjectStore::new(&h.test.repo);
+        let tree = object_store.load_commit(&h.merge).unwrap().tree;
+        assert_eq!(rev(&h, "HEAD^{tree}").unwrap(), tree);
+    }
+
+    #[test]
+    fn previous_values_come_from_the_reflog() {
+        let h = history();
+        assert_eq!(rev(&h, "main@{1}").unwrap(), h.root);
+        assert_eq!(rev(&h, "HEAD@{1}").unwrap(), h.root);
+        assert_eq!(rev(&h, "@{1}~0").unwrap(), h.root);
+        assert_eq!(rev(&h, "side@{0}").unwrap(), h.side);
+        assert!(rev(&h, "side@{1}").is_err());
+    }
+
+    #[test]
+    fn annotated_tags_peel_on_request() {
+        let h = history();
+        tag(&h.test.repo, Some("v1".into()), Some(h.a.clone()), Some("release".into()), false, None).unwrap();
+
+        let tag_object = rev(&h, "v1").unwrap();
+        assert_ne!(tag_object, h.a);
+        assert_eq!(rev(&h, "v1^{}").unwrap(), h.a);
+        assert_eq!(rev(&h, "v1^{commit}").unwrap(), h.a);
+        assert_eq!(rev(&h, "v1~1").unwrap(), h.root);
+        let object_store = ObjectStore::new(&h.test.repo);
+        assert_eq!(resolve_commit(&h.test.repo, &object_store, "v1").unwrap(), h.a);
+    }
+
+    #[test]
+    fn invalid_specs_are_not_found() {
+        let h = history();
+        for spec in [
+            "missing",
+            "main~3",
+            "main^3",
+            "main~1x",
+            "main^é",
+            "main~é",
+            "main^{",
+            "main^{blob}",
+            "main@{x}",
+            "main@{1",
+            "../main",
+        ] {
+            assert!(rev(&h, spec).is_err(), "{} resolved", spec);
+        }
+        let err = rev(&h, "main~1x").unwrap_err();
+        assert!(matches!(err, CrabGitError::RefNotFound(_)), "{}", err);
+    }
+
+    #[test]
+    fn ranges_need_revisions_on_both_sides() {
+        let h = history();
+        let object_store = ObjectStore::new(&h.test.repo);
+        let is_range = |spec: &str| is_range(&h.test.repo, &object_store, spec);
+
+        assert!(is_range("side..main"));
+        assert!(is_range("side...main"));
+        assert!(is_range("..side"));
+        assert!(!is_range("main"));
+        assert!(!is_range("../notes.txt"));
+        assert!(!is_range("main..missing"));
+
+        let range = resolve_range(&h.test.repo, &object_store, "side..main").unwrap();
+        assert_eq!(range, RevRange::Range { exclude: h.side.clone(), include: h.merge.clone() });
+        let (include, exclude) = range.tips(&object_store).unwrap();
+        let hashes: Vec<String> =
+            rev_list(&object_store, &include, &exclude).unwrap().into_iter().map(|c| c.hash).collect();
+        assert_eq!(hashes.len(), 2);
+        assert!(hashes.contains(&h.merge) && hashes.contains(&h.a));
+    }
+
+    #[test]
+    fn symmetric_difference_excludes_every_merge_base() {
+        let test = TestRepo::new();
+        let object_store = ObjectStore::new(&test.repo);
+        let tree = object_store.write_tree(&crate::Index::default().entries).unwrap();
+        let make = |parents: &[&String], message: &str| {
+            let parents = parents.iter().map(|p| p.to_string()).collect();
+            commit::create_commit(&object_store, tree.clone(), parents, "A <a@b>".into(), message.into()).unwrap()
+        };
+
+        let root = make(&[], "root");
+        let left = make(&[&root], "left");
+        let right = make(&[&root], "right");
+        let left_merge = make(&[&left, &right], "left merge");
+        let right_merge = make(&[&right, &left], "right merge");
+
+        let range = RevRange::Symmetric { left: left_merge.clone(), right: right_merge.clone() };
+        let (include, exclude) = range.tips(&object_store).unwrap();
+        let mut hashes: Vec<String> =
+            rev_list(&object_store, &include, &exclude).unwrap().into_iter().map(|c| c.hash).collect();
+        hashes.sort();
+        let mut expected = vec![left_merge, right_merge];
+        expected.sort();
+        assert_eq!(hashes, expected);
+    }
+
+    #[test]
+    fn topo_order_finishes_the_merged_branch_first() {
+        let h = history();
+        let object_store = ObjectStore::new(&h.test.repo);
+        let commits = rev_list(&object_store, std::slice::from_ref(&h.merge), &[]).unwrap();
+        let order: Vec<String> = topo_order(commits).into_iter().map(|commit| commit.hash).collect();
+        assert_eq!(order, [h.merge.clone(), h.side.clone(), h.a.clone(), h.root.clone()]);
+    }
+
+    #[test]
+    fn abbreviations_grow_until_unique() {
+        let h = history();
+        let object_store = ObjectStore::new(&h.test.repo);
+        let short = abbreviate(&object_store, &h.merge, 7).unwrap();
+        assert!(short.len() >= 7 && h.merge.starts_with(&short));
+        assert!(abbreviate(&object_store, &h.merge, 1).unwrap().len() >= MIN_ABBREV);
+    }
+}
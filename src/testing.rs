@@ -0,0 +1,93 @@
+//! Scratch repositories for unit tests.
+//!
+//! Commands resolve paths against the current directory, so every test that
+//! touches a repository holds a process-wide lock for as long as it is
+//! inside one.
+
+use crate::{commands, utils, Repository};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+static LOCK: Mutex<()> = Mutex::new(());
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) struct TestRepo {
+    pub root: PathBuf,
+    pub repo: Repository,
+    previous_dir: PathBuf,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestRepo {
+    pub fn new() -> Self {
+        Self::init(false)
+    }
+
+    /// A repository created with `init --git`.
+    pub fn git() -> Self {
+        Self::init(true)
+    }
+
+    fn init(git_compatible: bool) -> Self {
+        let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let root = std::env::temp_dir().join(format!(
+            "crabgit-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let previous_dir = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&root).unwrap();
+
+        commands::init(Some(root.clone()), git_compatible, None).unwrap();
+        let repo = utils::get_repository(None).unwrap();
+        Self { root, repo, previous_dir, _guard: guard }
+    }
+
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub fn write(&self, path: &str, content: &str) {
+        let full_path = self.path(path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(full_path, content).unwrap();
+    }
+
+    pub fn add_all(&self) {
+        commands::add(&self.repo, vec![".".to_string()], false).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.path(path)).unwrap()
+    }
+
+    /// Changes the current directory to `dir` inside the work tree.
+    pub fn cd(&self, dir: &str) {
+        std::env::set_current_dir(self.path(dir)).unwrap();
+    }
+
+    /// Stages everything in the working tree and commits it.
+    pub fn commit(&self, message: &str) {
+        self.add_all();
+        commands::commit(&self.repo, Some(message.to_string()), None).unwrap();
+    }
+
+    pub fn head(&self) -> String {
+        let branch = utils::get_current_branch(&self.repo).unwrap();
+        utils::get_branch_commit(&self.repo, &branch).unwrap().unwrap()
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = std::env::set_current_dir(&self.previous_dir);
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
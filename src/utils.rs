@@ -1,12 +1,36 @@
-use crate::{Index, IndexEntry, Repository, Result};
+use crate::config::Config;
+use crate::encoding::{ObjectKind, OBJECT_VERSION};
+use crate::object_store::HashAlgorithm;
+use crate::lockfile::{self, LockFile};
+use crate::refs::{Expected, RefTransaction};
+use crate::{CrabGitError, Index, IndexEntry, Repository, Result, TreeEntry, git_index, object_store::ObjectStore};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
+pub const CRAB_GIT_DIR: &str = ".crab_git";
+/// Directory of a git-compatible repository.
+pub const GIT_DIR: &str = ".git";
+/// Config entry `init --git` sets to mark a `.git` directory as one CrabGit
+/// may work on. Repositories created by git lack it: CrabGit cannot read
+/// their packed objects and refs, and would rewrite their index without the
+/// data git keeps in it.
+pub const GIT_COMPATIBLE_KEY: &str = "crabgit.gitcompatible";
+
+/// Finds the repository directory for `start_path` or its closest ancestor.
+/// A `.git` directory only counts when `init --git` marked it, otherwise the
+/// search goes on upwards, past the git checkout.
 pub fn find_git_dir(start_path: &Path) -> Option<PathBuf> {
     let mut path = start_path.to_path_buf();
     loop {
-        let git_dir = path.join(".crab_git");
-        if git_dir.exists() && git_dir.is_dir() {
+        let crab_git_dir = path.join(CRAB_GIT_DIR);
+        if crab_git_dir.is_dir() {
+            return Some(crab_git_dir);
+        }
+        let git_dir = path.join(GIT_DIR);
+        if git_dir.is_dir() && is_marked_git_dir(&git_dir) {
             return Some(git_dir);
         }
 
@@ -17,37 +41,170 @@ pub fn find_git_dir(start_path: &Path) -> Option<PathBuf> {
     None
 }
 
+fn is_marked_git_dir(git_dir: &Path) -> bool {
+    Config::load(git_dir).is_ok_and(|config| config.get_bool(GIT_COMPATIBLE_KEY) == Some(true))
+}
+
 pub fn get_repository(work_dir: Option<PathBuf>) -> Result<Repository> {
     let work_dir = work_dir.unwrap_or_else(|| std::env::current_dir().unwrap());
 
-    if let Some(git_dir) = find_git_dir(&work_dir) {
-        Ok((Repository { 
-            git_dir: git_dir.clone(),
-            work_dir: git_dir.parent().unwrap().to_path_buf()
-        }))
-    } else {
-        Err(format!("Not a crab git repository: {}", work_dir.display()).into())
+    let Some(git_dir) = find_git_dir(&work_dir) else {
+        return Err(CrabGitError::NotARepository(work_dir));
+    };
+
+    let git_compatible = git_dir.file_name().is_some_and(|name| name == GIT_DIR);
+    let config = Config::load(&git_dir)?;
+    let hash_algorithm = match config.get("extensions.objectformat") {
+        Some(format) => format.parse().map_err(CrabGitError::InvalidOperation)?,
+        None if git_compatible => HashAlgorithm::Sha1,
+        None => HashAlgorithm::Sha256,
+    };
+
+    Ok(Repository {
+        work_dir: git_dir.parent().unwrap().to_path_buf(),
+        git_dir,
+        hash_algorithm,
+        git_compatible,
+    })
+}
+
+/// The object encoding version recorded in the config; repositories created
+/// before it was recorded use the legacy JSON encoding, version 1. Git
+/// repositories always use the current encoding.
+pub fn object_version(repo: &Repository, config: &Config) -> u32 {
+    if repo.git_compatible {
+        return OBJECT_VERSION;
+    }
+    config
+        .get("core.objectversion")
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Refuses to work on a repository whose objects need `migrate` first.
+pub fn ensure_current_format(repo: &Repository) -> Result<()> {
+    let version = object_version(repo, &Config::for_repo(repo)?);
+    if version < OBJECT_VERSION {
+        return Err(CrabGitError::InvalidOperation(format!(
+            "This repository uses object format version {}, run `crab_git migrate` to convert it to version {}",
+            version, OBJECT_VERSION
+        )));
     }
+    Ok(())
+}
+
+/// Version of the JSON index. Version 1 indexes (without a `version` field)
+/// only held the paths staged since the last commit rather than the whole
+/// next commit.
+const INDEX_VERSION: u32 = 2;
+
+#[derive(Deserialize)]
+struct StoredIndex {
+    #[serde(default = "legacy_index_version")]
+    version: u32,
+    #[serde(flatten)]
+    index: Index,
+}
+
+#[derive(Serialize)]
+struct StoredIndexRef<'a> {
+    version: u32,
+    #[serde(flatten)]
+    index: &'a Index,
+}
+
+fn legacy_index_version() -> u32 {
+    1
 }
 
+/// Loads the index, which holds the complete tree of the next commit. Without
+/// an index file it matches HEAD.
 pub fn load_index(repo: &Repository) -> Result<Index> {
     let index_path = repo.git_dir.join("index");
-    if index_path.exists() {
-        let content = fs::read_to_string(index_path)?;
-        let index: Index = serde_json::from_str(&content)?;
-        Ok(index)
-    } else {
-        Ok(
-            Index { entries: std::collections::HashMap::new() },
-        )
+    if !index_path.exists() {
+        return head_index(repo);
     }
+    if repo.git_compatible {
+        return git_index::read(&fs::read(index_path)?, repo.hash_algorithm);
+    }
+
+    let stored: StoredIndex = serde_json::from_str(&fs::read_to_string(index_path)?)?;
+    if stored.version >= INDEX_VERSION {
+        return Ok(stored.index);
+    }
+    let mut index = head_index(repo)?;
+    index.entries.extend(stored.index.entries);
+    index.unmerged = stored.index.unmerged;
+    Ok(index)
 }
 
+/// Reads a version 1 index as written, without filling in HEAD's files.
+pub fn load_legacy_index(repo: &Repository) -> Result<Index> {
+    let index_path = repo.git_dir.join("index");
+    if !index_path.exists() {
+        return Ok(Index::default());
+    }
+    let stored: StoredIndex = serde_json::from_str(&fs::read_to_string(index_path)?)?;
+    Ok(stored.index)
+}
+
+/// Writes the index through `index.lock`, failing if another process holds it.
 pub fn save_index(repo: &Repository, index: &Index) -> Result<()> {
+    write_index(repo, lock_index(repo)?, index)
+}
+
+/// Takes `index.lock`, for commands that must hold the index while they
+/// change the work tree so the two cannot get out of step.
+pub fn lock_index(repo: &Repository) -> Result<LockFile> {
     let index_path = repo.git_dir.join("index");
-    let content = serde_json::to_string_pretty(index)?;
-    fs::write(index_path, content)?;
-    Ok(())
+    LockFile::acquire(&index_path)?.ok_or_else(|| CrabGitError::IndexLocked(lockfile::lock_path(&index_path)))
+}
+
+/// Writes the index through a lock taken with [`lock_index`].
+pub fn write_index(repo: &Repository, mut lock: LockFile, index: &Index) -> Result<()> {
+    if repo.git_compatible {
+        lock.write_all(&git_index::write(index, repo.hash_algorithm)?)?;
+    } else {
+        let stored = StoredIndexRef {
+            version: INDEX_VERSION,
+            index,
+        };
+        lock.write_all(serde_json::to_string_pretty(&stored)?.as_bytes())?;
+    }
+    lock.commit()
+}
+
+/// An index matching the HEAD commit, or an empty one before the first commit.
+pub fn head_index(repo: &Repository) -> Result<Index> {
+    match get_head_commit(repo)? {
+        Some(commit) => {
+            let object_store = ObjectStore::new(repo);
+            let tree = object_store.load_commit(&commit)?.tree;
+            Ok(tree_to_index(&object_store.read_tree(&tree)?))
+        }
+        None => Ok(Index::default()),
+    }
+}
+
+/// An index holding exactly the files of a flattened tree.
+pub fn tree_to_index(files: &BTreeMap<String, TreeEntry>) -> Index {
+    let entries = files
+        .iter()
+        .map(|(path, entry)| {
+            (
+                path.clone(),
+                IndexEntry {
+                    hash: entry.hash.clone(),
+                    mode: entry.mode.clone(),
+                    path: path.clone(),
+                },
+            )
+        })
+        .collect();
+    Index {
+        entries,
+        ..Index::default()
+    }
 }
 
 pub fn get_current_branch(repo: &Repository) -> Result<String> {
@@ -65,10 +222,31 @@ pub fn get_current_branch(repo: &Repository) -> Result<String> {
     }
 }
 
-pub fn update_head(repo: &Repository, branch: &str) -> Result<()> {
+pub fn get_head_commit(repo: &Repository) -> Result<Option<String>> {
     let head_path = repo.git_dir.join("HEAD");
-    fs::write(head_path, format!("ref: refs/heads/{}", branch))?;
-    Ok(())
+    if !head_path.exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(head_path)?;
+    match content.trim().strip_prefix("ref: refs/heads/") {
+        Some(branch) => get_branch_commit(repo, branch),
+        None => Ok(Some(content.trim().to_string())),
+    }
+}
+
+/// Points HEAD at `branch`, logging the move in HEAD's reflog.
+pub fn update_head(repo: &Repository, branch: &str, reason: &str) -> Result<()> {
+    let mut transaction = RefTransaction::new(repo);
+    transaction.symbolic("HEAD", &format!("refs/heads/{}", branch), Some(reason));
+    transaction.commit()
+}
+
+/// Detaches HEAD at `commit`, logging the move in HEAD's reflog.
+pub fn detach_head(repo: &Repository, commit: &str, reason: &str) -> Result<()> {
+    let mut transaction = RefTransaction::new(repo);
+    transaction.update("HEAD", commit, Expected::Any, Some(reason));
+    transaction.commit()
 }
 
 pub fn get_branch_commit(repo: &Repository, branch: &str) -> Result<Option<String>> {
@@ -82,11 +260,274 @@ pub fn get_branch_commit(repo: &Repository, branch: &str) -> Result<Option<Strin
     }
 }
 
-pub fn update_branch(repo: &Repository, branch: &str, commit: &str) -> Result<()> {
-    let refs_heads= repo.git_dir.join("refs").join("heads");
-    fs::create_dir_all(&refs_heads)?;
+/// Points `branch` at `commit` if it still holds the `expected` value, and
+/// records `reason` in its reflog, and in HEAD's when HEAD is on that branch.
+pub fn update_branch(repo: &Repository, branch: &str, commit: &str, expected: Expected, reason: &str) -> Result<()> {
+    let mut transaction = RefTransaction::new(repo);
+    transaction.update(&format!("refs/heads/{}", branch), commit, expected, Some(reason));
+    transaction.commit()
+}
+
+pub fn get_tag_target(repo: &Repository, tag: &str) -> Result<Option<String>> {
+    let tag_path = repo.git_dir.join("refs").join("tags").join(tag);
+
+    if tag_path.exists() {
+        let target = fs::read_to_string(tag_path)?;
+        Ok(Some(target.trim().to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Creates `tag` pointing at `target`; fails if the tag appeared meanwhile.
+pub fn update_tag(repo: &Repository, tag: &str, target: &str) -> Result<()> {
+    let mut transaction = RefTransaction::new(repo);
+    transaction.update(&format!("refs/tags/{}", tag), target, Expected::Missing, None);
+    transaction.commit()
+}
+
+/// Points the current branch, or HEAD itself when detached, at `commit` if
+/// it still holds the `expected` value.
+pub fn set_head_commit(repo: &Repository, commit: &str, expected: Expected, reason: &str) -> Result<()> {
+    let mut transaction = RefTransaction::new(repo);
+    transaction.update_head(commit, expected, reason)?;
+    transaction.commit()
+}
+
+/// The identity recorded for commits and tags made without an author, and
+/// in reflogs when the config has no `user.name`.
+pub const DEFAULT_IDENTITY: &str = "Unknown email <unknown@example.com>";
+
+/// The identity recorded in reflogs: `user.name <user.email>` from the
+/// config, or [`DEFAULT_IDENTITY`].
+pub fn identity(repo: &Repository) -> Result<String> {
+    let config = Config::for_repo(repo)?;
+    match (config.get("user.name"), config.get("user.email")) {
+        (Some(name), Some(email)) => Ok(format!("{} <{}>", name, email)),
+        (Some(name), None) => Ok(format!("{} <>", name)),
+        _ => Ok(DEFAULT_IDENTITY.to_string()),
+    }
+}
+
+/// Parses a point in time: `now`, `yesterday`, `never` (the earliest time),
+/// a relative time such as `2.weeks.ago` or `3 days ago`, a date
+/// `YYYY-MM-DD`, a date and time `YYYY-MM-DD HH:MM[:SS]` in UTC, RFC 3339, or
+/// `@<unix time>`.
+pub fn parse_time(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    match value {
+        "now" | "all" => return Some(now),
+        "yesterday" => return Some(now - Duration::days(1)),
+        "never" => return Some(DateTime::<Utc>::MIN_UTC),
+        _ => {}
+    }
+
+    if let Some(seconds) = value.strip_prefix('@') {
+        return DateTime::from_timestamp(seconds.parse().ok()?, 0);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(time.and_utc());
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.and_hms_opt(0, 0, 0)?.and_utc());
+    }
+
+    let words: Vec<&str> = value.split(['.', ' ']).filter(|word| !word.is_empty()).collect();
+    let (count, unit) = match words.as_slice() {
+        [count, unit] | [count, unit, "ago"] => (count.parse::<i64>().ok()?, *unit),
+        _ => return None,
+    };
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    let duration = match unit {
+        "second" => Duration::seconds(count),
+        "minute" => Duration::minutes(count),
+        "hour" => Duration::hours(count),
+        "day" => Duration::days(count),
+        "week" => Duration::weeks(count),
+        "month" => Duration::days(count * 30),
+        "year" => Duration::days(count * 365),
+        _ => return None,
+    };
+    Some(now - duration)
+}
+
+/// Follows annotated tag objects until a non-tag object is reached.
+pub fn peel_to_commit(object_store: &ObjectStore, hash: &str) -> Result<String> {
+    let mut hash = hash.to_string();
+    while object_store.object_kind(&hash)? == ObjectKind::Tag {
+        hash = object_store.load_tag(&hash)?.target;
+    }
+    Ok(hash)
+}
+
+/// Checks `name` against git's `check-ref-format` rules, so that no ref
+/// can point outside the refs of the repository: it is either a one-level
+/// name in capitals such as `HEAD` or `MERGE_HEAD`, or a `refs/...` name whose
+/// components are not empty, do not start with `.` or end with `.lock`, and
+/// which has no `..`, `@{`, control characters, spaces or any of `~^:?*[\`.
+pub fn check_ref_format(name: &str) -> Result<()> {
+    let one_level = !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_');
+    let valid = one_level
+        || (name.starts_with("refs/")
+            && !name.ends_with('.')
+            && !name.contains("..")
+            && !name.contains("@{")
+            && name != "@"
+            && !name.bytes().any(|b| b < 0x20 || b == 0x7f || b" ~^:?*[\\".contains(&b))
+            && name.split('/').all(|component| {
+                !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+            }));
+    if valid {
+        Ok(())
+    } else {
+        Err(CrabGitError::InvalidOperation(format!("'{}' is not a valid ref name", name)))
+    }
+}
 
-    let branch_path = refs_heads.join(branch);
-    fs::write(branch_path, commit)?;
+/// Whether a work-tree relative `path` is `prefix` itself or lies below it.
+/// An empty prefix (the work tree root) matches every path.
+pub fn is_path_or_child(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path == prefix
+        || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Removes the now empty directories above a deleted file, up to the work tree.
+pub fn remove_empty_parents(work_dir: &Path, file_path: &Path) -> Result<()> {
+    let mut dir = file_path.parent();
+    while let Some(path) = dir {
+        if path == work_dir || !path.is_dir() || fs::read_dir(path)?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(path)?;
+        dir = path.parent();
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn same_tree_entry(a: Option<&TreeEntry>, b: Option<&TreeEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.hash == b.hash && a.mode == b.mode,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// `path` relative to the work tree, with `/` separators (`""` for the root).
+/// `.` and `..` components are resolved first, and a path that ends up
+/// outside the work tree is refused.
+pub fn relative_path(repo: &Repository, path: &Path) -> Result<String> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    match normalized.strip_prefix(&repo.work_dir) {
+        Ok(relative) => Ok(relative.to_string_lossy().replace("\\", "/")),
+        Err(_) => Err(CrabGitError::InvalidOperation(format!(
+            "'{}' is outside the repository at '{}'",
+            path.display(),
+            repo.work_dir.display()
+        ))),
+    }
+}
+
+/// A path given on the command line, which is relative to the current
+/// directory, as a work-tree relative path.
+pub fn pathspec(repo: &Repository, path: &str) -> Result<String> {
+    relative_path(repo, &std::env::current_dir()?.join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ref_name_rules() {
+        for name in ["HEAD", "MERGE_HEAD", "refs/heads/main", "refs/tags/release/v1.0", "refs/heads/a-b_c"] {
+            assert!(check_ref_format(name).is_ok(), "{} was rejected", name);
+        }
+        for name in [
+            "", "head", "main", "refs/heads/../HEAD", "refs/heads/a..b", "refs/heads/x.lock",
+            "refs/heads/.x", "refs/heads//x", "refs/heads/x/", "refs/heads/x.", "refs/heads/a b",
+            "refs/heads/a:b", "refs/heads/a@{1}", "refs/heads/a\\b", "refs/heads/a\tb",
+        ] {
+            assert!(check_ref_format(name).is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn unmarked_git_directories_are_skipped() {
+        let test = crate::testing::TestRepo::new();
+        fs::create_dir_all(test.path("checkout/.git")).unwrap();
+        fs::write(test.path("checkout/.git/config"), "[core]\n\tbare = false\n").unwrap();
+        fs::create_dir_all(test.path("checkout/src")).unwrap();
+
+        assert_eq!(find_git_dir(&test.path("checkout/src")), Some(test.repo.git_dir.clone()));
+        let repo = get_repository(Some(test.path("checkout"))).unwrap();
+        assert_eq!(repo.work_dir, test.root);
+
+        fs::write(test.path("checkout/.git/config"), "[crabgit]\n\tgitcompatible = true\n").unwrap();
+        assert_eq!(find_git_dir(&test.path("checkout/src")), Some(test.path("checkout/.git")));
+    }
+
+    #[test]
+    fn git_compatible_repositories_are_found() {
+        let test = crate::testing::TestRepo::git();
+        assert!(test.repo.git_compatible);
+        assert_eq!(test.repo.hash_algorithm, HashAlgorithm::Sha1);
+        fs::create_dir_all(test.path("sub")).unwrap();
+        assert_eq!(find_git_dir(&test.path("sub")), Some(test.path(".git")));
+    }
+
+    #[test]
+    fn legacy_indexes_are_layered_on_head() {
+        let test = crate::testing::TestRepo::new();
+        test.write("a.txt", "a\n");
+        test.write("b.txt", "b\n");
+        test.commit("initial");
+        let head = head_index(&test.repo).unwrap();
+
+        // A version 1 index only lists what was staged since the last commit.
+        let mut staged = Index::default();
+        staged.entries.insert("c.txt".to_string(), head.entries["a.txt"].clone());
+        fs::write(test.repo.git_dir.join("index"), serde_json::to_string(&staged).unwrap()).unwrap();
+
+        let index = load_index(&test.repo).unwrap();
+        let mut paths: Vec<&String> = index.entries.keys().collect();
+        paths.sort();
+        assert_eq!(paths, ["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(load_legacy_index(&test.repo).unwrap().entries.len(), 1);
+
+        save_index(&test.repo, &index).unwrap();
+        assert_eq!(load_index(&test.repo).unwrap().entries.len(), 3);
+        fs::remove_file(test.repo.git_dir.join("index")).unwrap();
+        assert_eq!(load_index(&test.repo).unwrap().entries.len(), 2);
+    }
+
+    #[test]
+    fn times() {
+        let now = DateTime::parse_from_rfc3339("2024-05-10T12:00:00Z").unwrap().with_timezone(&Utc);
+        let at = |value: &str| parse_time(value, now).map(|time| time.to_rfc3339());
+
+        assert_eq!(at("now"), Some(now.to_rfc3339()));
+        assert_eq!(at("yesterday").unwrap(), "2024-05-09T12:00:00+00:00");
+        assert_eq!(at("2.weeks.ago").unwrap(), "2024-04-26T12:00:00+00:00");
+        assert_eq!(at("3 days ago").unwrap(), "2024-05-07T12:00:00+00:00");
+        assert_eq!(at("2024-01-02").unwrap(), "2024-01-02T00:00:00+00:00");
+        assert_eq!(at("2024-01-02 03:04").unwrap(), "2024-01-02T03:04:00+00:00");
+        assert_eq!(at("@0").unwrap(), "1970-01-01T00:00:00+00:00");
+        assert_eq!(parse_time("never", now), Some(DateTime::<Utc>::MIN_UTC));
+        assert_eq!(at("soon"), None);
+    }
+}